@@ -3,6 +3,7 @@ use log::{debug, error, info};
 use prism::api::Server as ApiServer;
 use prism::blockchain::BlockChain;
 use prism::blockdb::BlockDatabase;
+use prism::chainspec::ChainSpec;
 use prism::config::BlockchainConfig;
 use prism::crypto::hash::{H256, Address};
 use prism::wallet::Wallet;
@@ -20,15 +21,15 @@ use rand::seq::SliceRandom;
 
 fn main() {
     stderrlog::new().verbosity(2).init().unwrap();
-    run_local(String::from("payment"),10000,100000,200);
-    run_local(String::from("donothing"),10000,100000,200);
-    run_local(String::from("simpleerc20"),10000,100000,200);
-    run_local(String::from("cryptokitties"),10000,100000,200);
-    run_local(String::from("cpuheavy"),10000,100000,200);
-    run_local(String::from("ioheavy"),10000,100000,200);
+    run_local(String::from("payment"),10000,100000,200,8);
+    run_local(String::from("donothing"),10000,100000,200,8);
+    run_local(String::from("simpleerc20"),10000,100000,200,8);
+    run_local(String::from("cryptokitties"),10000,100000,200,8);
+    run_local(String::from("cpuheavy"),10000,100000,200,8);
+    run_local(String::from("ioheavy"),10000,100000,200,8);
 }
 
-fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_block: usize) {
+fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_block: usize, workers: usize) {
     macro_rules! include_int_vec {
         ($file: expr) => {{
             let my_str = include_str!($file);
@@ -39,8 +40,13 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
 
     let mut rng = rand::thread_rng();
 
-    let statedb = StateDatabase::new("/tmp/prism/node_0-utxodb.rocksdb").unwrap();
+    let chain_spec = ChainSpec::prism_test();
+    let contract = |name: &str| chain_spec.contract(name).unwrap();
+    let statedb = StateDatabase::new("/tmp/prism/node_0-utxodb.rocksdb", &chain_spec).unwrap();
     let statedb = Arc::new(statedb);
+    let parallel_statedb =
+        StateDatabase::new("/tmp/prism/node_0-utxodb-parallel.rocksdb", &chain_spec).unwrap();
+    let parallel_statedb = Arc::new(parallel_statedb);
 
     let wallet = Wallet::new("/tmp/prism/node_0-wallet.rocksdb").unwrap();
     let wallet = Arc::new(wallet);
@@ -59,6 +65,7 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
     let to_dist = WeightedIndex::new(&probs).unwrap();
 
     prism::experiment::ico(&addrs, &statedb, &wallet, 0xffffffffffffffff).unwrap();
+    prism::experiment::ico(&addrs, &parallel_statedb, &wallet, 0xffffffffffffffff).unwrap();
 
     let mut has_erc20_ico: HashSet<Address> = HashSet::new();
 
@@ -69,9 +76,9 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
         let to_addr = addrs.get(to_dist.sample(&mut rng)).unwrap();
         let transaction = match transaction_type.trim() {
             "payment" => wallet.create_transaction_payment(addr, to_addr, 1.into()),
-            "donothing" => wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf01), hex::decode("448f30a3").unwrap()),
-            "cpuheavy" => wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf02), hex::decode("fe91386500000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
-            "ioheavy" => wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf03), hex::decode("b6a3f0e900000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
+            "donothing" => wallet.create_transaction_call(addr, &contract("donothing"), hex::decode("448f30a3").unwrap()),
+            "cpuheavy" => wallet.create_transaction_call(addr, &contract("cpuheavy"), hex::decode("fe91386500000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
+            "ioheavy" => wallet.create_transaction_call(addr, &contract("ioheavy"), hex::decode("b6a3f0e900000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
             "cryptokitties" => {
                 let mut data = hex::decode("0d9f5aed").unwrap();
                 // generate 2 random u256 parameter (= 4 u128)
@@ -81,7 +88,7 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
                 }
                 // last parameter, target block number is 0
                 data.append(&mut hex::decode("0000000000000000000000000000000000000000000000000000000000000000").unwrap());
-                wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe01), data)
+                wallet.create_transaction_call(addr, &contract("cryptokitties"), data)
             }
             "simpleerc20" => {
                 if has_erc20_ico.contains(addr) {
@@ -89,11 +96,11 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
                     let mut data = hex::decode("a9059cbb000000000000000000000000").unwrap();
                     data.extend_from_slice(to_addr.as_bytes());
                     data.append(&mut hex::decode("0000000000000000000000000000000000000000000000000000000000000001").unwrap());
-                    wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe02), data)
+                    wallet.create_transaction_call(addr, &contract("simpleerc20"), data)
                 } else {
                     has_erc20_ico.insert(addr.clone());
                     // mint tokens, number is very large: 0xffffffffffffffff
-                    wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe02), hex::decode("a0712d68000000000000000000000000000000000000000000000000ffffffffffffffff").unwrap())
+                    wallet.create_transaction_call(addr, &contract("simpleerc20"), hex::decode("a0712d68000000000000000000000000000000000000000000000000ffffffffffffffff").unwrap())
                 }
             }
             _ => panic!("Transaction type does not match any known one."),
@@ -114,4 +121,20 @@ fn run_local(transaction_type: String, key_num: usize, tx_num: usize, tx_per_blo
     println!("Total {} txs", tx_num);
     println!("{} txs per block (block means commit)", tx_per_block);
     println!("Throughput: {} tps", tx_num as f64/time_1 *1000_000.0);
+
+    // the same blocks with optimistic parallel execution, in the environment apply() uses
+    let info = StateDatabase::default_env_info();
+    let start = Instant::now();
+    for block in txs.chunks(tx_per_block) {
+        parallel_statedb.apply_parallel(block, &info, workers).unwrap();
+        parallel_statedb.commit().unwrap();
+    }
+    let end = Instant::now();
+    let time_2 = end.duration_since(start).as_micros() as f64;
+    // a partial last block is not committed in the serial run
+    if tx_num % tx_per_block != 0 {
+        statedb.commit().unwrap();
+    }
+    assert_eq!(statedb.root(), parallel_statedb.root(), "Parallel execution changed the state root");
+    println!("Parallel throughput with {} workers: {} tps", workers, tx_num as f64/time_2 *1000_000.0);
 }
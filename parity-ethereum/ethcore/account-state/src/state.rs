@@ -758,6 +758,23 @@ impl<B: Backend> State<B> {
 		self.cache.borrow_mut().clear();
 	}
 
+	/// Addresses of the accounts in the local cache, with whether each was modified. Without a
+	/// parent block the global cache is not used, so these are all the accounts accessed since
+	/// the cache was last cleared (added for prism).
+	pub fn cached_accounts(&self) -> Vec<(Address, bool)> {
+		self.cache.borrow().iter().map(|(address, entry)| (*address, entry.is_dirty())).collect()
+	}
+
+	/// Copy the modifications of account `a` from `other` into the local cache. `other` must have
+	/// started from the same root, and our copy of the account must not have been modified since
+	/// (added for prism).
+	pub fn copy_account_from<X: Backend>(&mut self, other: &State<X>, a: &Address) {
+		let entry = other.cache.borrow().get(a).and_then(AccountEntry::clone_if_dirty);
+		if let Some(entry) = entry {
+			self.insert_cache(a, entry);
+		}
+	}
+
 	/// Remove any touched empty or dust accounts.
 	pub fn kill_garbage(&mut self, touched: &HashSet<Address>, remove_empty_touched: bool, min_balance: &Option<U256>, kill_contracts: bool) -> TrieResult<()> {
 		let to_kill: HashSet<_> = {
@@ -992,8 +1009,12 @@ impl<B: Backend> State<B> {
 		match result {
 			Some(r) => Ok(r?),
 			None => {
-				// first check if it is not in database for sure
-				if check_null && self.db.is_known_null(a) { return Ok(f(None)); }
+				// first check if it is not in database for sure, and remember the account was
+				// looked up so that speculative execution sees the read (added for prism)
+				if check_null && self.db.is_known_null(a) {
+					self.insert_cache(a, AccountEntry::new_clean(None));
+					return Ok(f(None));
+				}
 
 				// not found in the global cache, get from the DB and insert into local
 				let db = &self.db.as_hash_db();
@@ -1,16 +1,24 @@
 use crate::blockchain::BlockChain;
+use crate::blockdb::BlockDatabase;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::experiment::transaction_generator;
 use crate::miner::memory_pool::MemoryPool;
+use crate::miner::strategy::Strategy;
 use crate::miner::Handle as MinerHandle;
 use crate::network::server::Handle as ServerHandle;
-use crate::statedb::StateDatabase;
+use crate::ledger_manager;
+use crate::logdb::{LogDatabase, LogFilter};
+use crate::statedb::{StateDatabase, TraceOptions};
 use crate::wallet::Wallet;
 use crate::handler::new_transaction;
-use crate::crypto::hash::{Address, H256};
+use crate::crypto::hash::{Address, EthereumH256, H256};
+use crate::transaction::{Action, RawTransaction};
+use common_types::receipt::TransactionOutcome;
+use ethereum_types::U256;
 
 use log::info;
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use tiny_http::Header;
@@ -23,8 +31,10 @@ pub struct Server {
     handle: HTTPServer,
     miner: MinerHandle,
     statedb: Arc<StateDatabase>,
+    logdb: Arc<LogDatabase>,
     wallet: Arc<Wallet>,
     blockchain: Arc<BlockChain>,
+    blockdb: Arc<BlockDatabase>,
     mempool: Arc<Mutex<MemoryPool>>,
     /// the network server handle
     server: ServerHandle,
@@ -51,6 +61,58 @@ struct BlockchainSnapshotResponse {
     leaders: Vec<String>,
 }
 
+#[derive(Serialize)]
+struct FastConfirmedResponse {
+    fast_confirmed: bool,
+}
+
+#[derive(Serialize)]
+struct ProposerConfidenceResponse {
+    hash: String,
+    level: u64,
+    vote_depths: Vec<u64>,
+    votes_lcb: f32,
+    remaining_votes: f32,
+    ledger_level: Option<u64>,
+    epsilon: f64,
+    confirmed: bool,
+}
+
+#[derive(Serialize)]
+struct TransactionConfidenceResponse {
+    transaction_block: String,
+    proposers: Vec<ProposerConfidenceResponse>,
+    epsilon: f64,
+    confirmed: bool,
+}
+
+#[derive(Serialize)]
+struct StateAccountResponse {
+    root: EthereumH256,
+    balance: U256,
+    nonce: U256,
+    code: String,
+}
+
+#[derive(Serialize)]
+struct StateStorageResponse {
+    root: EthereumH256,
+    value: EthereumH256,
+}
+
+#[derive(Serialize)]
+struct StateCallResponse {
+    root: EthereumH256,
+    success: bool,
+    gas_used: U256,
+    output: String,
+}
+
+#[derive(Serialize)]
+struct SubmitWorkResponse {
+    block_hash: String,
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -73,12 +135,167 @@ macro_rules! respond_json {
     }};
 }
 
+/// Parse the optional `gas_price` (in Wei) and `gas` parameters of a transaction, defaulting to
+/// the ones of the wallet.
+fn parse_gas(params: &HashMap<String, String>, wallet: &Wallet) -> Result<(U256, U256), String> {
+    let (mut gas_price, mut gas) = wallet.gas();
+    if let Some(v) = params.get("gas_price") {
+        gas_price = U256::from_dec_str(v).map_err(|e| format!("error parsing gas_price: {:?}", e))?;
+    }
+    if let Some(v) = params.get("gas") {
+        gas = U256::from_dec_str(v).map_err(|e| format!("error parsing gas: {:?}", e))?;
+    }
+    Ok((gas_price, gas))
+}
+
+/// Parse a hex-encoded 32-byte hash.
+fn parse_hash(s: &str) -> Option<H256> {
+    let raw: [u8; 32] = hex::decode(s).ok()?.as_slice().try_into().ok()?;
+    Some(raw.into())
+}
+
+/// Parse a hex-encoded address.
+fn parse_address(s: &str) -> Option<Address> {
+    let raw: [u8; 20] = hex::decode(s).ok()?.as_slice().try_into().ok()?;
+    Some(raw.into())
+}
+
+/// Parse the state root a query is about: the root after the ledger level given by `level`, the
+/// root given by `root`, or the latest root.
+fn parse_state_root(
+    params: &HashMap<String, String>,
+    statedb: &StateDatabase,
+) -> Result<EthereumH256, String> {
+    if let Some(v) = params.get("level") {
+        let level = v
+            .parse::<u64>()
+            .map_err(|e| format!("error parsing level: {}", e))?;
+        return match statedb.level_root(level) {
+            Ok(Some(root)) => Ok(root),
+            Ok(None) => Err(format!("ledger level {} not executed", level)),
+            Err(e) => Err(format!("error reading state root: {}", e)),
+        };
+    }
+    match params.get("root") {
+        Some(s) => parse_hash(s)
+            .map(Into::into)
+            .ok_or_else(|| "invalid state root".to_string()),
+        None => Ok(statedb.root()),
+    }
+}
+
+/// Parse a call: the `from` address, the wallet's by default, the `to` address, a contract
+/// creation if missing, and the optional hex-encoded `data`, `value` (in Wei), `gas_price` and
+/// `gas`. The nonce is left to the caller.
+fn parse_call(
+    params: &HashMap<String, String>,
+    wallet: &Wallet,
+) -> Result<(Address, RawTransaction), String> {
+    let from = match params.get("from") {
+        Some(s) => parse_address(s),
+        None => wallet.addresses().unwrap().into_iter().next(),
+    };
+    let from = from.ok_or_else(|| "missing or invalid sender address".to_string())?;
+    let action = match params.get("to") {
+        Some(s) => Action::Call(parse_address(s).ok_or_else(|| "invalid receiver address".to_string())?),
+        None => Action::Create,
+    };
+    let data = match params.get("data") {
+        Some(s) => hex::decode(s).map_err(|e| format!("error parsing data: {}", e))?,
+        None => vec![],
+    };
+    let value = match params.get("value") {
+        Some(v) => U256::from_dec_str(v).map_err(|e| format!("error parsing value: {:?}", e))?,
+        None => U256::zero(),
+    };
+    let (gas_price, gas) = parse_gas(params, wallet)?;
+    let t = RawTransaction {
+        nonce: U256::zero(),
+        gas_price,
+        gas,
+        action,
+        value,
+        data,
+    };
+    Ok((from, t))
+}
+
+/// Parse the `type` parameter of a trace request, a comma-separated list of `trace`, `vmTrace`
+/// and `stateDiff`. All of them are returned if it is missing.
+fn parse_trace_options(params: &HashMap<String, String>) -> Result<TraceOptions, String> {
+    let types = match params.get("type") {
+        Some(v) => v,
+        None => {
+            return Ok(TraceOptions {
+                trace: true,
+                vm_trace: true,
+                state_diff: true,
+            })
+        }
+    };
+    let mut options = TraceOptions::default();
+    for t in types.split(',') {
+        match t {
+            "trace" => options.trace = true,
+            "vmTrace" => options.vm_trace = true,
+            "stateDiff" => options.state_diff = true,
+            t => return Err(format!("unknown trace type: {}", t)),
+        }
+    }
+    Ok(options)
+}
+
+/// Parse a log query: the ledger levels `from_level`, 0 by default, to `to_level`, the last one
+/// executed by default, the comma-separated `address` list of contracts, and the `topics` the logs
+/// must have, comma-separated by position, each a `|`-separated list of alternatives or empty for
+/// any. At most the last `limit` logs are returned.
+fn parse_log_filter(params: &HashMap<String, String>) -> Result<LogFilter, String> {
+    let mut filter = LogFilter::default();
+    if let Some(v) = params.get("from_level") {
+        filter.from_level = v
+            .parse::<u64>()
+            .map_err(|e| format!("error parsing from_level: {}", e))?;
+    }
+    if let Some(v) = params.get("to_level") {
+        filter.to_level = Some(
+            v.parse::<u64>()
+                .map_err(|e| format!("error parsing to_level: {}", e))?,
+        );
+    }
+    if let Some(v) = params.get("address") {
+        let addresses: Option<Vec<Address>> = v.split(',').map(parse_address).collect();
+        filter.addresses = Some(addresses.ok_or_else(|| "invalid address".to_string())?);
+    }
+    if let Some(v) = params.get("topics") {
+        for position in v.split(',') {
+            if position.is_empty() {
+                filter.topics.push(None);
+                continue;
+            }
+            let topics: Option<Vec<EthereumH256>> = position
+                .split('|')
+                .map(|s| parse_hash(s).map(Into::into))
+                .collect();
+            filter.topics.push(Some(topics.ok_or_else(|| "invalid topic".to_string())?));
+        }
+    }
+    if let Some(v) = params.get("limit") {
+        filter.limit = Some(
+            v.parse::<usize>()
+                .map_err(|e| format!("error parsing limit: {}", e))?,
+        );
+    }
+    Ok(filter)
+}
+
 impl Server {
     pub fn start(
         addr: std::net::SocketAddr,
         statedb: &Arc<StateDatabase>,
+        logdb: &Arc<LogDatabase>,
         wallet: &Arc<Wallet>,
         blockchain: &Arc<BlockChain>,
+        blockdb: &Arc<BlockDatabase>,
         server: &ServerHandle,
         miner: &MinerHandle,
         mempool: &Arc<Mutex<MemoryPool>>,
@@ -90,8 +307,10 @@ impl Server {
             transaction_generator_handle: txgen_control_chan,
             miner: miner.clone(),
             statedb: Arc::clone(statedb),
+            logdb: Arc::clone(logdb),
             wallet: Arc::clone(wallet),
             blockchain: Arc::clone(blockchain),
+            blockdb: Arc::clone(blockdb),
             mempool: Arc::clone(mempool),
             server: server.clone(),
         };
@@ -100,8 +319,10 @@ impl Server {
                 let transaction_generator_handle = server.transaction_generator_handle.clone();
                 let miner = server.miner.clone();
                 let statedb = Arc::clone(&server.statedb);
+                let logdb = Arc::clone(&server.logdb);
                 let wallet = Arc::clone(&server.wallet);
                 let blockchain = Arc::clone(&server.blockchain);
+                let blockdb = Arc::clone(&server.blockdb);
                 let mempool = Arc::clone(&server.mempool);
                 let network_server = server.server.clone();
                 thread::spawn(move || {
@@ -124,6 +345,66 @@ impl Server {
                             };
                             respond_json!(req, resp);
                         }
+                        "/blockchain/fast-confirmed" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash").and_then(|s| parse_hash(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid block hash");
+                                    return;
+                                }
+                            };
+                            let resp = FastConfirmedResponse {
+                                fast_confirmed: blockchain.is_fast_confirmed(&hash).unwrap(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/blockchain/confidence" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash").and_then(|s| parse_hash(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid hash");
+                                    return;
+                                }
+                            };
+                            let confidence = match blockchain.transaction_confidence(&hash) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error computing confidence: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let resp: Vec<TransactionConfidenceResponse> = confidence
+                                .into_iter()
+                                .map(|c| TransactionConfidenceResponse {
+                                    transaction_block: c.hash.to_string(),
+                                    proposers: c
+                                        .proposers
+                                        .into_iter()
+                                        .map(|p| ProposerConfidenceResponse {
+                                            hash: p.hash.to_string(),
+                                            level: p.level,
+                                            vote_depths: p.vote_depths,
+                                            votes_lcb: p.votes_lcb,
+                                            remaining_votes: p.remaining_votes,
+                                            ledger_level: p.ledger_level,
+                                            epsilon: p.epsilon,
+                                            confirmed: p.confirmed,
+                                        })
+                                        .collect(),
+                                    epsilon: c.epsilon,
+                                    confirmed: c.confirmed,
+                                })
+                                .collect();
+                            respond_json!(req, resp);
+                        }
                         "/utxo/snapshot" => {
                             let checksum = statedb.root();
                             let resp = UtxoSnapshotResponse {
@@ -197,6 +478,13 @@ impl Server {
                                 .get("data")
                                 .and_then(|s| hex::decode(s).ok());
                             let data: Vec<u8> = data.unwrap_or(vec![]);
+                            let (gas_price, gas) = match parse_gas(&params, &wallet) {
+                                Ok(gas) => gas,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
                             match receiver_addr {
                                 Some(receiver_addr) => {
                                     let addrs = wallet.addresses().unwrap();
@@ -207,7 +495,7 @@ impl Server {
                                             return;
                                         }
                                     };
-                                    match wallet.create_transaction_call(addr, &receiver_addr, data) {
+                                    match wallet.create_transaction(addr, Action::Call(receiver_addr), 0.into(), data, gas_price, gas) {
                                         Ok(t) => {
                                             new_transaction(t, &mempool, &network_server);
                                             respond_result!(req, true, "ok");
@@ -225,9 +513,24 @@ impl Server {
                             let data: Option<Vec<u8>> = params
                                 .get("data")
                                 .and_then(|s| hex::decode(s).ok());
+                            let (gas_price, gas) = match parse_gas(&params, &wallet) {
+                                Ok(gas) => gas,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
                             match data {
                                 Some(data) => {
-                                    match wallet.create_transaction_create(data) {
+                                    let addrs = wallet.addresses().unwrap();
+                                    let addr = match addrs.iter().next() {
+                                        Some(a) => a,
+                                        _ => {
+                                            respond_result!(req, false, "error of creating transaction: failed to get a key pair from wallet");
+                                            return;
+                                        }
+                                    };
+                                    match wallet.create_transaction(addr, Action::Create, 0.into(), data, gas_price, gas) {
                                         Ok(t) => {
                                             new_transaction(t, &mempool, &network_server);
                                             respond_result!(req, true, "ok");
@@ -239,6 +542,245 @@ impl Server {
                                 None => respond_result!(req, false, "error of creating transaction, you need data (the init code of a contract)"),
                             };
                         }
+                        "/state/account" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match params.get("address").and_then(|s| parse_address(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid address");
+                                    return;
+                                }
+                            };
+                            let view = match parse_state_root(&params, &statedb)
+                                .and_then(|root| statedb.view_at(&root).map_err(|e| e.to_string()))
+                            {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let resp = StateAccountResponse {
+                                root: view.root(),
+                                balance: view.balance(&address).unwrap(),
+                                nonce: view.nonce(&address).unwrap(),
+                                code: format!(
+                                    "0x{}",
+                                    hex::encode(&*view.code(&address).unwrap().unwrap_or_default())
+                                ),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/state/storage" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match params.get("address").and_then(|s| parse_address(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid address");
+                                    return;
+                                }
+                            };
+                            let key: EthereumH256 = match params.get("key").and_then(|s| parse_hash(s)) {
+                                Some(v) => v.into(),
+                                None => {
+                                    respond_result!(req, false, "missing or invalid storage key");
+                                    return;
+                                }
+                            };
+                            let view = match parse_state_root(&params, &statedb)
+                                .and_then(|root| statedb.view_at(&root).map_err(|e| e.to_string()))
+                            {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let resp = StateStorageResponse {
+                                root: view.root(),
+                                value: view.storage_at(&address, &key).unwrap(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/state/call" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let (from, mut t) = match parse_call(&params, &wallet) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let view = match parse_state_root(&params, &statedb)
+                                .and_then(|root| statedb.view_at(&root).map_err(|e| e.to_string()))
+                            {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            // a call at a ledger level runs in the environment of the level
+                            let info = match params.get("level").and_then(|v| v.parse().ok()) {
+                                Some(level) => {
+                                    match ledger_manager::level_env_info(&blockdb, &blockchain, level) {
+                                        Some(info) => info,
+                                        None => {
+                                            respond_result!(
+                                                req,
+                                                false,
+                                                format!("ledger level {} not in the ledger", level)
+                                            );
+                                            return;
+                                        }
+                                    }
+                                }
+                                None => StateDatabase::default_env_info(),
+                            };
+                            t.nonce = view.nonce(&from).unwrap();
+                            let t = t.fake_sign(from);
+                            match view.call(&t, &info) {
+                                Ok(outcome) => {
+                                    let resp = StateCallResponse {
+                                        root: view.root(),
+                                        success: outcome.receipt.outcome
+                                            == TransactionOutcome::StatusCode(1),
+                                        gas_used: outcome.receipt.gas_used,
+                                        output: format!("0x{}", hex::encode(&outcome.output)),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error executing call: {}", e)
+                                ),
+                            }
+                        }
+                        "/state/proof" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match params.get("address").and_then(|s| parse_address(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid address");
+                                    return;
+                                }
+                            };
+                            let keys: Option<Vec<H256>> = match params.get("keys") {
+                                Some(s) => s.split(',').map(parse_hash).collect(),
+                                None => Some(vec![]),
+                            };
+                            let keys = match keys {
+                                Some(v) => v.into_iter().map(Into::into).collect::<Vec<_>>(),
+                                None => {
+                                    respond_result!(req, false, "invalid storage key");
+                                    return;
+                                }
+                            };
+                            let root = match parse_state_root(&params, &statedb) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match statedb.prove(&root, &address, &keys) {
+                                Ok(proof) => respond_json!(req, proof),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error proving account: {}", e)
+                                ),
+                            }
+                        }
+                        "/trace/transaction" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash").and_then(|s| parse_hash(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid transaction hash");
+                                    return;
+                                }
+                            };
+                            let options = match parse_trace_options(&params) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match ledger_manager::trace_transaction(
+                                &blockdb,
+                                &blockchain,
+                                &statedb,
+                                &hash.into(),
+                                options,
+                            ) {
+                                Ok(Some(results)) => respond_json!(req, results),
+                                Ok(None) => respond_result!(
+                                    req,
+                                    false,
+                                    "transaction not executed, or its block was pruned"
+                                ),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error replaying transaction: {}", e)
+                                ),
+                            }
+                        }
+                        "/trace/call" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let (from, mut t) = match parse_call(&params, &wallet) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let options = match parse_trace_options(&params) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            t.nonce = statedb.nonce(&from).unwrap();
+                            let t = t.fake_sign(from);
+                            match statedb.trace_call(&t, &StateDatabase::default_env_info(), options) {
+                                Ok(results) => respond_json!(req, results),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error tracing call: {}", e)
+                                ),
+                            }
+                        }
+                        "/logs" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let filter = match parse_log_filter(&params) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match logdb.logs(&filter) {
+                                Ok(logs) => respond_json!(req, logs),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error reading logs: {}", e)
+                                ),
+                            }
+                        }
                         "/miner/start" => {
                             let params = url.query_pairs();
                             let params: HashMap<_, _> = params.into_owned().collect();
@@ -295,13 +837,68 @@ impl Server {
                                     return;
                                 }
                             };
-                            miner.start(lambda, lazy, prob);
+                            let strategy = match Strategy::from_params(&params) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            miner.start(lambda, lazy, prob, strategy);
                             respond_result!(req, true, "ok");
                         }
                         "/miner/step" => {
                             miner.step();
                             respond_result!(req, true, "ok");
                         }
+                        "/miner/get-work" => {
+                            match miner.get_work() {
+                                Some(package) => respond_json!(req, package),
+                                None => respond_result!(req, false, "miner has shut down"),
+                            }
+                        }
+                        "/miner/submit-work" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let work_id = match params.get("work_id").and_then(|s| parse_hash(s)) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing or invalid work id");
+                                    return;
+                                }
+                            };
+                            let nonce = match params.get("nonce") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing nonce");
+                                    return;
+                                }
+                            };
+                            let nonce = match nonce.parse::<u32>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing nonce: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            match miner.submit_work(work_id, nonce) {
+                                Ok(hash) => {
+                                    let resp = SubmitWorkResponse {
+                                        block_hash: hash.to_string(),
+                                    };
+                                    respond_json!(req, resp);
+                                }
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error submitting work: {}", e)
+                                ),
+                            }
+                        }
                         "/telematics/snapshot" => {
                             respond_json!(req, PERFORMANCE_COUNTER.snapshot());
                         }
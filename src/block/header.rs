@@ -1,8 +1,6 @@
-use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::hash::{Address, Hashable, H256};
 use keccak_hash::keccak;
 
-// TODO: Add the address of the miner
-
 /// The header of a block.
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Copy)]
 pub struct Header {
@@ -18,6 +16,8 @@ pub struct Header {
     pub extra_content: [u8; 32],
     /// Mining difficulty of this block.
     pub difficulty: H256,
+    /// Address of the miner, which receives the block reward and the transaction fees.
+    pub miner: Address,
 }
 
 impl Header {
@@ -29,6 +29,7 @@ impl Header {
         content_merkle_root: H256,
         extra_content: [u8; 32],
         difficulty: H256,
+        miner: Address,
     ) -> Self {
         Self {
             parent,
@@ -37,6 +38,7 @@ impl Header {
             content_merkle_root,
             extra_content,
             difficulty,
+            miner,
         }
     }
 }
@@ -52,7 +54,7 @@ impl Hashable for Header {
 pub mod tests {
     use super::super::header::Header;
 
-    use crate::crypto::hash::{Hashable, H256};
+    use crate::crypto::hash::{Address, Hashable, H256};
 
     ///The hash should match
     #[test]
@@ -94,6 +96,7 @@ pub mod tests {
             0, 20, 10,
         ];
         let difficulty = (&difficulty).into();
+        let miner: Address = hex!("1a2a3a4a1a2a3a4a1a2a3a4a1a2a3a4a1a2a3a4a").into();
         let header = Header::new(
             parent_hash,
             timestamp,
@@ -101,13 +104,14 @@ pub mod tests {
             content_root,
             extra_content,
             difficulty,
+            miner,
         );
         header
     }
 
     pub fn sample_header_hash_should_be() -> H256 {
         let header_hash_should_be =
-            (&hex!("a34291a7290e7036c18903b867b39ff0609301673e153f1b9e199663fe1622c5")).into(); // Calculated on Jan 23, 2020
+            (&hex!("d6c61c8f5ee9ea89702c008de4cd06bcb41c70e4a383cff29bf59a94c6ef7e98")).into(); // Calculated on Oct 18, 2026
         header_hash_should_be
     }
 }
@@ -2,7 +2,7 @@ pub mod header;
 pub mod proposer;
 pub mod transaction;
 pub mod voter;
-use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::hash::{Address, Hashable, H256};
 use crate::experiment::performance_counter::PayloadSize;
 
 /// A block in the Prism blockchain.
@@ -29,6 +29,7 @@ impl Block {
         content: Content,
         extra_content: [u8; 32],
         difficulty: H256,
+        miner: Address,
     ) -> Self {
         let header = header::Header::new(
             parent,
@@ -37,6 +38,7 @@ impl Block {
             content_merkle_root,
             extra_content,
             difficulty,
+            miner,
         );
         Self {
             header,
@@ -113,6 +115,7 @@ pub mod tests {
 
     use super::*;
     use crate::config;
+    use crate::crypto::merkle::MerkleTree;
     use crate::transaction::Transaction;
     use rand::Rng;
 
@@ -144,6 +147,7 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::zero(),
         )
     }
 
@@ -169,6 +173,7 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::zero(),
         )
     }
 
@@ -188,9 +193,44 @@ pub mod tests {
             content,
             [0u8; 32],
             *config::DEFAULT_DIFFICULTY,
+            Address::zero(),
         )
     }
 
+    /// Mine a block with the given content, so that it passes the sortition checks. The mining
+    /// rates of the config should not make the content type too rare.
+    pub fn mined_block(
+        config: &config::BlockchainConfig,
+        parent: H256,
+        timestamp: u128,
+        content: Content,
+    ) -> Block {
+        let index = match &content {
+            Content::Proposer(_) => config::PROPOSER_INDEX,
+            Content::Transaction(_) => config::TRANSACTION_INDEX,
+            Content::Voter(c) => c.chain_number + config::FIRST_VOTER_INDEX,
+        };
+        // only the content at the sortition index matters, so fill every slot with it
+        let contents = vec![content.clone(); (config.voter_chains + config::FIRST_VOTER_INDEX) as usize];
+        let tree = MerkleTree::new(&contents);
+        let mut nonce: u32 = 0;
+        loop {
+            let header = header::Header::new(
+                parent,
+                timestamp,
+                nonce,
+                tree.root(),
+                [0u8; 32],
+                *config::DEFAULT_DIFFICULTY,
+                Address::zero(),
+            );
+            if config.sortition_hash(&header.hash(), &header.difficulty) == Some(index) {
+                return Block::from_header(header, content, tree.proof(index as usize));
+            }
+            nonce += 1;
+        }
+    }
+
     #[test]
     fn ser() {
         let voter = voter_block(Default::default(), Default::default(), Default::default(), Default::default(), vec![Default::default(), Default::default()]);
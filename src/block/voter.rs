@@ -1,7 +1,7 @@
 use super::Block;
 use super::Content as BlockContent;
 use crate::config::*;
-use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::hash::{Address, Hashable, H256};
 use crate::crypto::merkle::MerkleTree;
 use crate::experiment::performance_counter::PayloadSize;
 use keccak_hash::keccak;
@@ -67,6 +67,7 @@ pub fn genesis(chain_num: u16) -> Block {
         BlockContent::Voter(content),
         all_zero,
         *DEFAULT_DIFFICULTY,
+        Address::zero(),
     )
 }
 
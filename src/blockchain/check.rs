@@ -0,0 +1,275 @@
+use super::*;
+use super::replay::ReplayReport;
+use crate::blockdb::BlockDatabase;
+use log::info;
+use rocksdb::IteratorMode;
+
+/// Column families compared by the checker. The leader sequence goes before the ledger order and
+/// the ledger levels, since whether a ledger entry is expected depends on the leader of its level.
+const CHECKED_CFS: &[&str] = &[
+    PROPOSER_NODE_LEVEL_CF,
+    VOTER_NODE_LEVEL_CF,
+    VOTER_NODE_CHAIN_CF,
+    VOTER_TREE_LEVEL_COUNT_CF,
+    PROPOSER_TREE_LEVEL_CF,
+    VOTER_NODE_VOTED_LEVEL_CF,
+    PROPOSER_NODE_VOTE_CF,
+    PROPOSER_VOTE_COUNT_CF,
+    PARENT_NEIGHBOR_CF,
+    VOTE_NEIGHBOR_CF,
+    VOTER_PARENT_NEIGHBOR_CF,
+    TRANSACTION_REF_NEIGHBOR_CF,
+    PROPOSER_REF_NEIGHBOR_CF,
+    PROPOSER_VOTER_NEIGHBOR_CF,
+    TRANSACTION_REFERRER_NEIGHBOR_CF,
+    TRANSACTION_BLOCK_CF,
+    PROPOSER_LEADER_SEQUENCE_CF,
+    PROPOSER_LEDGER_ORDER_CF,
+    PROPOSER_LEDGER_LEVEL_CF,
+];
+
+/// An entry of the blockchain database that does not match the one recomputed from the blocks.
+pub struct Discrepancy {
+    pub column_family: &'static str,
+    /// The serialized key.
+    pub key: Vec<u8>,
+    /// The recomputed value, or None if the entry should not exist.
+    pub expected: Option<Vec<u8>>,
+    /// The value in the database, or None if the entry is missing.
+    pub found: Option<Vec<u8>>,
+}
+
+impl std::fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let problem = match (&self.expected, &self.found) {
+            (Some(_), None) => "missing entry",
+            (None, Some(_)) => "unexpected entry",
+            _ => "wrong value",
+        };
+        write!(
+            f,
+            "{} key {}: {}",
+            self.column_family,
+            hex::encode(&self.key),
+            problem
+        )
+    }
+}
+
+/// Result of checking a blockchain database.
+pub struct CheckReport {
+    /// Statistics of replaying the blocks to recompute the database.
+    pub replay: ReplayReport,
+    pub discrepancies: Vec<Discrepancy>,
+    /// Whether the discrepancies were fixed in the database.
+    pub repaired: bool,
+}
+
+impl BlockChain {
+    /// Check that the column families of the blockchain database at `path` are consistent with
+    /// the blocks in `blockdb`. The blocks are replayed into a fresh database at `scratch_path`,
+    /// and every entry is compared with the recomputed one. With `repair`, wrong entries are
+    /// overwritten with the recomputed ones. The database must not be in use.
+    pub fn check<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+        path: P,
+        scratch_path: Q,
+        blockdb: &BlockDatabase,
+        config: BlockchainConfig,
+        repair: bool,
+    ) -> Result<CheckReport> {
+        let checked = Self::open(&path, config.clone())?;
+        let reference = Self::new(&scratch_path, config.clone())?;
+
+        // blocks in the database were validated when inserted, others must be validated now, so
+        // that valid blocks missing from the database are reported
+        let replay = reference.replay(blockdb, |hash| checked.contains_block(hash))?;
+        reference.update_ledger()?;
+        info!(
+            "Replayed {} blocks, {} invalid blocks ignored",
+            replay.replayed_blocks, replay.invalid_blocks
+        );
+
+        // compare the database with the recomputed one
+        let mut discrepancies: Vec<Discrepancy> = vec![];
+        // levels whose leader is only recorded in the database, but would be kept there
+        let mut kept_levels: HashSet<u64> = HashSet::new();
+        for cf_name in CHECKED_CFS {
+            let checked_cf = checked.db.cf_handle(cf_name).unwrap();
+            let reference_cf = reference.db.cf_handle(cf_name).unwrap();
+            let mut found = checked.db.iterator_cf(checked_cf, IteratorMode::Start)?.peekable();
+            let mut expected = reference
+                .db
+                .iterator_cf(reference_cf, IteratorMode::Start)?
+                .peekable();
+            loop {
+                let order = match (found.peek(), expected.peek()) {
+                    (None, None) => break,
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (Some((f, _)), Some((e, _))) => f.cmp(e),
+                };
+                let (key, found_value, expected_value) = match order {
+                    std::cmp::Ordering::Less => {
+                        let (k, v) = found.next().unwrap();
+                        (k, Some(v), None)
+                    }
+                    std::cmp::Ordering::Greater => {
+                        let (k, v) = expected.next().unwrap();
+                        (k, None, Some(v))
+                    }
+                    std::cmp::Ordering::Equal => {
+                        let (k, f) = found.next().unwrap();
+                        let (_, e) = expected.next().unwrap();
+                        if normalize(cf_name, &f) == normalize(cf_name, &e) {
+                            continue;
+                        }
+                        (k, Some(f), Some(e))
+                    }
+                };
+                // a leader needs more votes to be confirmed than to be kept, so a leader the
+                // replay does not confirm is fine as long as the votes still keep it
+                if expected_value.is_none() {
+                    if *cf_name == PROPOSER_LEADER_SEQUENCE_CF {
+                        let level: u64 = deserialize(&key).unwrap();
+                        let leader: H256 = deserialize(found_value.as_ref().unwrap()).unwrap();
+                        if reference.proposer_leader(level, false)? == Some(leader) {
+                            kept_levels.insert(level);
+                            continue;
+                        }
+                    }
+                    if *cf_name == PROPOSER_LEDGER_ORDER_CF {
+                        let level: u64 = deserialize(&key).unwrap();
+                        if kept_levels.contains(&level) {
+                            continue;
+                        }
+                    }
+                    if *cf_name == PROPOSER_LEDGER_LEVEL_CF {
+                        let level: u64 = deserialize(found_value.as_ref().unwrap()).unwrap();
+                        if kept_levels.contains(&level) {
+                            continue;
+                        }
+                    }
+                }
+                discrepancies.push(Discrepancy {
+                    column_family: *cf_name,
+                    key: key.to_vec(),
+                    expected: expected_value.map(|v| v.to_vec()),
+                    found: found_value.map(|v| v.to_vec()),
+                });
+            }
+        }
+
+        let repaired = repair && !discrepancies.is_empty();
+        if repaired {
+            let mut wb = WriteBatch::default();
+            for d in &discrepancies {
+                let cf = checked.db.cf_handle(d.column_family).unwrap();
+                match &d.expected {
+                    Some(value) => wb.put_cf(cf, &d.key, value)?,
+                    None => wb.delete_cf(cf, &d.key)?,
+                }
+            }
+            checked.db.write(wb)?;
+            info!("Repaired {} entries", discrepancies.len());
+        }
+
+        Ok(CheckReport {
+            replay,
+            discrepancies,
+            repaired,
+        })
+    }
+
+    /// Whether the block has been inserted, whatever its type.
+    fn contains_block(&self, hash: &H256) -> Result<bool> {
+        let parent_neighbor_cf = self.db.cf_handle(PARENT_NEIGHBOR_CF).unwrap();
+        Ok(self
+            .db
+            .get_pinned_cf(parent_neighbor_cf, serialize(&hash).unwrap())?
+            .is_some())
+    }
+}
+
+/// Bring a value into a canonical form, since lists built by merge operators depend on the order
+/// in which blocks were inserted.
+fn normalize(cf_name: &str, value: &[u8]) -> Vec<u8> {
+    match cf_name {
+        PROPOSER_TREE_LEVEL_CF
+        | VOTE_NEIGHBOR_CF
+        | PROPOSER_VOTER_NEIGHBOR_CF
+        | TRANSACTION_REFERRER_NEIGHBOR_CF
+        | TRANSACTION_BLOCK_CF => {
+            let mut list: Vec<H256> = deserialize(value).unwrap();
+            list.sort();
+            serialize(&list).unwrap()
+        }
+        PROPOSER_NODE_VOTE_CF => {
+            let mut votes: Vec<(u16, u64)> = deserialize(value).unwrap();
+            votes.sort();
+            serialize(&votes).unwrap()
+        }
+        _ => value.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{proposer_block, transaction_block, voter_block};
+
+    #[test]
+    fn check_and_repair() {
+        const NUM_VOTER_CHAINS: u16 = 3;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.1,20.0);
+        let path = "/tmp/prism_test_blockchain_check.rocksdb";
+        let scratch_path = "/tmp/prism_test_blockchain_check_scratch.rocksdb";
+        let blockdb =
+            BlockDatabase::new("/tmp/prism_test_blockdb_check.rocksdb", config.clone()).unwrap();
+        let db = BlockChain::new(path, config.clone()).unwrap();
+
+        let tx_block = transaction_block(config.proposer_genesis, 0, vec![]);
+        let proposer = proposer_block(config.proposer_genesis, 1, vec![], vec![tx_block.hash()]);
+        let mut blocks = vec![tx_block, proposer.clone()];
+        for chain in 0..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { vec![proposer.hash()] } else { vec![] };
+                let block = voter_block(proposer.hash(), depth, chain, voter_parent, votes);
+                voter_parent = block.hash();
+                blocks.push(block);
+            }
+        }
+        for block in &blocks {
+            blockdb.insert(block).unwrap();
+            db.insert_block(block).unwrap();
+        }
+        db.update_ledger().unwrap();
+
+        // corrupt a vote count
+        let proposer_vote_count_cf = db.db.cf_handle(PROPOSER_VOTE_COUNT_CF).unwrap();
+        db.db
+            .put_cf(
+                proposer_vote_count_cf,
+                serialize(&proposer.hash()).unwrap(),
+                serialize(&(1 as u64)).unwrap(),
+            )
+            .unwrap();
+        drop(db);
+
+        let report =
+            BlockChain::check(path, scratch_path, &blockdb, config.clone(), false).unwrap();
+        assert_eq!(report.replay.replayed_blocks, blocks.len());
+        assert_eq!(report.replay.invalid_blocks, 0);
+        assert_eq!(report.replay.unresolved_blocks, 0);
+        assert_eq!(report.discrepancies.len(), 1);
+        assert_eq!(report.discrepancies[0].column_family, PROPOSER_VOTE_COUNT_CF);
+        assert!(!report.repaired);
+
+        let report =
+            BlockChain::check(path, scratch_path, &blockdb, config.clone(), true).unwrap();
+        assert!(report.repaired);
+        let report =
+            BlockChain::check(path, scratch_path, &blockdb, config.clone(), false).unwrap();
+        assert!(report.discrepancies.is_empty());
+    }
+}
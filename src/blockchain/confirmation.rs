@@ -0,0 +1,393 @@
+use crate::config::{BlockchainConfig, ConfirmationRule};
+use crate::crypto::hash::H256;
+use statrs::distribution::{Discrete, Poisson, Univariate};
+
+/// The votes on the proposer blocks of one level, as seen from the voter main chains.
+pub struct LevelVotes {
+    /// Number of voter chains.
+    pub voter_chains: u16,
+    /// The proposer blocks on this level, each with the depth of every main chain vote on it.
+    pub blocks: Vec<(H256, Vec<u64>)>,
+    /// Number of voter blocks mined on the voter main chains since the votes were cast.
+    pub total_vote_blocks: u64,
+    /// Number of proposer levels on top of this one.
+    pub proposer_depth: u64,
+    /// The block of this level on the longest proposer chain. Only filled in for policies that
+    /// follow the proposer chain.
+    pub proposer_main_chain: Option<H256>,
+}
+
+impl LevelVotes {
+    /// Total number of votes cast on this level.
+    pub fn total_votes(&self) -> u16 {
+        self.blocks.iter().map(|(_, v)| v.len() as u16).sum()
+    }
+}
+
+/// A rule deciding the leader block of each proposer level.
+pub trait ConfirmationPolicy: Send + Sync {
+    /// Get the leader of the level, or None if it cannot be decided yet. `confirm` is set when
+    /// the level has no leader yet, so that policies can be stricter when confirming a new leader
+    /// than when keeping an existing one.
+    fn leader(&self, level: &LevelVotes, confirm: bool) -> Option<H256>;
+
+    /// Get a list of blocks that is guaranteed to contain the eventual leader of the level, or
+    /// None if no such list can be decided yet.
+    fn leader_list(&self, level: &LevelVotes) -> Option<Vec<H256>>;
+
+    /// Get the probability that the given block is not the eventual leader of the level. Policies
+    /// without a confidence model report 0 for the block they would confirm and 1 otherwise.
+    fn reversal_probability(&self, level: &LevelVotes, block: &H256) -> f64 {
+        if self.leader(level, true) == Some(*block) {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Whether the policy needs the longest proposer chain in `LevelVotes`.
+    fn follows_proposer_chain(&self) -> bool {
+        false
+    }
+}
+
+/// Create the confirmation policy selected in the config.
+pub fn policy(config: &BlockchainConfig) -> Box<dyn ConfirmationPolicy> {
+    match config.confirmation_rule {
+        ConfirmationRule::Lcb => Box::new(LcbPolicy::new(config)),
+        ConfirmationRule::KDeep(depth) => Box::new(KDeepPolicy { depth }),
+        ConfirmationRule::LongestChain(depth) => Box::new(LongestChainPolicy { depth }),
+    }
+}
+
+/// Largest Gaussian quantile we consider when searching for the confidence of a leader.
+const MAX_QUANTILE: f32 = 64.0;
+
+/// Get the epsilon corresponding to a Gaussian quantile. This inverts the way the quantiles are
+/// derived from `-log(epsilon)` in the config.
+fn epsilon_from_quantile(quantile: f32) -> f64 {
+    let target = f64::from(quantile) * f64::from(quantile);
+    let f = |log_epsilon: f64| {
+        2.0 * log_epsilon - (2.0 * log_epsilon).ln() - (2.0 * std::f64::consts::PI).ln()
+    };
+    // f is increasing above 0.5, and f(0.5) is negative
+    let mut low: f64 = 0.5;
+    let mut high: f64 = 2.0 + target;
+    for _ in 0..64 {
+        let mid = (low + high) / 2.0;
+        if f(mid) < target {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (-low).exp()
+}
+
+/// Pick the leader given a lower bound on the votes each block keeps. The leader is the block
+/// with the most votes, as long as no other block, including a private one, could get the
+/// remaining votes and overtake it.
+fn leader_by_votes(votes_lcb: &[(H256, f32)], voter_chains: u16) -> Option<H256> {
+    let mut new_leader: Option<H256> = None;
+    let mut total_votes_lcb: f32 = 0.0;
+    let mut max_vote_lcb: f32 = 0.0;
+
+    for (block, block_votes_lcb) in votes_lcb {
+        let block_votes_lcb = *block_votes_lcb;
+        total_votes_lcb += block_votes_lcb;
+
+        if max_vote_lcb < block_votes_lcb {
+            max_vote_lcb = block_votes_lcb;
+            new_leader = Some(*block);
+        }
+        // In case of a tie, choose block with lower hash.
+        if (max_vote_lcb - block_votes_lcb).abs() < std::f32::EPSILON && new_leader.is_some() {
+            // TODO: is_some required?
+            if *block < new_leader.unwrap() {
+                new_leader = Some(*block);
+            }
+        }
+    }
+    // check if the lcb_vote of new_leader is bigger than second best ucb votes
+    let remaining_votes = f32::from(voter_chains) - total_votes_lcb;
+
+    // if max_vote_lcb is lesser than the remaining_votes, then a private block could
+    // get the remaining votes and become the leader block
+    if max_vote_lcb <= remaining_votes || new_leader.is_none() {
+        return None;
+    }
+    for (p_block, p_block_votes_lcb) in votes_lcb {
+        // if the below condition is true, then final votes on p_block could overtake new_leader
+        if max_vote_lcb < p_block_votes_lcb + remaining_votes && *p_block != new_leader.unwrap() {
+            return None;
+        }
+        //In case of a tie, choose block with lower hash.
+        if (max_vote_lcb - (p_block_votes_lcb + remaining_votes)).abs() < std::f32::EPSILON
+            && *p_block < new_leader.unwrap()
+        {
+            return None;
+        }
+    }
+    new_leader
+}
+
+/// List the blocks that could still become the leader given a lower bound on the votes each
+/// block keeps, or None if a block not on the list could still win.
+fn leader_list_by_votes(votes_lcb: &[(H256, f32)], voter_chains: u16) -> Option<Vec<H256>> {
+    let total_votes_lcb: f32 = votes_lcb.iter().map(|(_, v)| v).sum();
+    let max_vote_lcb: f32 = votes_lcb.iter().map(|(_, v)| *v).fold(0.0, f32::max);
+    let remaining_votes = f32::from(voter_chains) - total_votes_lcb;
+
+    // a private block could get the remaining votes and become the leader block
+    if max_vote_lcb <= remaining_votes {
+        return None;
+    }
+    // keep every block whose final votes could reach those of the current best block
+    Some(
+        votes_lcb
+            .iter()
+            .filter(|(_, v)| v + remaining_votes >= max_vote_lcb)
+            .map(|(block, _)| *block)
+            .collect(),
+    )
+}
+
+/// Lower confidence bound on the votes that survive an adversary with the given share of the
+/// hashing power, using a Gaussian approximation.
+pub struct LcbPolicy {
+    pub adversary_ratio: f32,
+    pub quantile_confirm: f32,
+    pub quantile_deconfirm: f32,
+}
+
+impl LcbPolicy {
+    pub fn new(config: &BlockchainConfig) -> Self {
+        Self {
+            adversary_ratio: config.adversary_ratio,
+            quantile_confirm: config.quantile_epsilon_confirm,
+            quantile_deconfirm: config.quantile_epsilon_deconfirm,
+        }
+    }
+
+    /// Compute the lower confidence bound of the votes on each block of the level, in the order
+    /// of `level.blocks`. Returns None if not more than 3/5 of the voter chains have voted.
+    pub fn votes_lcb(&self, level: &LevelVotes, quantile: f32) -> Option<Vec<(H256, f32)>> {
+        let total_vote_count = level.total_votes();
+        // no point in going further if less than 3/5 votes are cast
+        if total_vote_count <= level.voter_chains * 3 / 5 {
+            return None;
+        }
+
+        // calculate the average number of voter blocks mined after
+        // a vote is casted. we use this as an estimator of honest mining
+        // rate, and then derive the believed malicious mining rate
+        let avg_vote_blocks = level.total_vote_blocks as f32 / f32::from(total_vote_count);
+        // expected voter depth of an adversary
+        let adversary_expected_vote_depth =
+            avg_vote_blocks / (1.0 - self.adversary_ratio) * self.adversary_ratio;
+        let poisson = Poisson::new(f64::from(adversary_expected_vote_depth)).unwrap();
+
+        // for each block calculate the lower bound on the number of votes
+        let mut votes_lcb: Vec<(H256, f32)> = vec![];
+
+        for (block, votes) in &level.blocks {
+            let mut block_votes_mean: f32 = 0.0; // mean E[X]
+            let mut block_votes_variance: f32 = 0.0; // Var[X]
+            let mut block_votes_lcb: f32 = 0.0;
+            for depth in votes.iter() {
+                // probability that the adversary will remove this vote
+                let mut p: f32 = 1.0 - poisson.cdf((*depth as f32 + 1.0).into()) as f32;
+                for k in 0..(*depth as u64) {
+                    // probability that the adversary has mined k blocks
+                    let p1 = poisson.pmf(k) as f32;
+                    // probability that the adversary will overtake 'depth-k' blocks
+                    let p2 = (self.adversary_ratio / (1.0 - self.adversary_ratio))
+                        .powi((depth - k + 1) as i32);
+                    p += p1 * p2;
+                }
+                block_votes_mean += 1.0 - p;
+                block_votes_variance += p * (1.0 - p);
+            }
+            // using gaussian approximation
+            let tmp = block_votes_mean - (block_votes_variance).sqrt() * quantile;
+            if tmp > 0.0 {
+                block_votes_lcb += tmp;
+            }
+            votes_lcb.push((*block, block_votes_lcb));
+        }
+        Some(votes_lcb)
+    }
+}
+
+impl ConfirmationPolicy for LcbPolicy {
+    fn leader(&self, level: &LevelVotes, confirm: bool) -> Option<H256> {
+        // we confirm with a higher confidence so we don't have false deconfirmation
+        let quantile = if confirm {
+            self.quantile_confirm
+        } else {
+            self.quantile_deconfirm
+        };
+        let votes_lcb = self.votes_lcb(level, quantile)?;
+        leader_by_votes(&votes_lcb, level.voter_chains)
+    }
+
+    fn leader_list(&self, level: &LevelVotes) -> Option<Vec<H256>> {
+        let votes_lcb = self.votes_lcb(level, self.quantile_confirm)?;
+        leader_list_by_votes(&votes_lcb, level.voter_chains)
+    }
+
+    /// Get the probability that the given block is not the eventual leader of the level. This is
+    /// the epsilon of the strictest confidence at which the block is confirmed as the leader, or 1
+    /// if it is not the leader at any confidence.
+    fn reversal_probability(&self, level: &LevelVotes, block: &H256) -> f64 {
+        let is_leader = |quantile: f32| match self.votes_lcb(level, quantile) {
+            Some(votes_lcb) => leader_by_votes(&votes_lcb, level.voter_chains) == Some(*block),
+            None => false,
+        };
+        if !is_leader(0.0) {
+            return 1.0;
+        }
+        // search for the largest quantile that still confirms the block
+        let mut low: f32 = 0.0;
+        let mut high: f32 = MAX_QUANTILE;
+        if is_leader(high) {
+            return epsilon_from_quantile(high);
+        }
+        for _ in 0..32 {
+            let mid = (low + high) / 2.0;
+            if is_leader(mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        epsilon_from_quantile(low)
+    }
+}
+
+/// Votes at least `depth` deep are considered permanent, and a block with permanent votes on a
+/// majority of voter chains is the leader.
+pub struct KDeepPolicy {
+    pub depth: u64,
+}
+
+impl KDeepPolicy {
+    fn deep_votes(&self, level: &LevelVotes) -> Vec<(H256, f32)> {
+        level
+            .blocks
+            .iter()
+            .map(|(block, votes)| {
+                let deep = votes.iter().filter(|&&d| d >= self.depth).count();
+                (*block, deep as f32)
+            })
+            .collect()
+    }
+}
+
+impl ConfirmationPolicy for KDeepPolicy {
+    fn leader(&self, level: &LevelVotes, _confirm: bool) -> Option<H256> {
+        self.deep_votes(level)
+            .into_iter()
+            .find(|(_, votes)| *votes * 2.0 > f32::from(level.voter_chains))
+            .map(|(block, _)| block)
+    }
+
+    fn leader_list(&self, level: &LevelVotes) -> Option<Vec<H256>> {
+        leader_list_by_votes(&self.deep_votes(level), level.voter_chains)
+    }
+}
+
+/// The block on the longest proposer chain is the leader once it is `depth` levels deep.
+pub struct LongestChainPolicy {
+    pub depth: u64,
+}
+
+impl ConfirmationPolicy for LongestChainPolicy {
+    fn leader(&self, level: &LevelVotes, _confirm: bool) -> Option<H256> {
+        if level.proposer_depth >= self.depth {
+            level.proposer_main_chain
+        } else {
+            None
+        }
+    }
+
+    fn leader_list(&self, level: &LevelVotes) -> Option<Vec<H256>> {
+        self.leader(level, true).map(|block| vec![block])
+    }
+
+    fn follows_proposer_chain(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(blocks: Vec<(H256, Vec<u64>)>) -> LevelVotes {
+        LevelVotes {
+            voter_chains: 10,
+            total_vote_blocks: blocks.iter().map(|(_, v)| v.iter().sum::<u64>()).sum(),
+            blocks,
+            proposer_depth: 3,
+            proposer_main_chain: Some([2u8; 32].into()),
+        }
+    }
+
+    #[test]
+    fn k_deep() {
+        let policy = KDeepPolicy { depth: 6 };
+        let a: H256 = [1u8; 32].into();
+        let b: H256 = [2u8; 32].into();
+        // 5 deep votes are not a majority of 10 chains
+        let votes = level(vec![(a, vec![6, 6, 6, 6, 6, 1]), (b, vec![6, 6, 6])]);
+        assert_eq!(policy.leader(&votes, true), None);
+        // but the leader is one of the two blocks
+        assert_eq!(policy.leader_list(&votes), Some(vec![a, b]));
+        let votes = level(vec![(a, vec![6, 6, 6, 6, 6, 6]), (b, vec![6, 6, 6])]);
+        assert_eq!(policy.leader(&votes, true), Some(a));
+        assert_eq!(policy.leader_list(&votes), Some(vec![a]));
+    }
+
+    #[test]
+    fn longest_chain() {
+        let votes = level(vec![]);
+        assert_eq!(
+            LongestChainPolicy { depth: 3 }.leader(&votes, true),
+            Some([2u8; 32].into())
+        );
+        assert_eq!(LongestChainPolicy { depth: 4 }.leader(&votes, true), None);
+        assert_eq!(LongestChainPolicy { depth: 4 }.leader_list(&votes), None);
+    }
+
+    #[test]
+    fn epsilon() {
+        // the config derives the quantile from -log(epsilon) = 20
+        let log_epsilon: f32 = 20.0;
+        let quantile =
+            (2.0 * log_epsilon - (2.0 * log_epsilon).ln() - (2.0 * 3.141_692_6 as f32).ln()).sqrt();
+        let epsilon = epsilon_from_quantile(quantile);
+        assert!((epsilon.ln() + 20.0).abs() < 0.01);
+        assert!(epsilon_from_quantile(2.0) > epsilon_from_quantile(3.0));
+    }
+
+    #[test]
+    fn lcb() {
+        let policy = LcbPolicy {
+            adversary_ratio: 0.1,
+            quantile_confirm: 6.0,
+            quantile_deconfirm: 5.0,
+        };
+        let a: H256 = [1u8; 32].into();
+        let b: H256 = [2u8; 32].into();
+        // not enough votes
+        let votes = level(vec![(a, vec![20; 6])]);
+        assert_eq!(policy.leader(&votes, true), None);
+        // deep votes on all chains
+        let votes = level(vec![(a, vec![20; 7]), (b, vec![20; 3])]);
+        assert_eq!(policy.leader(&votes, true), Some(a));
+        assert_eq!(policy.leader(&votes, false), Some(a));
+        assert!(policy.reversal_probability(&votes, &a) < 1e-6);
+        assert_eq!(policy.reversal_probability(&votes, &b), 1.0);
+    }
+}
@@ -1,17 +1,29 @@
+mod check;
+pub mod confirmation;
+mod replay;
+mod voter_levels;
+
 use crate::block::{Block, Content};
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::transaction::Transaction;
 use bincode::{deserialize, serialize};
 use log::{debug, info, warn};
 use rocksdb::{ColumnFamilyDescriptor, Options, WriteBatch, DB};
-use statrs::distribution::{Discrete, Poisson, Univariate};
+use confirmation::{ConfirmationPolicy, LcbPolicy, LevelVotes};
+use voter_levels::VoterLevels;
+pub use check::{CheckReport, Discrepancy};
+pub use replay::ReplayReport;
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 use std::ops::Range;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex};
+
+/// Maximum number of threads recomputing the leaders of the affected proposer levels.
+const LEADER_THREADS: usize = 4;
 
 // Column family names for node/chain metadata
 const PROPOSER_NODE_LEVEL_CF: &str = "PROPOSER_NODE_LEVEL"; // hash to node level (u64)
@@ -25,6 +37,7 @@ const PROPOSER_LEADER_SEQUENCE_CF: &str = "PROPOSER_LEADER_SEQUENCE"; // level (
 const PROPOSER_LEDGER_ORDER_CF: &str = "PROPOSER_LEDGER_ORDER"; // level (u64) to the list of proposer blocks confirmed
 // by this level, including the leader itself. The list
 // is in the order that those blocks should live in the ledger.
+const PROPOSER_LEDGER_LEVEL_CF: &str = "PROPOSER_LEDGER_LEVEL"; // hash to the level (u64) whose ledger order includes it
 const PROPOSER_VOTE_COUNT_CF: &str = "PROPOSER_VOTE_COUNT"; // number of all votes on a block
 
 // Column family names for graph neighbors
@@ -33,20 +46,67 @@ const VOTE_NEIGHBOR_CF: &str = "GRAPH_VOTE_NEIGHBOR"; // neighbors associated by
 const VOTER_PARENT_NEIGHBOR_CF: &str = "GRAPH_VOTER_PARENT_NEIGHBOR"; // the voter parent of a block
 const TRANSACTION_REF_NEIGHBOR_CF: &str = "GRAPH_TRANSACTION_REF_NEIGHBOR";
 const PROPOSER_REF_NEIGHBOR_CF: &str = "GRAPH_PROPOSER_REF_NEIGHBOR";
+const PROPOSER_VOTER_NEIGHBOR_CF: &str = "GRAPH_PROPOSER_VOTER_NEIGHBOR"; // voter blocks voting on a proposer block
+const TRANSACTION_REFERRER_NEIGHBOR_CF: &str = "GRAPH_TRANSACTION_REFERRER_NEIGHBOR"; // proposer blocks referring to a transaction block
+
+// Column family names for transaction indices
+const TRANSACTION_BLOCK_CF: &str = "TRANSACTION_BLOCK"; // hash of transaction to hashes of the transaction blocks including it (Vec<hash>)
 
 pub type Result<T> = std::result::Result<T, rocksdb::Error>;
 
+/// How likely a transaction block is to stay in the ledger.
+pub struct TransactionBlockConfidence {
+    pub hash: H256,
+    /// The proposer blocks referring to the transaction block.
+    pub proposers: Vec<ProposerConfidence>,
+    /// Probability that the transaction block drops out of the ledger. This is the smallest
+    /// among the proposer blocks referring to it.
+    pub epsilon: f64,
+    /// Whether the transaction block is confirmed by the configured confirmation rule.
+    pub confirmed: bool,
+}
+
+/// How likely a proposer block is to stay in the ledger.
+pub struct ProposerConfidence {
+    pub hash: H256,
+    pub level: u64,
+    /// Depth of each main chain vote on the proposer block.
+    pub vote_depths: Vec<u64>,
+    /// Lower confidence bound on the votes the proposer block keeps, at the configured confidence.
+    pub votes_lcb: f32,
+    /// Votes on the level of the proposer block that are not cast yet or may be reversed.
+    pub remaining_votes: f32,
+    /// Level of the leader whose ledger includes the proposer block, if any.
+    pub ledger_level: Option<u64>,
+    /// Probability that the proposer block drops out of the ledger.
+    pub epsilon: f64,
+    /// Whether the proposer block is in the ledger, or is the leader the configured confirmation
+    /// rule would confirm on its level.
+    pub confirmed: bool,
+}
+
 // cf_handle is a lightweight operation, it takes 44000 micro seconds to get 100000 cf handles
 
 pub struct BlockChain {
     db: DB,
     proposer_best_level: Mutex<u64>,
     voter_best: Vec<Mutex<(H256, u64)>>,
+    voter_levels: Mutex<VoterLevels>,
     unreferred_transactions: Mutex<HashMap<H256,u128>>,
     unreferred_proposers: Mutex<HashMap<H256,u128>>,
     unconfirmed_proposers: Mutex<HashSet<H256>>,
+    /// Transaction blocks fast confirmed beyond the ledger tip, with the proposer level at which
+    /// they were confirmed. They are dropped once the ledger passes that level.
+    fast_confirmed_transactions: Mutex<HashMap<H256, u64>>,
     proposer_ledger_tip: Mutex<u64>,
     voter_ledger_tips: Mutex<Vec<H256>>,
+    /// Highest proposer level whose metadata has been pruned.
+    pruned_level: Mutex<u64>,
+    /// Set when a chain tip moves after the last ledger update, so that the ledger is only
+    /// updated when its result may change.
+    tip_updated: Mutex<bool>,
+    tip_updated_cond: Condvar,
+    confirmation_policy: Box<dyn ConfirmationPolicy>,
     config: BlockchainConfig,
 }
 
@@ -82,6 +142,7 @@ impl BlockChain {
         add_cf!(VOTER_NODE_VOTED_LEVEL_CF);
         add_cf!(PROPOSER_LEADER_SEQUENCE_CF);
         add_cf!(PROPOSER_LEDGER_ORDER_CF);
+        add_cf!(PROPOSER_LEDGER_LEVEL_CF);
         add_cf!(PROPOSER_TREE_LEVEL_CF, h256_vec_append_merge);
         add_cf!(PROPOSER_NODE_VOTE_CF, vote_vec_full_merge, vote_vec_partial_merge);
         add_cf!(PARENT_NEIGHBOR_CF, h256_vec_append_merge);
@@ -91,6 +152,9 @@ impl BlockChain {
         add_cf!(VOTER_PARENT_NEIGHBOR_CF, h256_vec_append_merge);
         add_cf!(TRANSACTION_REF_NEIGHBOR_CF, h256_vec_append_merge);
         add_cf!(PROPOSER_REF_NEIGHBOR_CF, h256_vec_append_merge);
+        add_cf!(PROPOSER_VOTER_NEIGHBOR_CF, h256_vec_append_merge);
+        add_cf!(TRANSACTION_REFERRER_NEIGHBOR_CF, h256_vec_append_merge);
+        add_cf!(TRANSACTION_BLOCK_CF, h256_vec_append_merge);
 
         let mut opts = Options::default();
         opts.create_if_missing(true);
@@ -105,11 +169,17 @@ impl BlockChain {
             db,
             proposer_best_level: Mutex::new(0),
             voter_best,
+            voter_levels: Mutex::new(VoterLevels::new(config.voter_chains)),
             unreferred_transactions: Mutex::new(HashMap::new()),
             unreferred_proposers: Mutex::new(HashMap::new()),
             unconfirmed_proposers: Mutex::new(HashSet::new()),
+            fast_confirmed_transactions: Mutex::new(HashMap::new()),
             proposer_ledger_tip: Mutex::new(0),
             voter_ledger_tips: Mutex::new(vec![H256::default(); config.voter_chains as usize]),
+            pruned_level: Mutex::new(0),
+            tip_updated: Mutex::new(false),
+            tip_updated_cond: Condvar::new(),
+            confirmation_policy: confirmation::policy(&config),
             config,
         };
 
@@ -133,6 +203,7 @@ impl BlockChain {
         let proposer_vote_count_cf = db.db.cf_handle(PROPOSER_VOTE_COUNT_CF).unwrap();
         let proposer_leader_sequence_cf = db.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_order_cf = db.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        let proposer_ledger_level_cf = db.db.cf_handle(PROPOSER_LEDGER_LEVEL_CF).unwrap();
         let proposer_ref_neighbor_cf = db.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let transaction_ref_neighbor_cf = db.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
 
@@ -164,6 +235,11 @@ impl BlockChain {
             serialize(&(0 as u64)).unwrap(),
             serialize(&proposer_genesis_ledger).unwrap(),
         )?;
+        wb.put_cf(
+            proposer_ledger_level_cf,
+            serialize(&db.config.proposer_genesis).unwrap(),
+            serialize(&(0 as u64)).unwrap(),
+        )?;
         wb.put_cf(
             proposer_ref_neighbor_cf,
             serialize(&db.config.proposer_genesis).unwrap(),
@@ -221,6 +297,7 @@ impl BlockChain {
             let mut voter_best = db.voter_best[chain_num as usize].lock().unwrap();
             voter_best.0 = db.config.voter_genesis[chain_num as usize];
             drop(voter_best);
+            db.voter_levels.lock().unwrap().insert(chain_num, 0);
             voter_ledger_tips[chain_num as usize] = db.config.voter_genesis[chain_num as usize];
         }
         drop(voter_ledger_tips);
@@ -245,6 +322,10 @@ impl BlockChain {
         let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
         let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
+        let proposer_voter_neighbor_cf = self.db.cf_handle(PROPOSER_VOTER_NEIGHBOR_CF).unwrap();
+        let transaction_referrer_neighbor_cf =
+            self.db.cf_handle(TRANSACTION_REFERRER_NEIGHBOR_CF).unwrap();
+        let transaction_block_cf = self.db.cf_handle(TRANSACTION_BLOCK_CF).unwrap();
 
         let mut wb = WriteBatch::default();
 
@@ -289,6 +370,9 @@ impl BlockChain {
                     block_hash,
                     content.transaction_refs
                 );
+                for tx_block_hash in &content.transaction_refs {
+                    merge_value!(transaction_referrer_neighbor_cf, tx_block_hash, vec![block_hash]);
+                }
                 // get current block level
                 let parent_level: u64 = get_value!(proposer_node_level_cf, parent_hash);
                 let self_level = parent_level + 1;
@@ -322,11 +406,16 @@ impl BlockChain {
                 // proposer block and is using it as the proposer parent.
                 let mut proposer_best = self.proposer_best_level.lock().unwrap();
                 self.db.write(wb)?;
-                if self_level > *proposer_best {
+                let best_updated = self_level > *proposer_best;
+                if best_updated {
                     *proposer_best = self_level;
                     PERFORMANCE_COUNTER.record_update_proposer_main_chain(self_level as usize);
                 }
                 drop(proposer_best);
+                // policies following the proposer chain may confirm a leader without new votes
+                if best_updated && self.confirmation_policy.follows_proposer_chain() {
+                    self.notify_tip_updated();
+                }
 
                 // remove referenced proposer and transaction blocks from the unreferred list
                 // This could happen after committing to the database. It's because that we are
@@ -370,6 +459,7 @@ impl BlockChain {
                 // add voting blocks for the proposer
                 for proposer_hash in &content.votes {
                     merge_value!(proposer_vote_count_cf, proposer_hash, 1 as u64);
+                    merge_value!(proposer_voter_neighbor_cf, proposer_hash, vec![block_hash]);
                 }
                 // add voted blocks and set deepest voted level
                 put_value!(vote_neighbor_cf, block_hash, content.votes);
@@ -390,38 +480,77 @@ impl BlockChain {
                 // from a record.
                 let mut voter_best = self.voter_best[self_chain as usize].lock().unwrap();
                 // update best block
-                if self_level > voter_best.1 {
+                let best_updated = self_level > voter_best.1;
+                if best_updated {
                     PERFORMANCE_COUNTER
                         .record_update_voter_main_chain(voter_best.1 as usize, self_level as usize);
                     voter_best.0 = block_hash;
                     voter_best.1 = self_level;
                 }
+                self.voter_levels.lock().unwrap().insert(self_chain, self_level);
                 drop(voter_best);
+                if best_updated {
+                    self.notify_tip_updated();
+                }
                 debug!(
                     "Adding voter block {:?} at chain {} level {}",
                     block_hash, self_chain, self_level
                 );
             }
-            Content::Transaction(_content) => {
-                // mark itself as unreferred
+            Content::Transaction(content) => {
+                // index the transactions in this block
+                for tx in &content.transactions {
+                    merge_value!(
+                        transaction_block_cf,
+                        <Transaction as Hashable>::hash(tx),
+                        vec![block_hash]
+                    );
+                }
+                // mark itself as unreferred, unless a proposer block referring to it came first,
+                // as after a snapshot sync
                 // Note that this could happen before committing to db, because no module will try
                 // to access transaction content based on pointers in unreferred_transactions.
-                let mut unreferred_transactions = self.unreferred_transactions.lock().unwrap();
-                unreferred_transactions.insert(block_hash, block.header.timestamp);
-                drop(unreferred_transactions);
+                let referred = self
+                    .db
+                    .get_pinned_cf(transaction_referrer_neighbor_cf, serialize(&block_hash).unwrap())?
+                    .is_some();
+                if !referred {
+                    let mut unreferred_transactions = self.unreferred_transactions.lock().unwrap();
+                    unreferred_transactions.insert(block_hash, block.header.timestamp);
+                    drop(unreferred_transactions);
+                }
 
-                // This db write is only to facilitate check_existence
+                // This db write is to facilitate check_existence and the transaction index
                 self.db.write(wb)?;
             }
         }
         Ok(())
     }
 
-    pub fn update_ledger(&self) -> Result<(Vec<H256>, Vec<H256>)> {
+    fn notify_tip_updated(&self) {
+        let mut tip_updated = self.tip_updated.lock().unwrap();
+        *tip_updated = true;
+        self.tip_updated_cond.notify_all();
+    }
+
+    /// Block until a voter chain tip moves (or the proposer chain tip, if the confirmation policy
+    /// follows it) after the last call, so that `update_ledger` is not called in vain.
+    pub fn wait_for_tip_update(&self) {
+        let mut tip_updated = self.tip_updated.lock().unwrap();
+        while !*tip_updated {
+            tip_updated = self.tip_updated_cond.wait(tip_updated).unwrap();
+        }
+        *tip_updated = false;
+    }
+
+    /// Update the ledger with the latest votes. Returns the proposer blocks added to and removed
+    /// from the ledger, each with the transaction blocks it refers to, in ledger order.
+    pub fn update_ledger(&self) -> Result<(Vec<(H256, Vec<H256>)>, Vec<(H256, Vec<H256>)>)> {
         let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
         let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        let proposer_ledger_level_cf = self.db.cf_handle(PROPOSER_LEDGER_LEVEL_CF).unwrap();
         let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
 
@@ -443,6 +572,8 @@ impl BlockChain {
         }
 
         let mut voter_ledger_tips = self.voter_ledger_tips.lock().unwrap();
+        // votes on pruned levels can no longer change their leaders
+        let pruned_level: u64 = *self.pruned_level.lock().unwrap();
         let mut affected_range: Range<u64> = Range {
             start: std::u64::MAX,
             end: std::u64::MIN,
@@ -460,12 +591,15 @@ impl BlockChain {
 
             // apply the vote diff on the proposer main chain vote cf
             for vote in &removed {
+                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
+                if proposer_level <= pruned_level {
+                    continue;
+                }
                 merge_value!(
                     proposer_node_vote_cf,
                     vote.0,
                     vec![(false, chain_num as u16, vote.1)]
                 );
-                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
                 if proposer_level < affected_range.start {
                     affected_range.start = proposer_level;
                 }
@@ -475,12 +609,15 @@ impl BlockChain {
             }
 
             for vote in &added {
+                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
+                if proposer_level <= pruned_level {
+                    continue;
+                }
                 merge_value!(
                     proposer_node_vote_cf,
                     vote.0,
                     vec![(true, chain_num as u16, vote.1)]
                 );
-                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
                 if proposer_level < affected_range.start {
                     affected_range.start = proposer_level;
                 }
@@ -527,22 +664,9 @@ impl BlockChain {
         // start actually recomputing the leaders
         let mut change_begin: Option<u64> = None;
 
-        for level in affected_range {
-            let existing_leader: Option<H256> =
-                get_value!(proposer_leader_sequence_cf, level as u64);
-            let new_leader_confirm: Option<H256> = self.proposer_leader(level as u64, self.config.quantile_epsilon_confirm)?;
-            let new_leader_deconfirm: Option<H256> = self.proposer_leader(level as u64, self.config.quantile_epsilon_deconfirm)?;
-
-            // we confirm with a higher confidence so we don't have false deconfirmation
-            let new_leader = {
-                if existing_leader.is_some() {
-                    new_leader_deconfirm
-                }
-                else {
-                    new_leader_confirm
-                }
-            };
-
+        let levels: Vec<u64> = affected_range.collect();
+        let leaders = self.recompute_leaders(&levels)?;
+        for (level, (existing_leader, new_leader)) in levels.into_iter().zip(leaders) {
             if new_leader != existing_leader {
                 match new_leader {
                     Some(hash) => info!(
@@ -551,6 +675,9 @@ impl BlockChain {
                     ),
                     None => warn!("Proposer leader deconfirmed for level {}", level),
                 }
+                if existing_leader.is_some() {
+                    PERFORMANCE_COUNTER.record_leader_reversal();
+                }
                 // mark it's the beginning of the change
                 if change_begin.is_none() {
                     change_begin = Some(level);
@@ -596,6 +723,7 @@ impl BlockChain {
                 delete_value!(proposer_ledger_order_cf, level as u64);
                 for block in &original_ledger {
                     unconfirmed_proposers.insert(*block);
+                    delete_value!(proposer_ledger_level_cf, block);
                     removed.push(*block);
                 }
             }
@@ -638,6 +766,9 @@ impl BlockChain {
                         .into_iter()
                         .filter(|h| unconfirmed_proposers.remove(h))
                         .collect();
+                    for block in &order {
+                        put_value!(proposer_ledger_level_cf, block, level as u64);
+                    }
                     put_value!(proposer_ledger_order_cf, level as u64, order);
                     added.extend(&order);
                 }
@@ -645,15 +776,22 @@ impl BlockChain {
             // commit the new ledger into the database
             self.db.write(wb)?;
 
-            let mut removed_transaction_blocks: Vec<H256> = vec![];
-            let mut added_transaction_blocks: Vec<H256> = vec![];
+            let mut removed_transaction_blocks: Vec<(H256, Vec<H256>)> = vec![];
+            let mut added_transaction_blocks: Vec<(H256, Vec<H256>)> = vec![];
             for block in &removed {
                 let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
-                removed_transaction_blocks.extend(&t);
+                removed_transaction_blocks.push((*block, t));
             }
             for block in &added {
                 let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
-                added_transaction_blocks.extend(&t);
+                added_transaction_blocks.push((*block, t));
+            }
+            // the ledger now answers for the fast confirmed blocks it has reached
+            if self.config.fast_confirm {
+                let ledger_tip: u64 = *proposer_ledger_tip;
+                let mut fast_confirmed_transactions =
+                    self.fast_confirmed_transactions.lock().unwrap();
+                fast_confirmed_transactions.retain(|_, level| *level > ledger_tip);
             }
             Ok((added_transaction_blocks, removed_transaction_blocks))
         } else {
@@ -661,7 +799,286 @@ impl BlockChain {
         }
     }
 
-    fn proposer_leader(&self, level: u64, quantile: f32) -> Result<Option<H256>> {
+    /// Prune the metadata of the proposer levels more than `depth` levels behind the ledger tip,
+    /// which are assumed to be final: their votes, the votes of the voter blocks that only vote
+    /// on them, on the main chains and on forks, and the voter level counts below those blocks.
+    /// Votes cast later on pruned levels are ignored. Returns the transaction blocks confirmed on
+    /// the newly pruned levels, and those only referred by proposer blocks left out of the ledger
+    /// on them, whose bodies can be dropped once executed.
+    pub fn prune(&self, depth: u64) -> Result<Vec<H256>> {
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+        let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let proposer_voter_neighbor_cf = self.db.cf_handle(PROPOSER_VOTER_NEIGHBOR_CF).unwrap();
+        let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let transaction_referrer_neighbor_cf =
+            self.db.cf_handle(TRANSACTION_REFERRER_NEIGHBOR_CF).unwrap();
+        let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
+        let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let voter_node_chain_cf = self.db.cf_handle(VOTER_NODE_CHAIN_CF).unwrap();
+        let voter_parent_neighbor_cf = self.db.cf_handle(VOTER_PARENT_NEIGHBOR_CF).unwrap();
+        let vote_neighbor_cf = self.db.cf_handle(VOTE_NEIGHBOR_CF).unwrap();
+        let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                }
+            }};
+        }
+
+        let mut wb = WriteBatch::default();
+        macro_rules! delete_value {
+            ($cf:expr, $key:expr) => {{
+                wb.delete_cf($cf, serialize(&$key).unwrap())?;
+            }};
+        }
+
+        let proposer_ledger_tip: u64 = *self.proposer_ledger_tip.lock().unwrap();
+        let mut pruned_level = self.pruned_level.lock().unwrap();
+        if proposer_ledger_tip <= *pruned_level + depth {
+            return Ok(vec![]);
+        }
+        let prune_to = proposer_ledger_tip - depth;
+
+        let mut transaction_blocks: Vec<H256> = vec![];
+        // the main chain voter block of each chain voting on the last pruned level
+        let mut last_votes: Vec<Option<(H256, u64)>> = vec![None; self.config.voter_chains as usize];
+        // all voter blocks voting on the pruned levels
+        let mut pruned_voters: HashSet<H256> = HashSet::new();
+        let unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap();
+        for level in (*pruned_level + 1)..=prune_to {
+            let proposer_blocks: Vec<H256> = get_value!(proposer_tree_level_cf, level).unwrap();
+            for block in &proposer_blocks {
+                let voters: Vec<H256> =
+                    get_value!(proposer_voter_neighbor_cf, block).unwrap_or_default();
+                if level == prune_to {
+                    let votes: Vec<(u16, u64)> =
+                        get_value!(proposer_node_vote_cf, block).unwrap_or_default();
+                    for voter in &voters {
+                        let chain: u16 = get_value!(voter_node_chain_cf, voter).unwrap();
+                        let voter_level: u64 = get_value!(voter_node_level_cf, voter).unwrap();
+                        if votes.contains(&(chain, voter_level)) {
+                            last_votes[chain as usize] = Some((*voter, voter_level));
+                        }
+                    }
+                }
+                pruned_voters.extend(voters);
+                delete_value!(proposer_node_vote_cf, block);
+                delete_value!(proposer_voter_neighbor_cf, block);
+            }
+            let ledger: Vec<H256> = get_value!(proposer_ledger_order_cf, level).unwrap();
+            for block in &ledger {
+                let refs: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
+                transaction_blocks.extend(refs);
+            }
+            // transaction blocks only referred by proposer blocks left out of the ledger on
+            // pruned levels are never confirmed
+            for block in proposer_blocks.iter().filter(|b| unconfirmed_proposers.contains(b)) {
+                let refs: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
+                for tx_block in refs {
+                    let referrers: Vec<H256> =
+                        get_value!(transaction_referrer_neighbor_cf, tx_block).unwrap();
+                    let mut left_out = true;
+                    for referrer in &referrers {
+                        let referrer_level: u64 =
+                            get_value!(proposer_node_level_cf, referrer).unwrap();
+                        if referrer_level > prune_to || !unconfirmed_proposers.contains(referrer) {
+                            left_out = false;
+                            break;
+                        }
+                    }
+                    if left_out && !transaction_blocks.contains(&tx_block) {
+                        transaction_blocks.push(tx_block);
+                    }
+                }
+            }
+        }
+        drop(unconfirmed_proposers);
+
+        // voter blocks below the ones voting on the last pruned level only vote on pruned levels
+        let mut voter_levels = self.voter_levels.lock().unwrap();
+        for (chain, last_vote) in last_votes.iter().enumerate() {
+            let chain = chain as u16;
+            let (voter, voter_level) = match last_vote {
+                Some(v) => *v,
+                // the chain has not voted on the level yet
+                None => continue,
+            };
+            let base_level = voter_levels.base_level(chain);
+            let mut block: H256 = get_value!(voter_parent_neighbor_cf, voter).unwrap();
+            let mut block_level = voter_level - 1;
+            // keep the genesis block
+            while block_level >= base_level && block_level > 0 {
+                delete_value!(vote_neighbor_cf, block);
+                block = get_value!(voter_parent_neighbor_cf, block).unwrap();
+                block_level -= 1;
+            }
+            for level in base_level..voter_level {
+                delete_value!(voter_tree_level_count_cf, (chain, level));
+            }
+            voter_levels.compact(chain, voter_level);
+        }
+        drop(voter_levels);
+        // so do the voter blocks on forks below them that only vote on pruned levels
+        for voter in &pruned_voters {
+            let chain: u16 = get_value!(voter_node_chain_cf, voter).unwrap();
+            let voter_level: u64 = get_value!(voter_node_level_cf, voter).unwrap();
+            match last_votes[chain as usize] {
+                Some((_, last_level)) if voter_level < last_level => {}
+                _ => continue,
+            }
+            let votes: Vec<H256> = match get_value!(vote_neighbor_cf, voter) {
+                Some(votes) => votes,
+                None => continue,
+            };
+            let mut only_pruned = true;
+            for vote in &votes {
+                let vote_level: u64 = get_value!(proposer_node_level_cf, vote).unwrap();
+                if vote_level > prune_to {
+                    only_pruned = false;
+                    break;
+                }
+            }
+            if only_pruned {
+                delete_value!(vote_neighbor_cf, voter);
+            }
+        }
+        self.db.write(wb)?;
+        debug!("Pruned proposer levels {} to {}", *pruned_level + 1, prune_to);
+        *pruned_level = prune_to;
+        Ok(transaction_blocks)
+    }
+
+    /// Fast confirm transaction blocks by list decoding the proposer levels beyond the ledger tip.
+    /// For each level we confirm a list of proposer blocks that contains the eventual leader, and
+    /// a transaction block is fast confirmed once it is in the ledger of every block on the list.
+    /// Its position in the ledger is not decided yet, so this is only safe for transactions that
+    /// are not double spends. Returns the newly fast confirmed transaction blocks.
+    pub fn update_fast_ledger(&self) -> Result<Vec<H256>> {
+        let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                }
+            }};
+        }
+
+        let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
+        let first_level: u64 = *proposer_ledger_tip + 1;
+        drop(proposer_ledger_tip);
+        let proposer_best = self.proposer_best_level.lock().unwrap();
+        let last_level: u64 = *proposer_best;
+        drop(proposer_best);
+
+        let unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap();
+        let mut fast_confirmed_transactions = self.fast_confirmed_transactions.lock().unwrap();
+        let mut confirmed: Vec<H256> = vec![];
+        // the ledger is continuous, so stop at the first level whose list is not decided
+        for level in first_level..=last_level {
+            let list = match self.proposer_leader_list(level)? {
+                Some(list) => list,
+                None => break,
+            };
+            // intersect the transaction blocks that each block on the list adds to the ledger
+            let mut common: Option<HashSet<H256>> = None;
+            for candidate in &list {
+                let mut transactions: HashSet<H256> = HashSet::new();
+                let mut visited: HashSet<H256> = HashSet::new();
+                let mut stack: Vec<H256> = vec![*candidate];
+                while let Some(top) = stack.pop() {
+                    // blocks already in the ledger are confirmed no matter which block we pick
+                    if !unconfirmed_proposers.contains(&top) || !visited.insert(top) {
+                        continue;
+                    }
+                    let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, top).unwrap();
+                    transactions.extend(t);
+                    let refs: Vec<H256> = get_value!(proposer_ref_neighbor_cf, top).unwrap();
+                    stack.extend(refs);
+                }
+                common = Some(match common {
+                    None => transactions,
+                    Some(c) => c.intersection(&transactions).cloned().collect(),
+                });
+            }
+            for hash in common.unwrap_or_default() {
+                if fast_confirmed_transactions.contains_key(&hash)
+                    || self.in_ledger(&hash, &unconfirmed_proposers)?
+                {
+                    continue;
+                }
+                fast_confirmed_transactions.insert(hash, level);
+                confirmed.push(hash);
+            }
+        }
+        Ok(confirmed)
+    }
+
+    /// Get the existing and the newly computed leader of each of the given levels. Levels are
+    /// independent of each other, so they are split among several threads.
+    fn recompute_leaders(&self, levels: &[u64]) -> Result<Vec<(Option<H256>, Option<H256>)>> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+
+        let recompute = |level: u64| -> Result<(Option<H256>, Option<H256>)> {
+            let existing_leader: Option<H256> =
+                match self.db.get_pinned_cf(proposer_leader_sequence_cf, serialize(&level).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                };
+            // we confirm with a higher confidence so we don't have false deconfirmation
+            let new_leader = self.proposer_leader(level, existing_leader.is_none())?;
+            Ok((existing_leader, new_leader))
+        };
+
+        if levels.len() <= 1 {
+            return levels.iter().map(|level| recompute(*level)).collect();
+        }
+        let chunk_size = (levels.len() + LEADER_THREADS - 1) / LEADER_THREADS;
+        let recompute = &recompute;
+        crossbeam::scope(|s| {
+            let handles: Vec<_> = levels
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move |_| {
+                        chunk
+                            .iter()
+                            .map(|level| recompute(*level))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            let mut leaders = Vec::with_capacity(levels.len());
+            for handle in handles {
+                leaders.extend(handle.join().unwrap()?);
+            }
+            Ok(leaders)
+        })
+        .unwrap()
+    }
+
+    /// Get the leader of the given level according to the confirmation policy. `confirm` is set
+    /// when the level has no leader yet.
+    fn proposer_leader(&self, level: u64, confirm: bool) -> Result<Option<H256>> {
+        let votes = self.level_votes(level)?;
+        Ok(self.confirmation_policy.leader(&votes, confirm))
+    }
+
+    /// Get the list of proposer blocks on the given level that may still become the leader. The
+    /// leader of the level is guaranteed to be in the list, even if it cannot be decided yet.
+    /// Returns None if a block not in the list, possibly a private one, could still win the level.
+    fn proposer_leader_list(&self, level: u64) -> Result<Option<Vec<H256>>> {
+        let votes = self.level_votes(level)?;
+        Ok(self.confirmation_policy.leader_list(&votes))
+    }
+
+    /// Collect the main chain votes on the proposer blocks of the given level.
+    fn level_votes(&self, level: u64) -> Result<LevelVotes> {
         let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
         let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
 
@@ -674,38 +1091,39 @@ impl BlockChain {
             }};
         }
         let proposer_blocks: Vec<H256> = get_value!(proposer_tree_level_cf, level as u64).unwrap();
-        // compute the new leader of this level
-        // we use the confirmation policy from https://arxiv.org/abs/1810.08092
-        let mut new_leader: Option<H256> = None;
 
         // collect the depth of each vote on each proposer block
-        let mut votes_depth: HashMap<&H256, Vec<u64>> = HashMap::new(); // chain number and vote depth casted on the proposer block
+        let mut blocks: Vec<(H256, Vec<u64>)> = vec![];
 
         // collect the total votes on all proposer blocks, and the number of
         // voter blocks mined after those votes are casted
         let mut total_vote_count: u16 = 0;
         let mut total_vote_blocks: u64 = 0;
 
+        // read the votes before taking the lock, so that levels are computed in parallel
+        let mut block_votes: Vec<(H256, Vec<(u16, u64)>)> = vec![];
         for block in &proposer_blocks {
             let votes: Vec<(u16, u64)> = match get_value!(proposer_node_vote_cf, block) {
                 None => vec![],
                 Some(d) => d,
             };
+            block_votes.push((*block, votes));
+        }
+
+        let voter_levels = self.voter_levels.lock().unwrap();
+        for (block, votes) in &block_votes {
             let mut vote_depth: Vec<u64> = vec![];
-            for (chain_num, vote_level) in &votes {
-                // TODO: cache the voter chain best levels
-                let voter_best = self.voter_best[*chain_num as usize].lock().unwrap();
-                let voter_best_level = voter_best.1;
-                drop(voter_best);
-                total_vote_blocks += self
-                    .num_voter_blocks(*chain_num, *vote_level, voter_best_level)
-                    .unwrap();
+            for (chain_num, vote_level) in votes {
+                let voter_best_level = voter_levels.best_level(*chain_num);
+                total_vote_blocks +=
+                    voter_levels.num_blocks(*chain_num, *vote_level, voter_best_level);
                 total_vote_count += 1;
                 let this_depth = voter_best_level - vote_level + 1;
                 vote_depth.push(this_depth);
             }
-            votes_depth.insert(block, vote_depth);
+            blocks.push((*block, vote_depth));
         }
+        drop(voter_levels);
 
         // For debugging purpose only. This is very important for security.
         // TODO: remove this check in the future
@@ -716,97 +1134,54 @@ impl BlockChain {
             )
         }
 
-        // no point in going further if less than 3/5 votes are cast
-        if total_vote_count > self.config.voter_chains * 3 / 5 {
-            // calculate the average number of voter blocks mined after
-            // a vote is casted. we use this as an estimator of honest mining
-            // rate, and then derive the believed malicious mining rate
-            let avg_vote_blocks = total_vote_blocks as f32 / f32::from(total_vote_count);
-            // expected voter depth of an adversary
-            let adversary_expected_vote_depth =
-                avg_vote_blocks / (1.0 - self.config.adversary_ratio) * self.config.adversary_ratio;
-            let poisson = Poisson::new(f64::from(adversary_expected_vote_depth)).unwrap();
-
-            // for each block calculate the lower bound on the number of votes
-            let mut votes_lcb: HashMap<&H256, f32> = HashMap::new();
-            let mut total_votes_lcb: f32 = 0.0;
-            let mut max_vote_lcb: f32 = 0.0;
+        let proposer_best = self.proposer_best_level.lock().unwrap();
+        let proposer_best_level: u64 = *proposer_best;
+        drop(proposer_best);
+        let proposer_depth = proposer_best_level.saturating_sub(level);
+        let proposer_main_chain = if self.confirmation_policy.follows_proposer_chain() {
+            Some(self.proposer_main_chain_block(level)?)
+        } else {
+            None
+        };
 
-            for block in &proposer_blocks {
-                let votes = votes_depth.get(block).unwrap();
-
-                let mut block_votes_mean: f32 = 0.0; // mean E[X]
-                let mut block_votes_variance: f32 = 0.0; // Var[X]
-                let mut block_votes_lcb: f32 = 0.0;
-                for depth in votes.iter() {
-                    // probability that the adversary will remove this vote
-                    let mut p: f32 = 1.0 - poisson.cdf((*depth as f32 + 1.0).into()) as f32;
-                    for k in 0..(*depth as u64) {
-                        // probability that the adversary has mined k blocks
-                        let p1 = poisson.pmf(k) as f32;
-                        // probability that the adversary will overtake 'depth-k' blocks
-                        let p2 = (self.config.adversary_ratio
-                            / (1.0 - self.config.adversary_ratio))
-                            .powi((depth - k + 1) as i32);
-                        p += p1 * p2;
-                    }
-                    block_votes_mean += 1.0 - p;
-                    block_votes_variance += p * (1.0 - p);
-                }
-                // using gaussian approximation
-                let tmp = block_votes_mean
-                    - (block_votes_variance).sqrt() * quantile;
-                if tmp > 0.0 {
-                    block_votes_lcb += tmp;
-                }
-                votes_lcb.insert(block, block_votes_lcb);
-                total_votes_lcb += block_votes_lcb;
+        Ok(LevelVotes {
+            voter_chains: self.config.voter_chains,
+            blocks,
+            total_vote_blocks,
+            proposer_depth,
+            proposer_main_chain,
+        })
+    }
 
-                if max_vote_lcb < block_votes_lcb {
-                    max_vote_lcb = block_votes_lcb;
-                    new_leader = Some(*block);
-                }
-                // In case of a tie, choose block with lower hash.
-                if (max_vote_lcb - block_votes_lcb).abs() < std::f32::EPSILON
-                    && new_leader.is_some()
-                {
-                    // TODO: is_some required?
-                    if *block < new_leader.unwrap() {
-                        new_leader = Some(*block);
-                    }
-                }
-            }
-            // check if the lcb_vote of new_leader is bigger than second best ucb votes
-            let remaining_votes = f32::from(self.config.voter_chains) - total_votes_lcb;
+    /// Get the block of the given level on the longest proposer chain.
+    fn proposer_main_chain_block(&self, level: u64) -> Result<H256> {
+        let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
+        let parent_neighbor_cf = self.db.cf_handle(PARENT_NEIGHBOR_CF).unwrap();
 
-            // if max_vote_lcb is lesser than the remaining_votes, then a private block could
-            // get the remaining votes and become the leader block
-            if max_vote_lcb <= remaining_votes || new_leader.is_none() {
-                new_leader = None;
-            } else {
-                for p_block in &proposer_blocks {
-                    // if the below condition is true, then final votes on p_block could overtake new_leader
-                    if max_vote_lcb < votes_lcb.get(p_block).unwrap() + remaining_votes
-                        && *p_block != new_leader.unwrap()
-                    {
-                        new_leader = None;
-                        break;
-                    }
-                    //In case of a tie, choose block with lower hash.
-                    if (max_vote_lcb - (votes_lcb.get(p_block).unwrap() + remaining_votes)).abs()
-                        < std::f32::EPSILON
-                        && *p_block < new_leader.unwrap()
-                    {
-                        new_leader = None;
-                        break;
-                    }
-                }
-            }
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
+            }};
         }
 
-        Ok(new_leader)
+        let mut block = self.best_proposer()?;
+        let mut block_level: u64 = get_value!(proposer_node_level_cf, block);
+        while block_level > level {
+            block = get_value!(parent_neighbor_cf, block);
+            block_level -= 1;
+        }
+        Ok(block)
     }
 
+    /// Count the voter blocks between the given levels from the database. Only used to check
+    /// the in-memory voter levels.
+    #[cfg(test)]
     fn num_voter_blocks(&self, chain: u16, start_level: u64, end_level: u64) -> Result<u64> {
         let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
         let mut total: u64 = 0;
@@ -936,6 +1311,17 @@ impl BlockChain {
     /// Get the list of unvoted proposer blocks that a voter chain should vote for, given the tip
     /// of the particular voter chain.
     pub fn unvoted_proposer(&self, tip: &H256, proposer_parent: &H256) -> Result<Vec<H256>> {
+        self.unvoted_proposer_by_votes(tip, proposer_parent, true)
+    }
+
+    /// Get the list of unvoted proposer blocks with the fewest votes on each level, given the tip
+    /// of the particular voter chain. This is what an adversary votes for to balance the votes on
+    /// competing proposer blocks.
+    pub fn least_voted_proposer(&self, tip: &H256, proposer_parent: &H256) -> Result<Vec<H256>> {
+        self.unvoted_proposer_by_votes(tip, proposer_parent, false)
+    }
+
+    fn unvoted_proposer_by_votes(&self, tip: &H256, proposer_parent: &H256, most_votes: bool) -> Result<Vec<H256>> {
         let voter_node_voted_level_cf = self.db.cf_handle(VOTER_NODE_VOTED_LEVEL_CF).unwrap();
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
         let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
@@ -957,7 +1343,7 @@ impl BlockChain {
         )
             .unwrap();
 
-        // get the block with the most votes on each proposer level
+        // get the block with the most (or fewest) votes on each proposer level
         // and break ties with hash value
         let mut list: Vec<H256> = vec![];
         for level in first_vote_level + 1..=last_vote_level {
@@ -981,7 +1367,9 @@ impl BlockChain {
                     };
                 match best_vote {
                     Some((_, num_votes)) => {
-                        if vote_count > num_votes {
+                        if (most_votes && vote_count > num_votes)
+                            || (!most_votes && vote_count < num_votes)
+                        {
                             best_vote = Some((*block_hash, vote_count));
                         }
                     }
@@ -995,6 +1383,184 @@ impl BlockChain {
         Ok(list)
     }
 
+    /// Check whether a transaction block is fast confirmed or in the ledger.
+    pub fn is_fast_confirmed(&self, hash: &H256) -> Result<bool> {
+        let fast_confirmed_transactions = self.fast_confirmed_transactions.lock().unwrap();
+        if fast_confirmed_transactions.contains_key(hash) {
+            return Ok(true);
+        }
+        drop(fast_confirmed_transactions);
+        let unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap();
+        self.in_ledger(hash, &unconfirmed_proposers)
+    }
+
+    /// Check whether a transaction block is referred to by a proposer block in the ledger.
+    fn in_ledger(&self, hash: &H256, unconfirmed_proposers: &HashSet<H256>) -> Result<bool> {
+        let transaction_referrer_neighbor_cf =
+            self.db.cf_handle(TRANSACTION_REFERRER_NEIGHBOR_CF).unwrap();
+        let referrers: Vec<H256> = match self
+            .db
+            .get_pinned_cf(transaction_referrer_neighbor_cf, serialize(hash).unwrap())?
+        {
+            Some(raw) => deserialize(&raw).unwrap(),
+            None => vec![],
+        };
+        Ok(referrers.iter().any(|r| !unconfirmed_proposers.contains(r)))
+    }
+
+    /// Get the confidence that a transaction block stays in the ledger. The hash may be of a
+    /// transaction block, or of a transaction, in which case we report every transaction block
+    /// including it. The reversal probability and the confirmation follow the configured
+    /// confirmation rule, while the vote bounds reported are always the lower confidence bound.
+    pub fn transaction_confidence(&self, hash: &H256) -> Result<Vec<TransactionBlockConfidence>> {
+        let transaction_block_cf = self.db.cf_handle(TRANSACTION_BLOCK_CF).unwrap();
+        let transaction_referrer_neighbor_cf =
+            self.db.cf_handle(TRANSACTION_REFERRER_NEIGHBOR_CF).unwrap();
+        let proposer_ledger_level_cf = self.db.cf_handle(PROPOSER_LEDGER_LEVEL_CF).unwrap();
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                }
+            }};
+        }
+
+        let transaction_blocks: Vec<H256> = match get_value!(transaction_block_cf, hash) {
+            Some(blocks) => blocks,
+            None => {
+                if self.contains_transaction(hash)?
+                    && !self.contains_proposer(hash)?
+                    && !self.contains_voter(hash)?
+                {
+                    vec![*hash]
+                } else {
+                    vec![]
+                }
+            }
+        };
+
+        let policy = &self.confirmation_policy;
+        let lcb = LcbPolicy::new(&self.config);
+        let pruned_level: u64 = *self.pruned_level.lock().unwrap();
+
+        let mut confidence: Vec<TransactionBlockConfidence> = vec![];
+        for transaction_block in transaction_blocks {
+            let referrers: Vec<H256> =
+                get_value!(transaction_referrer_neighbor_cf, transaction_block).unwrap_or_default();
+            let mut proposers: Vec<ProposerConfidence> = vec![];
+            for proposer in referrers {
+                let level = self.proposer_level(&proposer)?;
+                let votes = self.level_votes(level)?;
+                let vote_depths: Vec<u64> = votes
+                    .blocks
+                    .iter()
+                    .find(|(b, _)| *b == proposer)
+                    .map(|(_, d)| d.clone())
+                    .unwrap_or_default();
+                let (votes_lcb, remaining_votes) =
+                    match lcb.votes_lcb(&votes, lcb.quantile_confirm) {
+                        Some(lcb) => (
+                            lcb.iter()
+                                .find(|(b, _)| *b == proposer)
+                                .map(|(_, v)| *v)
+                                .unwrap_or(0.0),
+                            f32::from(self.config.voter_chains)
+                                - lcb.iter().map(|(_, v)| v).sum::<f32>(),
+                        ),
+                        None => (0.0, f32::from(self.config.voter_chains)),
+                    };
+
+                // a proposer block enters the ledger at its own level or a later one, which is
+                // recorded with it
+                let in_ledger = !self.unconfirmed_proposers.lock().unwrap().contains(&proposer);
+                let ledger_level: Option<u64> = if in_ledger {
+                    get_value!(proposer_ledger_level_cf, proposer)
+                } else {
+                    None
+                };
+                // once in the ledger, the proposer block stays as long as the leader that
+                // brought it in does
+                let leader: Option<H256> = match ledger_level {
+                    Some(l) => get_value!(proposer_leader_sequence_cf, l),
+                    None => None,
+                };
+                let (epsilon, confirmed) = match (ledger_level, leader) {
+                    // pruned levels are final, and their votes are gone
+                    (Some(l), _) if l <= pruned_level => (0.0, true),
+                    (Some(l), Some(leader)) => {
+                        (policy.reversal_probability(&self.level_votes(l)?, &leader), true)
+                    }
+                    // the same threshold the ledger uses to confirm a new leader
+                    _ => (
+                        policy.reversal_probability(&votes, &proposer),
+                        policy.leader(&votes, true) == Some(proposer),
+                    ),
+                };
+                proposers.push(ProposerConfidence {
+                    hash: proposer,
+                    level,
+                    vote_depths,
+                    votes_lcb,
+                    remaining_votes,
+                    ledger_level,
+                    epsilon,
+                    confirmed,
+                });
+            }
+            let epsilon = proposers.iter().map(|p| p.epsilon).fold(1.0, f64::min);
+            let confirmed = proposers.iter().any(|p| p.confirmed);
+            confidence.push(TransactionBlockConfidence {
+                hash: transaction_block,
+                proposers,
+                epsilon,
+                confirmed,
+            });
+        }
+        Ok(confidence)
+    }
+
+    /// Get the proposer blocks at the given level
+    pub fn proposers_at_level(&self, level: u64) -> Result<Vec<H256>> {
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+        let blocks: Vec<H256> = match self
+            .db
+            .get_pinned_cf(proposer_tree_level_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => deserialize(&d).unwrap(),
+            None => vec![],
+        };
+        Ok(blocks)
+    }
+
+    /// Get the blocks to sync the blockchain above the given proposer level from: the proposer
+    /// blocks above the level, the transaction blocks they refer to, and the best block of each
+    /// voter chain. The rest of the proposer and voter chains is fetched through the parents of
+    /// these blocks.
+    pub fn blocks_above(&self, level: u64) -> Result<Vec<H256>> {
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let best_level: u64 = *self.proposer_best_level.lock().unwrap();
+        let mut blocks = vec![];
+        for level in level + 1..=best_level {
+            for hash in self.proposers_at_level(level)? {
+                if let Some(refs) = self
+                    .db
+                    .get_pinned_cf(transaction_ref_neighbor_cf, serialize(&hash).unwrap())?
+                {
+                    let refs: Vec<H256> = deserialize(&refs).unwrap();
+                    blocks.extend(refs);
+                }
+                blocks.push(hash);
+            }
+        }
+        for chain_num in 0..self.config.voter_chains {
+            blocks.push(self.best_voter(chain_num as usize));
+        }
+        Ok(blocks)
+    }
+
     /// Get the level of the proposer block
     pub fn proposer_level(&self, hash: &H256) -> Result<u64> {
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
@@ -1072,6 +1638,22 @@ impl BlockChain {
             }
     }
 
+    /// Get the leader of the given level of the ledger, or None if the level is beyond the
+    /// ledger tip.
+    pub fn ledger_leader(&self, level: u64) -> Result<Option<H256>> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        if level > *self.proposer_ledger_tip.lock().unwrap() {
+            return Ok(None);
+        }
+        match self
+            .db
+            .get_pinned_cf(proposer_leader_sequence_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
     pub fn proposer_leaders(&self) -> Result<Vec<H256>> {
         let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
@@ -1093,6 +1675,11 @@ impl BlockChain {
 }
 
 impl BlockChain {
+    /// The consensus parameters of the blockchain.
+    pub fn config(&self) -> &BlockchainConfig {
+        &self.config
+    }
+
     pub fn lagging_level(&self) -> u64 {
         let proposer_best_level = self.proposer_best_level.lock().unwrap();
         let tip = self.proposer_ledger_tip.lock().unwrap();
@@ -1145,6 +1732,36 @@ impl BlockChain {
         Ok(ledger)
     }
 
+    /// Get the voter blocks on the voter main chains that vote for the given proposer block.
+    pub fn main_chain_voters(&self, hash: &H256) -> Result<Vec<H256>> {
+        let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let proposer_voter_neighbor_cf = self.db.cf_handle(PROPOSER_VOTER_NEIGHBOR_CF).unwrap();
+        let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let voter_node_chain_cf = self.db.cf_handle(VOTER_NODE_CHAIN_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
+                }
+            }};
+        }
+
+        // a voter block is on the main chain iff its (chain, level) is a main chain vote
+        let votes: Vec<(u16, u64)> = get_value!(proposer_node_vote_cf, hash).unwrap_or_default();
+        let voters: Vec<H256> = get_value!(proposer_voter_neighbor_cf, hash).unwrap_or_default();
+        let mut main_chain_voters: Vec<H256> = vec![];
+        for voter in voters {
+            let chain: u16 = get_value!(voter_node_chain_cf, voter).unwrap();
+            let level: u64 = get_value!(voter_node_level_cf, voter).unwrap();
+            if votes.contains(&(chain, level)) {
+                main_chain_voters.push(voter);
+            }
+        }
+        Ok(main_chain_voters)
+    }
+
     pub fn proposer_bottom_tip(&self) -> Result<(H256, H256, u64)> {
         let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
         let proposer_bottom = match self
@@ -1699,6 +2316,7 @@ fn u64_plus_merge(
 mod tests {
     use super::*;
     use crate::block::{proposer, transaction, voter, Block, Content};
+    use crate::config::ConfirmationRule;
     use crate::crypto::hash::H256;
     use crate::config::BlockchainConfig;
     use crate::block::tests::{proposer_block as get_proposer_block, voter_block as get_voter_block, transaction_block as get_transaction_block};
@@ -1896,6 +2514,44 @@ mod tests {
         db.insert_block(&new_proposer_block_2).unwrap();
         assert_eq!(db.unreferred_transactions(), vec![]);
         assert_eq!(db.unreferred_proposers(), vec![new_proposer_block_2.hash()]);
+
+        // a transaction block arriving after a proposer block referring to it is not unreferred
+        let late_transaction_block = get_transaction_block(config.proposer_genesis, 1, vec![]);
+        let new_proposer_block_3 = get_proposer_block(
+            new_proposer_block_2.hash(),
+            0,
+            vec![],
+            vec![late_transaction_block.hash()],
+        );
+        db.insert_block(&new_proposer_block_3).unwrap();
+        db.insert_block(&late_transaction_block).unwrap();
+        assert_eq!(db.unreferred_transactions(), vec![]);
+    }
+
+    #[test]
+    fn blocks_above() {
+        const NUM_VOTER_CHAINS: u16 = 1000;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.4,20.0);
+        let db =
+            BlockChain::new("/tmp/prism_test_blockchain_blocks_above.rocksdb", config.clone()).unwrap();
+
+        let transaction_block = get_transaction_block(config.proposer_genesis, 0, vec![]);
+        db.insert_block(&transaction_block).unwrap();
+        let proposer_block_1 = get_proposer_block(config.proposer_genesis, 0, vec![], vec![]);
+        db.insert_block(&proposer_block_1).unwrap();
+        let proposer_block_2 = get_proposer_block(
+            proposer_block_1.hash(),
+            0,
+            vec![],
+            vec![transaction_block.hash()],
+        );
+        db.insert_block(&proposer_block_2).unwrap();
+
+        let blocks = db.blocks_above(1).unwrap();
+        assert_eq!(blocks.len(), 2 + NUM_VOTER_CHAINS as usize);
+        assert_eq!(blocks[..2], [transaction_block.hash(), proposer_block_2.hash()]);
+        assert_eq!(blocks[2..], config.voter_genesis[..]);
+        assert_eq!(db.blocks_above(2).unwrap()[..], config.voter_genesis[..]);
     }
 
     #[test]
@@ -1942,6 +2598,457 @@ mod tests {
         );
     }
 
+    #[test]
+    fn least_voted_proposer() {
+        const NUM_VOTER_CHAINS: u16 = 1000;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.4,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_least_voted_proposer.rocksdb", config.clone()).unwrap();
+
+        let new_proposer_block_1 = get_proposer_block(config.proposer_genesis, 0, vec![], vec![]);
+        db.insert_block(&new_proposer_block_1).unwrap();
+        let new_proposer_block_2 = get_proposer_block(config.proposer_genesis, 0, vec![], vec![]);
+        db.insert_block(&new_proposer_block_2).unwrap();
+        assert_eq!(db.proposers_at_level(1).unwrap().len(), 2);
+
+        // vote for one of them on chain 0
+        let voted = std::cmp::min(new_proposer_block_1.hash(), new_proposer_block_2.hash());
+        let not_voted = std::cmp::max(new_proposer_block_1.hash(), new_proposer_block_2.hash());
+        let new_voter_block = get_voter_block(
+            new_proposer_block_1.hash(),
+            0,
+            0,
+            config.voter_genesis[0],
+            vec![voted],
+        );
+        db.insert_block(&new_voter_block).unwrap();
+
+        // honest voters on other chains follow the votes, adversarial ones balance them
+        let best = db.best_proposer().unwrap();
+        assert_eq!(db.unvoted_proposer(&config.voter_genesis[1], &best).unwrap(), vec![voted]);
+        assert_eq!(db.least_voted_proposer(&config.voter_genesis[1], &best).unwrap(), vec![not_voted]);
+    }
+
+    #[test]
+    fn main_chain_voters() {
+        const NUM_VOTER_CHAINS: u16 = 1000;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.4,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_main_chain_voters.rocksdb", config.clone()).unwrap();
+
+        let new_proposer_block = get_proposer_block(config.proposer_genesis, 0, vec![], vec![]);
+        db.insert_block(&new_proposer_block).unwrap();
+
+        // two competing voter blocks on chain 0, the first one stays on the main chain
+        let main_voter_block = get_voter_block(
+            new_proposer_block.hash(),
+            0,
+            0,
+            config.voter_genesis[0],
+            vec![new_proposer_block.hash()],
+        );
+        db.insert_block(&main_voter_block).unwrap();
+        let fork_voter_block = get_voter_block(
+            new_proposer_block.hash(),
+            1,
+            0,
+            config.voter_genesis[0],
+            vec![new_proposer_block.hash()],
+        );
+        db.insert_block(&fork_voter_block).unwrap();
+
+        // votes are only counted on the main chain after the ledger is updated
+        assert_eq!(db.main_chain_voters(&new_proposer_block.hash()).unwrap(), vec![]);
+        db.update_ledger().unwrap();
+        assert_eq!(
+            db.main_chain_voters(&new_proposer_block.hash()).unwrap(),
+            vec![main_voter_block.hash()]
+        );
+    }
+
+    #[test]
+    fn fast_confirmation() {
+        const NUM_VOTER_CHAINS: u16 = 10;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.1,20.0)
+            .with_fast_confirm(true);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_fast_confirmation.rocksdb", config.clone()).unwrap();
+
+        // two competing proposer blocks, both referring to the first transaction block
+        let tx_block_1 = get_transaction_block(config.proposer_genesis, 0, vec![]);
+        db.insert_block(&tx_block_1).unwrap();
+        let tx_block_2 = get_transaction_block(config.proposer_genesis, 1, vec![]);
+        db.insert_block(&tx_block_2).unwrap();
+        let proposer_block_1 = get_proposer_block(
+            config.proposer_genesis,
+            2,
+            vec![],
+            vec![tx_block_1.hash(), tx_block_2.hash()],
+        );
+        db.insert_block(&proposer_block_1).unwrap();
+        let proposer_block_2 = get_proposer_block(
+            config.proposer_genesis,
+            3,
+            vec![],
+            vec![tx_block_1.hash()],
+        );
+        db.insert_block(&proposer_block_2).unwrap();
+
+        // 4 chains vote for the first proposer block, 3 for the second, and the votes get deep
+        for chain in 0..7u16 {
+            let voted = if chain < 4 { &proposer_block_1 } else { &proposer_block_2 };
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { vec![voted.hash()] } else { vec![] };
+                let voter_block = get_voter_block(voted.hash(), depth, chain, voter_parent, votes);
+                db.insert_block(&voter_block).unwrap();
+                voter_parent = voter_block.hash();
+            }
+        }
+
+        // neither block is the leader yet, but one of them must be
+        let (added, _) = db.update_ledger().unwrap();
+        assert!(added.is_empty());
+        assert_eq!(db.proposer_leaders().unwrap(), vec![config.proposer_genesis]);
+        let mut list = db.proposer_leader_list(1).unwrap().unwrap();
+        list.sort();
+        let mut expected = vec![proposer_block_1.hash(), proposer_block_2.hash()];
+        expected.sort();
+        assert_eq!(list, expected);
+
+        // only the transaction block referred to by both proposer blocks is fast confirmed
+        assert_eq!(db.update_fast_ledger().unwrap(), vec![tx_block_1.hash()]);
+        assert!(db.is_fast_confirmed(&tx_block_1.hash()).unwrap());
+        assert!(!db.is_fast_confirmed(&tx_block_2.hash()).unwrap());
+        // and only once
+        assert!(db.update_fast_ledger().unwrap().is_empty());
+
+        // the remaining chains vote for the first proposer block, which enters the ledger
+        for chain in 7..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { vec![proposer_block_1.hash()] } else { vec![] };
+                let voter_block =
+                    get_voter_block(proposer_block_1.hash(), depth, chain, voter_parent, votes);
+                db.insert_block(&voter_block).unwrap();
+                voter_parent = voter_block.hash();
+            }
+        }
+        let (added, _) = db.update_ledger().unwrap();
+        assert_eq!(added[0].0, proposer_block_1.hash());
+        // the ledger has passed the level, so the block is no longer kept aside
+        assert!(db.fast_confirmed_transactions.lock().unwrap().is_empty());
+        assert!(db.is_fast_confirmed(&tx_block_1.hash()).unwrap());
+        assert!(db.is_fast_confirmed(&tx_block_2.hash()).unwrap());
+        assert!(db.update_fast_ledger().unwrap().is_empty());
+    }
+
+    #[test]
+    fn transaction_confidence() {
+        const NUM_VOTER_CHAINS: u16 = 10;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.1,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_transaction_confidence.rocksdb", config.clone()).unwrap();
+
+        let tx_block = get_transaction_block(config.proposer_genesis, 0, vec![]);
+        db.insert_block(&tx_block).unwrap();
+        let unreferred_tx_block = get_transaction_block(config.proposer_genesis, 1, vec![]);
+        db.insert_block(&unreferred_tx_block).unwrap();
+        let proposer_block = get_proposer_block(config.proposer_genesis, 2, vec![], vec![tx_block.hash()]);
+        db.insert_block(&proposer_block).unwrap();
+
+        // before any vote, nothing is confirmed
+        let confidence = db.transaction_confidence(&tx_block.hash()).unwrap();
+        assert_eq!(confidence.len(), 1);
+        assert_eq!(confidence[0].proposers.len(), 1);
+        assert_eq!(confidence[0].proposers[0].hash, proposer_block.hash());
+        assert_eq!(confidence[0].epsilon, 1.0);
+        assert!(!confidence[0].confirmed);
+
+        // 7 chains vote for the proposer block and the votes get deep
+        for chain in 0..7u16 {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { vec![proposer_block.hash()] } else { vec![] };
+                let voter_block = get_voter_block(proposer_block.hash(), depth, chain, voter_parent, votes);
+                db.insert_block(&voter_block).unwrap();
+                voter_parent = voter_block.hash();
+            }
+        }
+        db.update_ledger().unwrap();
+
+        let confidence = db.transaction_confidence(&tx_block.hash()).unwrap();
+        let proposer = &confidence[0].proposers[0];
+        assert_eq!(proposer.level, 1);
+        assert_eq!(proposer.vote_depths, vec![11; 7]);
+        assert_eq!(proposer.ledger_level, Some(1));
+        assert!(proposer.votes_lcb > proposer.remaining_votes);
+        assert!(confidence[0].epsilon < (-20.0f64).exp());
+        assert!(confidence[0].confirmed);
+
+        let confidence = db.transaction_confidence(&unreferred_tx_block.hash()).unwrap();
+        assert!(confidence[0].proposers.is_empty());
+        assert!(!confidence[0].confirmed);
+        assert!(db.transaction_confidence(&proposer_block.hash()).unwrap().is_empty());
+
+        // the same votes are not deep enough for a stricter k-deep rule
+        let config = config.with_confirmation_rule(ConfirmationRule::KDeep(12));
+        let db = BlockChain::new("/tmp/prism_test_blockchain_transaction_confidence_k_deep.rocksdb", config.clone()).unwrap();
+        db.insert_block(&tx_block).unwrap();
+        db.insert_block(&proposer_block).unwrap();
+        for chain in 0..7u16 {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { vec![proposer_block.hash()] } else { vec![] };
+                let voter_block = get_voter_block(proposer_block.hash(), depth, chain, voter_parent, votes);
+                db.insert_block(&voter_block).unwrap();
+                voter_parent = voter_block.hash();
+            }
+        }
+        db.update_ledger().unwrap();
+        let confidence = db.transaction_confidence(&tx_block.hash()).unwrap();
+        assert_eq!(confidence[0].proposers[0].ledger_level, None);
+        assert_eq!(confidence[0].epsilon, 1.0);
+        assert!(!confidence[0].confirmed);
+    }
+
+    #[test]
+    fn recompute_leaders_and_tip_update() {
+        const NUM_VOTER_CHAINS: u16 = 10;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.1,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_recompute_leaders.rocksdb", config.clone()).unwrap();
+
+        let mut proposer_parent = config.proposer_genesis;
+        let mut proposers = vec![];
+        for ts in 0..9 {
+            let block = get_proposer_block(proposer_parent, ts, vec![], vec![]);
+            db.insert_block(&block).unwrap();
+            proposer_parent = block.hash();
+            proposers.push(block.hash());
+        }
+        // every chain votes for all levels, and the votes get deep
+        for chain in 0..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..11 {
+                let votes = if depth == 0 { proposers.clone() } else { vec![] };
+                let block = get_voter_block(proposer_parent, depth, chain, voter_parent, votes);
+                db.insert_block(&block).unwrap();
+                voter_parent = block.hash();
+            }
+        }
+        // the new voter blocks were announced
+        db.wait_for_tip_update();
+        assert!(!*db.tip_updated.lock().unwrap());
+
+        let (added, _) = db.update_ledger().unwrap();
+        assert_eq!(added.len(), proposers.len());
+        let levels: Vec<u64> = (1..=proposers.len() as u64).collect();
+        let leaders = db.recompute_leaders(&levels).unwrap();
+        for (level, (existing_leader, new_leader)) in levels.iter().zip(leaders) {
+            assert_eq!(existing_leader, Some(proposers[*level as usize - 1]));
+            assert_eq!(new_leader, db.proposer_leader(*level, false).unwrap());
+            assert_eq!(new_leader, existing_leader);
+        }
+    }
+
+    #[test]
+    fn prune() {
+        const NUM_VOTER_CHAINS: u16 = 10;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.1,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_prune.rocksdb", config.clone()).unwrap();
+
+        // three proposer levels, each referring to a transaction block
+        let mut proposer_parent = config.proposer_genesis;
+        let mut proposers = vec![];
+        let mut tx_blocks = vec![];
+        for ts in 0..3 {
+            let tx_block = get_transaction_block(proposer_parent, ts, vec![]);
+            db.insert_block(&tx_block).unwrap();
+            let block = get_proposer_block(proposer_parent, ts, vec![], vec![tx_block.hash()]);
+            db.insert_block(&block).unwrap();
+            proposer_parent = block.hash();
+            proposers.push(block.hash());
+            tx_blocks.push(tx_block.hash());
+        }
+        // every chain votes for one level per block, and the votes get deep
+        let mut voters: Vec<Vec<H256>> = vec![];
+        for chain in 0..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            let mut chain_voters = vec![];
+            for depth in 0..13 {
+                let (parent, votes) = if depth < 3 {
+                    (proposers[depth], vec![proposers[depth]])
+                } else {
+                    (proposer_parent, vec![])
+                };
+                let block = get_voter_block(parent, depth as u128, chain, voter_parent, votes);
+                db.insert_block(&block).unwrap();
+                voter_parent = block.hash();
+                chain_voters.push(block.hash());
+            }
+            voters.push(chain_voters);
+        }
+        // a proposer block left out of the ledger on level 1, and a fork of voter chain 0
+        let fork_tx_block = get_transaction_block(config.proposer_genesis, 10, vec![]);
+        db.insert_block(&fork_tx_block).unwrap();
+        let fork_proposer =
+            get_proposer_block(config.proposer_genesis, 10, vec![], vec![fork_tx_block.hash()]);
+        db.insert_block(&fork_proposer).unwrap();
+        let fork_voter =
+            get_voter_block(proposers[0], 10, 0, config.voter_genesis[0], vec![proposers[0]]);
+        db.insert_block(&fork_voter).unwrap();
+        let (added, _) = db.update_ledger().unwrap();
+        assert_eq!(added.len(), 3);
+
+        // levels 1 and 2 are pruned
+        assert_eq!(
+            db.prune(1).unwrap(),
+            vec![tx_blocks[0], fork_tx_block.hash(), tx_blocks[1]]
+        );
+        assert_eq!(*db.pruned_level.lock().unwrap(), 2);
+        assert!(db.prune(1).unwrap().is_empty());
+        let proposer_node_vote_cf = db.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let vote_neighbor_cf = db.db.cf_handle(VOTE_NEIGHBOR_CF).unwrap();
+        let voter_tree_level_count_cf = db.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
+        macro_rules! exists {
+            ($cf:expr, $key:expr) => {{
+                db.db.get_pinned_cf($cf, serialize(&$key).unwrap()).unwrap().is_some()
+            }};
+        }
+        assert!(!exists!(proposer_node_vote_cf, proposers[0]));
+        assert!(!exists!(proposer_node_vote_cf, proposers[1]));
+        assert!(exists!(proposer_node_vote_cf, proposers[2]));
+        for chain in 0..NUM_VOTER_CHAINS {
+            // the voter block at level 2 votes on the last pruned level, and is kept
+            assert!(!exists!(vote_neighbor_cf, voters[chain as usize][0]));
+            assert!(exists!(vote_neighbor_cf, voters[chain as usize][1]));
+            assert!(!exists!(voter_tree_level_count_cf, (chain, 1 as u64)));
+            assert!(exists!(voter_tree_level_count_cf, (chain, 2 as u64)));
+            assert_eq!(db.voter_levels.lock().unwrap().base_level(chain), 2);
+        }
+        assert!(!exists!(vote_neighbor_cf, fork_voter.hash()));
+
+        // the level that is kept still has its leader, and new voter blocks are counted
+        let block = get_voter_block(proposer_parent, 13, 0, voters[0][12], vec![]);
+        db.insert_block(&block).unwrap();
+        db.update_ledger().unwrap();
+        assert_eq!(db.proposer_leader(3, false).unwrap(), Some(proposers[2]));
+        assert_eq!(db.level_votes(3).unwrap().total_vote_blocks, 10 * 11 + 1);
+
+        // transaction blocks on pruned levels stay confirmed without their votes
+        let confidence = db.transaction_confidence(&tx_blocks[0]).unwrap();
+        assert_eq!(confidence[0].proposers[0].ledger_level, Some(1));
+        assert_eq!(confidence[0].epsilon, 0.0);
+        assert!(confidence[0].confirmed);
+        assert!(db.transaction_confidence(&tx_blocks[2]).unwrap()[0].confirmed);
+    }
+
+    #[test]
+    fn voter_levels() {
+        const NUM_VOTER_CHAINS: u16 = 3;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.4,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_voter_levels.rocksdb", config.clone()).unwrap();
+
+        // chain 0 grows 5 blocks, chain 1 forks at level 1 and grows 3 blocks on the fork
+        let mut voter_parent = config.voter_genesis[0];
+        for ts in 0..5 {
+            let block = get_voter_block(config.proposer_genesis, ts, 0, voter_parent, vec![]);
+            db.insert_block(&block).unwrap();
+            voter_parent = block.hash();
+        }
+        let fork_point = config.voter_genesis[1];
+        db.insert_block(&get_voter_block(config.proposer_genesis, 10, 1, fork_point, vec![]))
+            .unwrap();
+        let mut voter_parent = fork_point;
+        for ts in 11..14 {
+            let block = get_voter_block(config.proposer_genesis, ts, 1, voter_parent, vec![]);
+            db.insert_block(&block).unwrap();
+            voter_parent = block.hash();
+        }
+
+        let voter_levels = db.voter_levels.lock().unwrap();
+        for chain in 0..NUM_VOTER_CHAINS {
+            let best_level = db.voter_best[chain as usize].lock().unwrap().1;
+            assert_eq!(voter_levels.best_level(chain), best_level);
+            for start in 0..=best_level {
+                assert_eq!(
+                    voter_levels.num_blocks(chain, start, best_level),
+                    db.num_voter_blocks(chain, start, best_level).unwrap()
+                );
+            }
+        }
+        assert_eq!(voter_levels.best_level(1), 3);
+        assert_eq!(voter_levels.num_blocks(1, 1, 3), 4);
+    }
+
+    /// Compare leader computation against counting the voter blocks from the database. Run with
+    /// `cargo test --release leader_computation_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn leader_computation_benchmark() {
+        use std::time::Instant;
+
+        const NUM_VOTER_CHAINS: u16 = 1000;
+        const NUM_LEVELS: u64 = 10;
+        const VOTE_DEPTH: u64 = 50;
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,168,70000,0.1,0.1,0.4,20.0);
+        let db = BlockChain::new("/tmp/prism_test_blockchain_leader_computation_benchmark.rocksdb", config.clone()).unwrap();
+
+        let mut proposer_parent = config.proposer_genesis;
+        let mut proposers = vec![];
+        for ts in 0..NUM_LEVELS {
+            let block = get_proposer_block(proposer_parent, ts as u128, vec![], vec![]);
+            db.insert_block(&block).unwrap();
+            proposer_parent = block.hash();
+            proposers.push(block.hash());
+        }
+        // every chain votes for all levels at once, and then grows VOTE_DEPTH blocks deep
+        for chain in 0..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..VOTE_DEPTH {
+                let votes = if depth == 0 { proposers.clone() } else { vec![] };
+                let block = get_voter_block(proposer_parent, depth as u128, chain, voter_parent, votes);
+                db.insert_block(&block).unwrap();
+                voter_parent = block.hash();
+            }
+        }
+        db.update_ledger().unwrap();
+
+        let proposer_node_vote_cf = db.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let start = Instant::now();
+        let mut uncached_blocks: u64 = 0;
+        for block in &proposers {
+            let votes: Vec<(u16, u64)> = deserialize(
+                &db.db
+                    .get_pinned_cf(proposer_node_vote_cf, serialize(block).unwrap())
+                    .unwrap()
+                    .unwrap(),
+            )
+                .unwrap();
+            for (chain_num, vote_level) in &votes {
+                let voter_best_level = db.voter_best[*chain_num as usize].lock().unwrap().1;
+                uncached_blocks += db
+                    .num_voter_blocks(*chain_num, *vote_level, voter_best_level)
+                    .unwrap();
+            }
+        }
+        let uncached = start.elapsed();
+
+        let start = Instant::now();
+        let mut cached_blocks: u64 = 0;
+        for level in 1..=NUM_LEVELS {
+            cached_blocks += db.level_votes(level).unwrap().total_vote_blocks;
+        }
+        let cached = start.elapsed();
+
+        assert_eq!(cached_blocks, uncached_blocks);
+        println!(
+            "{} levels, {} voter chains, votes {} deep: database {:?}, cached {:?} ({:.1}x)",
+            NUM_LEVELS,
+            NUM_VOTER_CHAINS,
+            VOTE_DEPTH,
+            uncached,
+            cached,
+            uncached.as_secs_f64() / cached.as_secs_f64()
+        );
+    }
+
     #[test]
     fn merge_operator_h256_vec() {
         const NUM_VOTER_CHAINS: u16 = 1000;
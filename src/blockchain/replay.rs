@@ -0,0 +1,163 @@
+use super::*;
+use crate::blockdb::BlockDatabase;
+use crate::network::buffer::BlockBuffer;
+use crate::validation::{self, BlockResult};
+use log::info;
+
+/// Number of blocks read from the block database at a time.
+const REPLAY_BATCH: u64 = 1024;
+
+/// Statistics of replaying the block database.
+pub struct ReplayReport {
+    /// Number of blocks inserted into the blockchain.
+    pub replayed_blocks: usize,
+    /// Number of blocks in the block database failing validation.
+    pub invalid_blocks: usize,
+    /// Number of blocks whose parent or references never showed up.
+    pub unresolved_blocks: usize,
+}
+
+impl BlockChain {
+    /// Rebuild the blockchain database at the given path from the blocks in `blockdb`, for
+    /// example to recompute the ledger with different confirmation parameters. The ledger is
+    /// left to the next call to `update_ledger`. Blocks pruned from `blockdb` cannot be replayed,
+    /// so the block database should come from an archive node.
+    pub fn reindex<P: AsRef<std::path::Path>>(
+        path: P,
+        blockdb: &BlockDatabase,
+        config: BlockchainConfig,
+    ) -> Result<(Self, ReplayReport)> {
+        let chain = Self::new(path, config)?;
+        let report = chain.replay(blockdb, |_| Ok(false))?;
+        info!(
+            "Reindexed {} blocks, {} invalid and {} with missing references ignored",
+            report.replayed_blocks, report.invalid_blocks, report.unresolved_blocks
+        );
+        Ok((chain, report))
+    }
+
+    /// Insert the blocks of `blockdb` in arrival order, buffering those whose references come
+    /// later. Blocks are validated the way the network worker does, except those for which
+    /// `validated` returns true.
+    pub fn replay<F>(&self, blockdb: &BlockDatabase, validated: F) -> Result<ReplayReport>
+    where
+        F: Fn(&H256) -> Result<bool>,
+    {
+        let mut buffer = BlockBuffer::new();
+        let mut num_blocks: usize = 0;
+        let mut replayed_blocks: usize = 0;
+        let mut invalid_blocks: usize = 0;
+        for batch in blockdb.blocks_after(&self.config.proposer_genesis, REPLAY_BATCH) {
+            for block in batch {
+                if self.config.voter_genesis.contains(&block.hash()) {
+                    continue;
+                }
+                num_blocks += 1;
+                let mut to_process: Vec<Block> = vec![block];
+                while let Some(block) = to_process.pop() {
+                    let hash = block.hash();
+                    if let BlockResult::MissingReferences(r) =
+                        validation::check_data_availability(&block, self, blockdb)
+                    {
+                        buffer.insert(block, &r);
+                        continue;
+                    }
+                    if !validated(&hash)? {
+                        let valid = match (
+                            validation::check_sortition_proof(&block, &self.config),
+                            validation::check_content_semantic(&block, self, blockdb),
+                        ) {
+                            (BlockResult::Pass, BlockResult::Pass) => true,
+                            _ => false,
+                        };
+                        if !valid {
+                            invalid_blocks += 1;
+                            continue;
+                        }
+                    }
+                    self.insert_block(&block)?;
+                    replayed_blocks += 1;
+                    to_process.extend(buffer.satisfy(hash));
+                }
+            }
+        }
+        Ok(ReplayReport {
+            replayed_blocks,
+            invalid_blocks,
+            unresolved_blocks: num_blocks - replayed_blocks - invalid_blocks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{mined_block, proposer_block};
+    use crate::block::{proposer, voter};
+
+    #[test]
+    fn reindex() {
+        const NUM_VOTER_CHAINS: u16 = 3;
+        // one transaction per block, so that proposer and voter blocks are easy to mine
+        let config = BlockchainConfig::new(NUM_VOTER_CHAINS,533,1,0.1,0.1,0.1,20.0);
+        let blockdb =
+            BlockDatabase::new("/tmp/prism_test_blockdb_reindex.rocksdb", config.clone()).unwrap();
+
+        // a proposer block voted by every chain, with the votes arriving before it
+        let proposer = mined_block(
+            &config,
+            config.proposer_genesis,
+            0,
+            Content::Proposer(proposer::Content::new(vec![], vec![])),
+        );
+        let mut voter_blocks = vec![];
+        for chain in 0..NUM_VOTER_CHAINS {
+            let mut voter_parent = config.voter_genesis[chain as usize];
+            for depth in 0..4 {
+                let votes = if depth == 0 { vec![proposer.hash()] } else { vec![] };
+                let block = mined_block(
+                    &config,
+                    proposer.hash(),
+                    depth,
+                    Content::Voter(voter::Content::new(chain, voter_parent, votes)),
+                );
+                voter_parent = block.hash();
+                voter_blocks.push(block);
+            }
+        }
+        for block in &voter_blocks {
+            blockdb.insert(block).unwrap();
+        }
+        blockdb.insert(&proposer).unwrap();
+        // a block with a wrong sortition proof, and one whose parent is unknown
+        let invalid = proposer_block(config.proposer_genesis, 1, vec![], vec![]);
+        blockdb.insert(&invalid).unwrap();
+        let orphan = proposer_block(H256::default(), 2, vec![], vec![]);
+        blockdb.insert(&orphan).unwrap();
+
+        let (chain, report) =
+            BlockChain::reindex("/tmp/prism_test_blockchain_reindex.rocksdb", &blockdb, config)
+                .unwrap();
+        assert_eq!(report.replayed_blocks, voter_blocks.len() + 1);
+        assert_eq!(report.invalid_blocks, 1);
+        assert_eq!(report.unresolved_blocks, 1);
+        assert_eq!(chain.best_proposer().unwrap(), proposer.hash());
+        for chain_num in 0..NUM_VOTER_CHAINS {
+            assert_eq!(
+                chain.best_voter(chain_num as usize),
+                voter_blocks[chain_num as usize * 4 + 3].hash()
+            );
+        }
+        chain.update_ledger().unwrap();
+        let proposer_node_vote_cf = chain.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let votes: Vec<(u16, u64)> = deserialize(
+            &chain
+                .db
+                .get_pinned_cf(proposer_node_vote_cf, serialize(&proposer.hash()).unwrap())
+                .unwrap()
+                .unwrap(),
+        )
+            .unwrap();
+        assert_eq!(votes.len(), NUM_VOTER_CHAINS as usize);
+    }
+}
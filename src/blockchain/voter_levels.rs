@@ -0,0 +1,129 @@
+/// In-memory copy of the shape of the voter trees: the best level of each voter chain, and the
+/// number of voter blocks on each level. Leader computation needs the number of voter blocks
+/// mined after each vote, which would otherwise take one database read per level.
+pub struct VoterLevels {
+    /// Best level of each voter chain.
+    best_levels: Vec<u64>,
+    /// Lowest level of each voter chain whose count is kept. Lower levels are compacted away
+    /// when pruning.
+    base_levels: Vec<u64>,
+    /// For each voter chain, the number of voter blocks below the base level.
+    base_counts: Vec<u64>,
+    /// For each voter chain, the number of voter blocks on levels 0 to base + i (inclusive) at
+    /// index i.
+    level_count_prefix: Vec<Vec<u64>>,
+}
+
+impl VoterLevels {
+    /// Create the cache for the given number of voter chains, without any block.
+    pub fn new(voter_chains: u16) -> Self {
+        Self {
+            best_levels: vec![0; voter_chains as usize],
+            base_levels: vec![0; voter_chains as usize],
+            base_counts: vec![0; voter_chains as usize],
+            level_count_prefix: vec![vec![]; voter_chains as usize],
+        }
+    }
+
+    /// Record a new voter block on the given chain and level. The parent of the block must have
+    /// been recorded before.
+    pub fn insert(&mut self, chain: u16, level: u64) {
+        let best = &mut self.best_levels[chain as usize];
+        if level > *best {
+            *best = level;
+        }
+        // blocks below the base level can no longer affect any vote we count
+        let base = self.base_levels[chain as usize];
+        if level < base {
+            return;
+        }
+        let prefix = &mut self.level_count_prefix[chain as usize];
+        let index = (level - base) as usize;
+        while prefix.len() <= index {
+            let last = prefix.last().cloned().unwrap_or(self.base_counts[chain as usize]);
+            prefix.push(last);
+        }
+        // blocks usually land on the top level, so this touches only a few entries
+        for count in prefix[index..].iter_mut() {
+            *count += 1;
+        }
+    }
+
+    /// Best level of the given voter chain.
+    pub fn best_level(&self, chain: u16) -> u64 {
+        self.best_levels[chain as usize]
+    }
+
+    /// Number of voter blocks on the given chain between the given levels (inclusive). Levels
+    /// below the base level are not counted.
+    pub fn num_blocks(&self, chain: u16, start_level: u64, end_level: u64) -> u64 {
+        let prefix = &self.level_count_prefix[chain as usize];
+        let base = self.base_levels[chain as usize];
+        let end = prefix[(end_level - base) as usize];
+        if start_level <= base {
+            end - self.base_counts[chain as usize]
+        } else {
+            end - prefix[(start_level - base) as usize - 1]
+        }
+    }
+
+    /// Drop the counts of the levels of the given chain below `level`.
+    pub fn compact(&mut self, chain: u16, level: u64) {
+        let base = self.base_levels[chain as usize];
+        let prefix = &mut self.level_count_prefix[chain as usize];
+        if level <= base || (level - base) as usize >= prefix.len() {
+            return;
+        }
+        let dropped = (level - base) as usize;
+        self.base_counts[chain as usize] = prefix[dropped - 1];
+        prefix.drain(..dropped);
+        self.base_levels[chain as usize] = level;
+    }
+
+    /// Lowest level of the given chain whose count is kept.
+    pub fn base_level(&self, chain: u16) -> u64 {
+        self.base_levels[chain as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_blocks() {
+        let mut levels = VoterLevels::new(2);
+        levels.insert(0, 0);
+        levels.insert(1, 0);
+        levels.insert(0, 1);
+        levels.insert(0, 2);
+        // a fork on level 1 and 2
+        levels.insert(0, 1);
+        levels.insert(0, 2);
+        levels.insert(0, 3);
+        assert_eq!(levels.best_level(0), 3);
+        assert_eq!(levels.best_level(1), 0);
+        assert_eq!(levels.num_blocks(0, 0, 3), 6);
+        assert_eq!(levels.num_blocks(0, 1, 2), 4);
+        assert_eq!(levels.num_blocks(0, 3, 3), 1);
+        assert_eq!(levels.num_blocks(1, 0, 0), 1);
+    }
+
+    #[test]
+    fn compact() {
+        let mut levels = VoterLevels::new(1);
+        for level in 0..5 {
+            levels.insert(0, level);
+        }
+        levels.insert(0, 3);
+        levels.compact(0, 3);
+        assert_eq!(levels.base_level(0), 3);
+        assert_eq!(levels.num_blocks(0, 3, 4), 3);
+        assert_eq!(levels.num_blocks(0, 4, 4), 1);
+        // blocks below the base level are ignored
+        levels.insert(0, 1);
+        levels.insert(0, 5);
+        assert_eq!(levels.num_blocks(0, 3, 5), 4);
+        assert_eq!(levels.best_level(0), 5);
+    }
+}
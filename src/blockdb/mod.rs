@@ -166,15 +166,23 @@ impl BlockDatabase {
         Ok(serialized)
     }
 
+    /// Check whether the block has been inserted. Pruned blocks are still contained, so that they
+    /// are not processed again.
     pub fn contains(&self, hash: &H256) -> Result<bool, rocksdb::Error> {
-        let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
-        let serialized = self.db.get_pinned_cf(block_cf, hash)?;
+        let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
+        let serialized = self.db.get_pinned_cf(block_sequence_number_cf, hash)?;
         match serialized {
             None => Ok(false),
             Some(_) => Ok(true),
         }
     }
 
+    /// Drop the body of a block, keeping its place in the arrival order.
+    pub fn prune(&self, hash: &H256) -> Result<(), rocksdb::Error> {
+        let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
+        self.db.delete_cf(block_cf, hash)
+    }
+
     pub fn blocks_after(&self, after: &H256, batch_size: u64) -> BlocksInArrivalOrder {
         let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
         let start_seq = u64::from_ne_bytes(
@@ -243,11 +251,13 @@ impl<'a> std::iter::Iterator for BlocksInArrivalOrder<'a> {
                 .get_cf(block_arrival_order_cf, &self.seq.to_ne_bytes())
                 .unwrap()
                 .unwrap();
-            let block: Block =
-                deserialize(&self.db.db.get_cf(block_cf, &hash_bytes).unwrap().unwrap()).unwrap();
-            result.push(block);
             self.seq += 1;
-            this_batch += 1;
+            // skip pruned blocks
+            if let Some(serialized) = self.db.db.get_cf(block_cf, &hash_bytes).unwrap() {
+                let block: Block = deserialize(&serialized).unwrap();
+                result.push(block);
+                this_batch += 1;
+            }
         }
         if result.is_empty() {
             None
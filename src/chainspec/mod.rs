@@ -0,0 +1,294 @@
+use crate::crypto::hash::Address;
+use ethereum_types::U256;
+use std::collections::BTreeMap;
+
+pub type Result<T> = std::result::Result<T, ChainSpecError>;
+
+/// Description of a Prism network: the consensus parameters, the accounts funded and the
+/// contracts deployed at genesis, and the EVM configuration.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainSpec {
+    /// Name of the network.
+    pub name: String,
+    #[serde(default)]
+    pub consensus: ConsensusSpec,
+    /// Balances credited to accounts at genesis, in Wei, on top of the genesis accounts of the
+    /// EVM specification.
+    #[serde(default)]
+    pub alloc: BTreeMap<Address, U256>,
+    /// Addresses of the contracts deployed at genesis, by name. The experiment transaction
+    /// generator calls the contracts by these names.
+    #[serde(default)]
+    pub contracts: BTreeMap<String, Address>,
+    /// The EVM chain specification, in the format of parity-ethereum. It holds the fork
+    /// transitions, the chain ID, and the genesis accounts with their code and storage.
+    pub evm: serde_json::Value,
+}
+
+/// Consensus parameters of a Prism network, from which the `BlockchainConfig` is built.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConsensusSpec {
+    /// Number of voter chains.
+    pub voter_chains: u16,
+    /// Maximum size of a transaction block in Bytes.
+    pub tx_block_size: u32,
+    /// Target transaction throughput in transactions/sec.
+    pub tx_throughput: u32,
+    /// Proposer block mining rate in blocks/sec.
+    pub proposer_mining_rate: f32,
+    /// Voter block mining rate for one voter chain, in blocks/sec.
+    pub voter_mining_rate: f32,
+    /// Ratio of adversary hashing power.
+    pub adversary_ratio: f32,
+    /// -log(epsilon) for confirmation.
+    pub confirm_confidence: f32,
+    /// Reward in Wei for a confirmed proposer block.
+    pub proposer_reward: u64,
+    /// Reward in Wei for a main chain vote on a confirmed proposer block.
+    pub voter_reward: u64,
+    /// Reward in Wei for a confirmed transaction block.
+    pub tx_reward: u64,
+    /// Maximum total gas of the transactions in a transaction block, unlimited if unset.
+    pub tx_block_gas_limit: Option<u64>,
+}
+
+impl Default for ConsensusSpec {
+    fn default() -> Self {
+        Self {
+            voter_chains: 1000,
+            tx_block_size: 64000,
+            tx_throughput: 80000,
+            proposer_mining_rate: 0.1,
+            voter_mining_rate: 0.1,
+            adversary_ratio: 0.4,
+            confirm_confidence: 20.0,
+            proposer_reward: 0,
+            voter_reward: 0,
+            tx_reward: 0,
+            tx_block_gas_limit: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ChainSpecError {
+    IOError(std::io::Error),
+    JsonError(serde_json::Error),
+    EthcoreError(common_types::errors::EthcoreError),
+}
+
+impl std::fmt::Display for ChainSpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ChainSpecError::IOError(ref e) => e.fmt(f),
+            ChainSpecError::JsonError(ref e) => e.fmt(f),
+            ChainSpecError::EthcoreError(ref e) => write!(f, "Invalid EVM specification: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChainSpecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            ChainSpecError::IOError(ref e) => Some(e),
+            ChainSpecError::JsonError(ref e) => Some(e),
+            ChainSpecError::EthcoreError(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ChainSpecError {
+    fn from(err: std::io::Error) -> ChainSpecError {
+        ChainSpecError::IOError(err)
+    }
+}
+
+impl From<serde_json::Error> for ChainSpecError {
+    fn from(err: serde_json::Error) -> ChainSpecError {
+        ChainSpecError::JsonError(err)
+    }
+}
+
+impl From<common_types::errors::EthcoreError> for ChainSpecError {
+    fn from(err: common_types::errors::EthcoreError) -> ChainSpecError {
+        ChainSpecError::EthcoreError(err)
+    }
+}
+
+impl ChainSpec {
+    /// Load the chain specification from a JSON file.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(file)
+    }
+
+    /// Parse the chain specification, and check that the EVM specification is valid.
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self> {
+        let spec: Self = serde_json::from_reader(reader)?;
+        spec.machine()?;
+        Ok(spec)
+    }
+
+    /// The built-in test network, with the experiment contracts deployed.
+    pub fn prism_test() -> Self {
+        Self::from_reader(&include_bytes!("prism_test.json")[..])
+            .expect("Built-in chain specification is invalid")
+    }
+
+    /// Build the parity-ethereum spec, which computes the genesis state, including the
+    /// allocations.
+    pub fn evm_spec(&self) -> Result<spec::Spec> {
+        let raw = serde_json::to_vec(&self.evm_with_alloc()?)?;
+        Ok(spec::Spec::load(&std::env::temp_dir(), &raw[..])?)
+    }
+
+    /// The EVM specification with the allocations added to the balances of its genesis accounts.
+    fn evm_with_alloc(&self) -> Result<serde_json::Value> {
+        let mut evm = self.evm.clone();
+        if self.alloc.is_empty() {
+            return Ok(evm);
+        }
+        let accounts = match evm.get_mut("accounts") {
+            Some(serde_json::Value::Object(accounts)) => accounts,
+            _ => return Err(invalid_evm("missing genesis accounts")),
+        };
+        for (addr, value) in &self.alloc {
+            // the same account may be written with or without the 0x prefix
+            let key = accounts
+                .keys()
+                .find(|k| k.trim_start_matches("0x").parse::<Address>().ok() == Some(*addr))
+                .cloned()
+                .unwrap_or_else(|| format!("{:x}", addr));
+            let account = accounts
+                .entry(key)
+                .or_insert_with(|| serde_json::json!({}));
+            let balance = match account.get("balance") {
+                Some(b) => parse_balance(b)?,
+                None => U256::zero(),
+            };
+            let balance = balance
+                .checked_add(*value)
+                .ok_or_else(|| invalid_evm("genesis balance overflow"))?;
+            account["balance"] = serde_json::json!(format!("0x{:x}", balance));
+        }
+        Ok(evm)
+    }
+
+    /// Build the machine applying transactions under the fork settings of the EVM specification.
+    pub fn machine(&self) -> Result<machine::Machine> {
+        let raw = serde_json::to_vec(&self.evm)?;
+        Ok(spec::Spec::load_machine(&raw[..])?)
+    }
+
+    /// The chain ID transactions are signed with.
+    pub fn chain_id(&self) -> Result<u64> {
+        Ok(self.machine()?.params().chain_id)
+    }
+
+    /// Address of the genesis contract with the given name.
+    pub fn contract(&self, name: &str) -> Option<Address> {
+        self.contracts.get(name).cloned()
+    }
+}
+
+/// Parse a balance of the EVM specification: a number, or a decimal or 0x-prefixed hex string.
+fn parse_balance(value: &serde_json::Value) -> Result<U256> {
+    match value {
+        serde_json::Value::Number(n) => n
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| invalid_evm("invalid balance")),
+        serde_json::Value::String(s) if s.starts_with("0x") => {
+            s[2..].parse::<U256>().map_err(|_| invalid_evm("invalid balance"))
+        }
+        serde_json::Value::String(s) => {
+            U256::from_dec_str(s).map_err(|_| invalid_evm("invalid balance"))
+        }
+        _ => Err(invalid_evm("invalid balance")),
+    }
+}
+
+fn invalid_evm(message: &str) -> ChainSpecError {
+    ChainSpecError::JsonError(serde::de::Error::custom(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::EthereumH256;
+    use crate::statedb::StateDatabase;
+
+    #[test]
+    fn prism_test() {
+        let spec = ChainSpec::prism_test();
+        assert_eq!(spec.consensus.voter_chains, 1000);
+        assert_eq!(spec.chain_id().unwrap(), 1);
+        assert_eq!(spec.contract("simpleerc20"), Some(Address::from_low_u64_be(0xe02)));
+        assert_eq!(spec.contract("nothing"), None);
+        let statedb =
+            StateDatabase::new("/tmp/prism_test_statedb_chainspec_builtin.rocksdb", &spec).unwrap();
+        // the constructors of the experiment contracts ran at genesis
+        for addr in spec.contracts.values() {
+            assert!(statedb.code(addr).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn custom_network() {
+        let mut spec = ChainSpec::prism_test();
+        let raw = r#"{
+            "name": "custom",
+            "consensus": {
+                "voterChains": 10,
+                "adversaryRatio": 0.2,
+                "txBlockGasLimit": 1000000
+            },
+            "alloc": {
+                "0x000000000000000000000000000000000000000a": "0x64"
+            },
+            "contracts": {
+                "store": "0x0000000000000000000000000000000000000c00"
+            },
+            "evm": null
+        }"#;
+        let mut custom: ChainSpec = serde_json::from_str(raw).unwrap();
+        assert_eq!(custom.consensus.voter_chains, 10);
+        assert_eq!(custom.consensus.adversary_ratio, 0.2);
+        // unset parameters keep their defaults
+        assert_eq!(custom.consensus.tx_throughput, 80000);
+        assert_eq!(custom.consensus.tx_block_gas_limit, Some(1_000_000));
+        assert_eq!(
+            custom.alloc.get(&Address::from_low_u64_be(0xa)),
+            Some(&U256::from(100))
+        );
+
+        // deploy a contract with code and storage, under another chain ID
+        spec.evm["params"]["chainID"] = serde_json::json!("0x2a");
+        spec.evm["accounts"]["0000000000000000000000000000000000000c00"] = serde_json::json!({
+            "balance": "0x0",
+            "code": "0x3331600055",
+            "storage": { "0x01": "0x2a" }
+        });
+        custom.evm = spec.evm;
+        assert_eq!(custom.chain_id().unwrap(), 42);
+        let statedb =
+            StateDatabase::new("/tmp/prism_test_statedb_chainspec_custom.rocksdb", &custom).unwrap();
+        // the allocations are part of the genesis state, added to existing genesis balances
+        assert_eq!(statedb.balance(&Address::from_low_u64_be(0xa)).unwrap(), 100.into());
+        custom.alloc.insert(Address::from_low_u64_be(1), 2.into());
+        let evm = custom.evm_with_alloc().unwrap();
+        assert_eq!(evm["accounts"]["0000000000000000000000000000000000000001"]["balance"], "0x3");
+        assert!(evm["accounts"]["0000000000000000000000000000000000000001"]["builtin"].is_object());
+        let addr = custom.contract("store").unwrap();
+        assert_eq!(
+            *statedb.code(&addr).unwrap().unwrap(),
+            hex::decode("3331600055").unwrap()
+        );
+        assert_eq!(
+            statedb.storage_at(&addr, &EthereumH256::from_low_u64_be(1)).unwrap(),
+            EthereumH256::from_low_u64_be(0x2a)
+        );
+    }
+}
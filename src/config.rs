@@ -1,3 +1,4 @@
+use crate::chainspec::ConsensusSpec;
 use crate::crypto::hash::H256;
 use bigint::uint::U256;
 
@@ -14,6 +15,40 @@ pub const FIRST_VOTER_INDEX: u16 = 2;
 // Lazy annotation of extra_content
 pub const LAZY_ANNOTATION: [u8;32] = [0x42;32];
 
+/// The available confirmation rules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfirmationRule {
+    /// Lower confidence bound on the votes, from https://arxiv.org/abs/1810.08092.
+    Lcb,
+    /// A block with votes at least the given depth on a majority of voter chains is the leader.
+    KDeep(u64),
+    /// The block on the longest proposer chain is the leader once the given number of levels are
+    /// on top of it. This ignores votes and serves as a baseline.
+    LongestChain(u64),
+}
+
+impl ConfirmationRule {
+    /// Get the rule with the given name. `depth` is ignored by rules that don't need it.
+    pub fn from_name(name: &str, depth: u64) -> Option<Self> {
+        match name {
+            "lcb" => Some(ConfirmationRule::Lcb),
+            "k-deep" => Some(ConfirmationRule::KDeep(depth)),
+            "longest-chain" => Some(ConfirmationRule::LongestChain(depth)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfirmationRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfirmationRule::Lcb => write!(f, "lcb"),
+            ConfirmationRule::KDeep(depth) => write!(f, "k-deep (k = {})", depth),
+            ConfirmationRule::LongestChain(depth) => write!(f, "longest-chain (k = {})", depth),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BlockchainConfig {
     /// Number of voter chains.
@@ -38,9 +73,28 @@ pub struct BlockchainConfig {
     voter_sortition_width: U256,
     tx_sortition_width: U256,
     pub adversary_ratio: f32,
-    log_epsilon: f32,
+    pub log_epsilon: f32,
     pub quantile_epsilon_confirm: f32,
     pub quantile_epsilon_deconfirm: f32,
+    /// Reward paid to the miner of a proposer block when it is confirmed, in Wei.
+    pub proposer_reward: u64,
+    /// Reward paid to the miner of a main chain voter block when the proposer block it votes for
+    /// is confirmed, in Wei.
+    pub voter_reward: u64,
+    /// Reward paid to the miner of a transaction block when it is confirmed, in Wei.
+    pub tx_reward: u64,
+    /// Whether to fast confirm transaction blocks by list decoding proposer blocks.
+    pub fast_confirm: bool,
+    /// The rule deciding the leader of each proposer level.
+    pub confirmation_rule: ConfirmationRule,
+    /// Number of proposer levels behind the ledger tip below which blockchain metadata and
+    /// transaction block bodies are pruned. None keeps everything (archive mode).
+    pub prune_depth: Option<u64>,
+    /// Maximum total gas of the transactions in a transaction block. None means no limit.
+    pub tx_block_gas_limit: Option<u64>,
+    /// Number of ledger levels between state snapshots served to syncing peers. None takes no
+    /// snapshots.
+    pub snapshot_interval: Option<u64>,
 }
 
 impl BlockchainConfig {
@@ -115,9 +169,70 @@ impl BlockchainConfig {
             log_epsilon,
             quantile_epsilon_confirm: quantile_confirm,
             quantile_epsilon_deconfirm: quantile_deconfirm,
+            proposer_reward: 0,
+            voter_reward: 0,
+            tx_reward: 0,
+            fast_confirm: false,
+            confirmation_rule: ConfirmationRule::Lcb,
+            prune_depth: None,
+            tx_block_gas_limit: None,
+            snapshot_interval: None,
         }
     }
 
+    /// Build the config from the consensus parameters of a chain specification.
+    pub fn from_spec(spec: &ConsensusSpec) -> Self {
+        Self::new(
+            spec.voter_chains,
+            spec.tx_block_size,
+            spec.tx_throughput,
+            spec.proposer_mining_rate,
+            spec.voter_mining_rate,
+            spec.adversary_ratio,
+            spec.confirm_confidence,
+        )
+        .with_rewards(spec.proposer_reward, spec.voter_reward, spec.tx_reward)
+        .with_tx_block_gas_limit(spec.tx_block_gas_limit)
+    }
+
+    /// Set the rewards of proposer, voter, and transaction blocks.
+    pub fn with_rewards(mut self, proposer_reward: u64, voter_reward: u64, tx_reward: u64) -> Self {
+        self.proposer_reward = proposer_reward;
+        self.voter_reward = voter_reward;
+        self.tx_reward = tx_reward;
+        self
+    }
+
+    /// Enable or disable fast confirmation of transaction blocks.
+    pub fn with_fast_confirm(mut self, fast_confirm: bool) -> Self {
+        self.fast_confirm = fast_confirm;
+        self
+    }
+
+    /// Set the rule deciding the leader of each proposer level.
+    pub fn with_confirmation_rule(mut self, confirmation_rule: ConfirmationRule) -> Self {
+        self.confirmation_rule = confirmation_rule;
+        self
+    }
+
+    /// Set the gas limit of transaction blocks, or None for no limit.
+    pub fn with_tx_block_gas_limit(mut self, gas_limit: Option<u64>) -> Self {
+        self.tx_block_gas_limit = gas_limit;
+        self
+    }
+
+    /// Set the pruning depth, or None to run in archive mode.
+    pub fn with_prune_depth(mut self, prune_depth: Option<u64>) -> Self {
+        self.prune_depth = prune_depth;
+        self
+    }
+
+    /// Set the number of ledger levels between state snapshots, or None to take no snapshots.
+    pub fn with_snapshot_interval(mut self, snapshot_interval: Option<u64>) -> Self {
+        self.snapshot_interval = snapshot_interval;
+        self
+    }
+
     pub fn sortition_hash(&self, hash: &H256, difficulty: &H256) -> Option<u16> {
         let hash = U256::from_big_endian(hash.as_ref());
         let difficulty = U256::from_big_endian(difficulty.as_ref());
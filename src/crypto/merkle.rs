@@ -2,7 +2,7 @@ use super::hash::{Hashable, H256};
 use keccak_hash::keccak;
 
 /// A Merkle tree.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MerkleTree {
     data_size: Vec<usize>,
     nodes: Vec<H256>,
@@ -199,6 +199,79 @@ impl MerkleTree {
             index >>= 1;
         }
     }
+
+    /// Update the leaves at the given indices to the hashes of the corresponding entries in `data`,
+    /// which holds all the leaves of the tree. Each internal node is recomputed at most once, no
+    /// matter how many of its descendants have changed.
+    pub fn batch_update<T>(&mut self, data: &[T], indices: &[usize])
+    where
+        T: Hashable,
+    {
+        let leaf_size = self.data_size[0];
+        let mut dirty: Vec<usize> = indices.iter().cloned().filter(|&i| i < leaf_size).collect();
+        dirty.sort_unstable();
+        dirty.dedup();
+        if dirty.is_empty() {
+            return;
+        }
+        if leaf_size == 1 {
+            self.nodes[0] = data[0].hash();
+            return;
+        }
+        let layer_start = self.layer_start();
+
+        // update the leaves
+        for &i in dirty.iter() {
+            self.nodes[layer_start[0] + i] = data[i].hash();
+        }
+        if leaf_size & 0x01 == 1 && dirty.last() == Some(&(leaf_size - 1)) {
+            // update the duplicate node
+            self.nodes[layer_start[0] + leaf_size] = self.nodes[layer_start[0] + leaf_size - 1];
+        }
+
+        // go up layer by layer, only touching the parents of the nodes changed in the layer below
+        for layer in 1..self.data_size.len() {
+            for i in dirty.iter_mut() {
+                *i >>= 1;
+            }
+            dirty.dedup();
+            for &i in dirty.iter() {
+                let left = self.nodes[layer_start[layer - 1] + (i << 1)];
+                let right = self.nodes[layer_start[layer - 1] + (i << 1) + 1];
+                self.nodes[layer_start[layer] + i] = hash_pair(&left, &right);
+            }
+            let size = self.data_size[layer];
+            if size > 1 && size & 0x01 == 1 && dirty.last() == Some(&(size - 1)) {
+                // update the duplicate node
+                self.nodes[layer_start[layer] + size] = self.nodes[layer_start[layer] + size - 1];
+            }
+        }
+    }
+
+    /// Returns the index of the first node of each layer, starting from the leaves
+    fn layer_start(&self) -> Vec<usize> {
+        let mut layer_start = vec![0usize; self.data_size.len()];
+        let mut start = 0;
+        for (layer, &size) in self.data_size.iter().enumerate().rev() {
+            layer_start[layer] = start;
+            start += if size > 1 && size & 0x01 == 1 {
+                size + 1
+            } else {
+                size
+            };
+        }
+        layer_start
+    }
+}
+
+/// Hash the concatenation of two nodes
+fn hash_pair(left: &H256, right: &H256) -> H256 {
+    let left: [u8; 32] = (*left).into();
+    let right: [u8; 32] = (*right).into();
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&left[..]);
+    bytes[32..].copy_from_slice(&right[..]);
+    keccak(&bytes[..]).into()
 }
 
 /// Verify that the data hash with a vector of proofs will produce the Merkle root. Also need the
@@ -398,4 +471,27 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn batch_update() {
+        for limit in 1..=40usize {
+            let input_data: Vec<H256> = (0..limit).map(|_| generate_random_hash()).collect();
+            let merkle_tree = MerkleTree::new(&input_data);
+            // change every third leaf, plus the last one
+            let mut changed: Vec<usize> = (0..limit).step_by(3).collect();
+            changed.push(limit - 1);
+            let mut updated_data = input_data.clone();
+            for &idx in changed.iter() {
+                updated_data[idx] = generate_random_hash();
+            }
+            let mut updated_tree = merkle_tree.clone();
+            updated_tree.batch_update(&updated_data, &changed);
+            let rebuilt_tree = MerkleTree::new(&updated_data);
+            assert_eq!(updated_tree.root(), rebuilt_tree.root());
+            assert_eq!(updated_tree.nodes, rebuilt_tree.nodes);
+            // out of range indices are ignored
+            updated_tree.batch_update(&updated_data, &[limit]);
+            assert_eq!(updated_tree.root(), rebuilt_tree.root());
+        }
+    }
 }
@@ -3,6 +3,7 @@ use crate::block::Content as BlockContent;
 
 use crate::transaction::{Transaction};
 use crate::wallet::WalletError;
+use ethereum_types::U256;
 use log::debug;
 use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 use std::time::SystemTime;
@@ -55,6 +56,18 @@ pub struct Counter {
     insert_block_db_time: AtomicUsize,
     insert_block_db_count: AtomicUsize,
     processed_message_bytes: AtomicUsize,
+    hashes_computed: AtomicUsize,
+    hash_rate: AtomicUsize,
+    withheld_blocks: AtomicUsize,
+    released_blocks: AtomicUsize,
+    censored_transactions: AtomicUsize,
+    delayed_voter_blocks: AtomicUsize,
+    proposer_leader_reversals: AtomicUsize,
+    fast_confirmed_transaction_blocks: AtomicUsize,
+    total_transaction_block_fast_confirmation_latency: AtomicUsize,
+    gas_used: AtomicUsize,
+    transaction_fees: AtomicUsize,
+    skipped_transactions: AtomicUsize,
 }
 
 #[derive(Serialize)]
@@ -96,6 +109,18 @@ pub struct Snapshot {
     pub voter_main_chain_length_sum: isize,
     pub avg_insert_block_db_time: usize,
     pub processed_message_bytes: usize,
+    pub hashes_computed: usize,
+    pub hash_rate: usize,
+    pub withheld_blocks: usize,
+    pub released_blocks: usize,
+    pub censored_transactions: usize,
+    pub delayed_voter_blocks: usize,
+    pub proposer_leader_reversals: usize,
+    pub fast_confirmed_transaction_blocks: usize,
+    pub total_transaction_block_fast_confirmation_latency: usize,
+    pub gas_used: usize,
+    pub transaction_fees: usize,
+    pub skipped_transactions: usize,
 }
 
 impl Counter {
@@ -198,6 +223,34 @@ impl Counter {
         }
     }
 
+    pub fn record_hashes(&self, num_hashes: usize) {
+        self.hashes_computed.fetch_add(num_hashes, Ordering::Relaxed);
+    }
+
+    pub fn record_hash_rate(&self, hashes_per_sec: usize) {
+        self.hash_rate.store(hashes_per_sec, Ordering::Relaxed);
+    }
+
+    pub fn record_withhold_block(&self) {
+        self.withheld_blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_release_blocks(&self, num_blocks: usize) {
+        self.released_blocks.fetch_add(num_blocks, Ordering::Relaxed);
+    }
+
+    pub fn record_censor_transaction(&self) {
+        self.censored_transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_delay_voter_block(&self) {
+        self.delayed_voter_blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_leader_reversal(&self) {
+        self.proposer_leader_reversals.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_update_proposer_main_chain(&self, new_height: usize) {
         self.proposer_main_chain_length
             .store(new_height, Ordering::Relaxed);
@@ -234,6 +287,23 @@ impl Counter {
             .fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn record_fast_confirm_transaction_block(&self, b: &Block) {
+        let mined_time = b.header.timestamp;
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let delay = if current_time <= mined_time {
+            0
+        } else {
+            current_time - mined_time
+        };
+        self.total_transaction_block_fast_confirmation_latency
+            .fetch_add(delay as usize, Ordering::Relaxed);
+        self.fast_confirmed_transaction_blocks
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_deconfirm_transaction_blocks(&self, num_blocks: usize) {
         self.deconfirmed_transaction_blocks
             .fetch_add(num_blocks, Ordering::Relaxed);
@@ -245,6 +315,20 @@ impl Counter {
             .fetch_add(t.size(), Ordering::Relaxed);
     }
 
+    /// Record the gas used by an executed transaction and the fee paid for it, in Wei.
+    pub fn record_transaction_fee(&self, gas_used: &U256, fee: &U256) {
+        let max = U256::from(std::usize::MAX);
+        self.gas_used
+            .fetch_add(std::cmp::min(*gas_used, max).as_usize(), Ordering::Relaxed);
+        self.transaction_fees
+            .fetch_add(std::cmp::min(*fee, max).as_usize(), Ordering::Relaxed);
+    }
+
+    pub fn record_skip_transactions(&self, num_transactions: usize) {
+        self.skipped_transactions
+            .fetch_add(num_transactions, Ordering::Relaxed);
+    }
+
     pub fn record_deconfirm_transaction(&self, t: &Transaction) {
         self.deconfirmed_transactions
             .fetch_add(1, Ordering::Relaxed);
@@ -342,6 +426,22 @@ impl Counter {
             voter_main_chain_length_sum,
             avg_insert_block_db_time: (self.insert_block_db_time.load(Ordering::Relaxed) as f64/ self.insert_block_db_count.load(Ordering::Relaxed) as f64) as usize,
             processed_message_bytes: self.processed_message_bytes.load(Ordering::Relaxed),
+            hashes_computed: self.hashes_computed.load(Ordering::Relaxed),
+            hash_rate: self.hash_rate.load(Ordering::Relaxed),
+            withheld_blocks: self.withheld_blocks.load(Ordering::Relaxed),
+            released_blocks: self.released_blocks.load(Ordering::Relaxed),
+            censored_transactions: self.censored_transactions.load(Ordering::Relaxed),
+            delayed_voter_blocks: self.delayed_voter_blocks.load(Ordering::Relaxed),
+            proposer_leader_reversals: self.proposer_leader_reversals.load(Ordering::Relaxed),
+            fast_confirmed_transaction_blocks: self
+                .fast_confirmed_transaction_blocks
+                .load(Ordering::Relaxed),
+            total_transaction_block_fast_confirmation_latency: self
+                .total_transaction_block_fast_confirmation_latency
+                .load(Ordering::Relaxed),
+            gas_used: self.gas_used.load(Ordering::Relaxed),
+            transaction_fees: self.transaction_fees.load(Ordering::Relaxed),
+            skipped_transactions: self.skipped_transactions.load(Ordering::Relaxed),
         }
     }
 }
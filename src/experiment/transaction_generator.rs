@@ -1,3 +1,4 @@
+use crate::chainspec::ChainSpec;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::handler::new_transaction;
 use crate::miner::memory_pool::MemoryPool;
@@ -11,7 +12,7 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
 use ethereum_types::Address;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use rand::distributions::WeightedIndex;
 use rand::distributions::Distribution;
 use rand::seq::SliceRandom;
@@ -57,6 +58,8 @@ pub struct TransactionGenerator {
     value_distribution: ValueDistribution,
     state: State,
     transaction_type: String,
+    /// Addresses of the experiment contracts, by name.
+    contracts: BTreeMap<String, Address>,
 }
 
 impl TransactionGenerator {
@@ -64,6 +67,7 @@ impl TransactionGenerator {
         wallet: &Arc<Wallet>,
         server: &ServerHandle,
         mempool: &Arc<Mutex<MemoryPool>>,
+        chain_spec: &ChainSpec,
     ) -> (Self, channel::Sender<ControlSignal>) {
         let (tx, rx) = channel::unbounded();
         let instance = Self {
@@ -75,6 +79,7 @@ impl TransactionGenerator {
             value_distribution: ValueDistribution::Uniform(UniformValue { min: 50, max: 100 }),
             state: State::Paused,
             transaction_type: String::from("donothing"),
+            contracts: chain_spec.contracts.clone(),
         };
         (instance, tx)
     }
@@ -134,6 +139,13 @@ impl TransactionGenerator {
             let to_dist = WeightedIndex::new(&probs).unwrap();
             //let mut addr_iter = addrs.iter().cycle();
             let mut has_erc20_ico: HashSet<Address> = HashSet::new();
+            let contracts = self.contracts.clone();
+            let contract = |name: &str| -> Address {
+                match contracts.get(name) {
+                    Some(addr) => *addr,
+                    None => panic!("Contract {} is not in the chain specification", name),
+                }
+            };
             loop {
                 let tx_gen_start = time::Instant::now();
                 // check the current state and try to receive control message
@@ -182,18 +194,18 @@ impl TransactionGenerator {
                 let to_addr = addrs.get(to_dist.sample(&mut rng)).unwrap();
                 let transaction = match self.transaction_type.as_ref() {
                     "payment" => self.wallet.create_transaction_payment(addr, to_addr, value.into()),
-                    "donothing" => self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf01), hex::decode("448f30a3").unwrap()),
-                    "cpuheavy" => self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf02), hex::decode("fe91386500000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
-                    "ioheavy" => self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf03), hex::decode("b6a3f0e900000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
+                    "donothing" => self.wallet.create_transaction_call(addr, &contract("donothing"), hex::decode("448f30a3").unwrap()),
+                    "cpuheavy" => self.wallet.create_transaction_call(addr, &contract("cpuheavy"), hex::decode("fe91386500000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
+                    "ioheavy" => self.wallet.create_transaction_call(addr, &contract("ioheavy"), hex::decode("b6a3f0e900000000000000000000000000000000000000000000000000000000000000ff").unwrap()),
                     "simpleballot" => {
                         panic!("In current experiment we don't want to call simple ballot");
                         // need to append a random 0 or 1 to it
                         let mut data = hex::decode("0121b93f00000000000000000000000000000000000000000000000000000000000000").unwrap();
                         // append a random value (represents to whom we vote)
                         data.append(&mut vec![rng.gen_range(0u8, 2u8)]);
-                        self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe00), data)
+                        self.wallet.create_transaction_call(addr, &contract("simpleballot"), data)
                     }
-                    "size600" => self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xf01), hex::decode("448f30a30000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000").unwrap()),
+                    "size600" => self.wallet.create_transaction_call(addr, &contract("donothing"), hex::decode("448f30a30000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000").unwrap()),
                     "cryptokitties" => {
                         let mut data = hex::decode("0d9f5aed").unwrap();
                         // generate 2 random u256 parameter (= 4 u128)
@@ -203,7 +215,7 @@ impl TransactionGenerator {
                         }
                         // last parameter, target block number is 0
                         data.append(&mut hex::decode("0000000000000000000000000000000000000000000000000000000000000000").unwrap());
-                        self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe01), data)
+                        self.wallet.create_transaction_call(addr, &contract("cryptokitties"), data)
                     }
                     "simpleerc20" => {
                         if has_erc20_ico.contains(addr) {
@@ -211,11 +223,11 @@ impl TransactionGenerator {
                             let mut data = hex::decode("a9059cbb000000000000000000000000").unwrap();
                             data.extend_from_slice(to_addr.as_bytes());
                             data.append(&mut hex::decode("0000000000000000000000000000000000000000000000000000000000000001").unwrap());
-                            self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe02), data)
+                            self.wallet.create_transaction_call(addr, &contract("simpleerc20"), data)
                         } else {
                             has_erc20_ico.insert(addr.clone());
                             // mint tokens, number is very large: 0xffffffffffffffff
-                            self.wallet.create_transaction_call(addr, &Address::from_low_u64_be(0xe02), hex::decode("a0712d68000000000000000000000000000000000000000000000000ffffffffffffffff").unwrap())
+                            self.wallet.create_transaction_call(addr, &contract("simpleerc20"), hex::decode("a0712d68000000000000000000000000000000000000000000000000ffffffffffffffff").unwrap())
                         }
                     }
                     _ => panic!("Transaction type does not match any known one."),
@@ -1,24 +1,55 @@
-use crate::block::Content;
+use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
-use crate::crypto::hash::{Hashable, H256, Address};
+use crate::crypto::hash::{Hashable, H256, Address, EthereumH256};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::logdb::LogDatabase;
 use log::{trace, debug, info, warn};
 use crate::transaction::{Transaction, UnverifiedTransaction};
-use crate::config::LAZY_ANNOTATION;
+use crate::config::{BlockchainConfig, LAZY_ANNOTATION};
 
-use crate::statedb::StateDatabase;
+use crate::network::message::Message;
+use crate::network::server::Handle as ServerHandle;
+use crate::statedb::{ExecutionRecord, SnapshotSync, StateDatabase, TraceOptions, TraceResults};
+use crate::validation;
 use crate::wallet::Wallet;
 use crossbeam::channel;
+use ethereum_types::U256;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+/// How long a node syncing from snapshots waits for a peer to serve one, before it executes the
+/// ledger from the start.
+const SNAPSHOT_SYNC_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub struct LedgerManager {
     blockdb: Arc<BlockDatabase>,
     chain: Arc<BlockChain>,
     statedb: Arc<StateDatabase>,
+    logdb: Arc<LogDatabase>,
     wallet: Arc<Wallet>,
+    server: ServerHandle,
+    snapshot_sync: Option<Arc<SnapshotSync>>,
+    config: BlockchainConfig,
+}
+
+/// Miner and transactions of a decoded transaction block, or None if the block is skipped.
+type DecodedBlock = Option<(Address, Vec<Transaction>)>;
+
+/// A confirmed piece of the ledger to be executed, in ledger order.
+enum LedgerUpdate {
+    /// Block rewards to credit to the miners.
+    Reward(Vec<(Address, U256)>),
+    /// A transaction block being decoded by the decoding workers, with the EVM block environment
+    /// of its ledger level. Its miner receives the reward and the transaction fees.
+    TransactionBlock(H256, vm::EnvInfo, channel::Receiver<DecodedBlock>),
+    /// Transaction blocks deep enough in the ledger for their bodies to be dropped.
+    Prune(Vec<H256>),
+    /// Everything confirmed on the ledger level has been sent.
+    LevelEnd(u64),
 }
 
 impl LedgerManager {
@@ -26,34 +57,164 @@ impl LedgerManager {
         blockdb: &Arc<BlockDatabase>,
         chain: &Arc<BlockChain>,
         statedb: &Arc<StateDatabase>,
+        logdb: &Arc<LogDatabase>,
         wallet: &Arc<Wallet>,
+        server: &ServerHandle,
+        config: BlockchainConfig,
     ) -> Self {
         Self {
             blockdb: Arc::clone(&blockdb),
             chain: Arc::clone(&chain),
             statedb: Arc::clone(&statedb),
+            logdb: Arc::clone(&logdb),
             wallet: Arc::clone(&wallet),
+            server: server.clone(),
+            snapshot_sync: None,
+            config,
         }
     }
 
-    pub fn start(self, buffer_size: usize) {
+    /// Restore the state from the snapshot of a peer before executing the ledger, from the
+    /// level after the snapshot.
+    pub fn with_snapshot_sync(mut self, snapshot_sync: &Arc<SnapshotSync>) -> Self {
+        self.snapshot_sync = Some(Arc::clone(snapshot_sync));
+        self
+    }
+
+    pub fn start(self, buffer_size: usize, workers: usize) {
         // start thread that updates transaction sequence
         let blockdb = Arc::clone(&self.blockdb);
         let chain = Arc::clone(&self.chain);
         let statedb = Arc::clone(&self.statedb);
+        let logdb = Arc::clone(&self.logdb);
+        let config = self.config.clone();
+        let tx_reward = self.config.tx_reward;
+        let gas_limit = self.config.tx_block_gas_limit;
+        let execution_blockdb = Arc::clone(&self.blockdb);
+        let execution_chain = Arc::clone(&self.chain);
+        let snapshot_sync = self.snapshot_sync.clone();
+        let snapshot_interval = self.config.snapshot_interval;
+        let server = self.server.clone();
+        let snapshotting = Arc::new(AtomicBool::new(false));
+        // updates wait for execution in ledger order, which bounds the blocks decoded ahead
         let (tx_diff_tx, tx_diff_rx) = channel::bounded(buffer_size);
-        thread::spawn(move || loop {
-            update_transaction_sequence(&blockdb, &chain, &tx_diff_tx);
+        let (decode_tx, decode_rx) = channel::unbounded();
+        thread::spawn(move || {
+            // the levels up to a restored snapshot are not executed
+            let restored = snapshot_sync.and_then(|sync| {
+                match sync.wait_timeout(SNAPSHOT_SYNC_TIMEOUT) {
+                    Some(level) => {
+                        info!("Executing the ledger from level {}, after the snapshot", level + 1);
+                        Some(level)
+                    }
+                    None => {
+                        warn!(
+                            "No snapshot served in {} seconds, executing the ledger from the start",
+                            SNAPSHOT_SYNC_TIMEOUT.as_secs()
+                        );
+                        None
+                    }
+                }
+            });
+            let mut last_hashes = LastHashes::new();
+            loop {
+                // only update the ledger when new blocks may have changed it
+                chain.wait_for_tip_update();
+                update_transaction_sequence(
+                    &blockdb,
+                    &chain,
+                    &config,
+                    restored,
+                    &mut last_hashes,
+                    &tx_diff_tx,
+                    &decode_tx,
+                );
+            }
         });
 
+        // decode transaction blocks and recover their senders ahead of execution
+        for _ in 0..std::cmp::max(workers, 1) {
+            let blockdb = Arc::clone(&self.blockdb);
+            let decode_rx: channel::Receiver<(H256, channel::Sender<DecodedBlock>)> =
+                decode_rx.clone();
+            thread::spawn(move || {
+                for (hash, result) in decode_rx {
+                    result
+                        .send(decode_transaction_block(&blockdb, &hash, gas_limit))
+                        .unwrap();
+                }
+            });
+        }
+
         thread::spawn(move || {
+            // logs emitted on the current level, indexed when it ends
+            let mut level_logs = vec![];
             loop {
                 // get the diff
-                let added_tx = tx_diff_rx.recv().unwrap();
+                let (hash, root, info, added_tx) = match tx_diff_rx.recv().unwrap() {
+                    LedgerUpdate::Reward(rewards) => {
+                        for (addr, value) in &rewards {
+                            statedb.add_balance(addr, value).unwrap();
+                        }
+                        statedb.commit().unwrap();
+                        continue;
+                    }
+                    LedgerUpdate::TransactionBlock(hash, mut info, decoded) => {
+                        match decoded.recv().unwrap() {
+                            Some((miner, txs)) => {
+                                // the state is committed after every update, so this is the
+                                // root the transactions are replayed from
+                                let root = statedb.root();
+                                if tx_reward > 0 {
+                                    statedb.add_balance(&miner, &tx_reward.into()).unwrap();
+                                }
+                                info.author = miner;
+                                (hash, root, info, txs)
+                            }
+                            None => continue,
+                        }
+                    }
+                    LedgerUpdate::Prune(hashes) => {
+                        // everything sent before has been executed
+                        for hash in &hashes {
+                            execution_blockdb.prune(hash).unwrap();
+                            statedb.prune_execution(&(*hash).into()).unwrap();
+                        }
+                        continue;
+                    }
+                    LedgerUpdate::LevelEnd(level) => {
+                        // the state is committed after every update
+                        statedb.record_level_root(level, &statedb.root()).unwrap();
+                        logdb.insert_level(level, &level_logs).unwrap();
+                        level_logs.clear();
+                        match snapshot_interval {
+                            Some(interval) if level % interval == 0 => {
+                                // one snapshot at a time, a level is skipped if one is running
+                                if !snapshotting.swap(true, Ordering::SeqCst) {
+                                    let leader = execution_chain.ledger_leader(level).unwrap().unwrap();
+                                    let statedb = Arc::clone(&statedb);
+                                    let server = server.clone();
+                                    let snapshotting = Arc::clone(&snapshotting);
+                                    thread::spawn(move || {
+                                        take_snapshot(&statedb, &server, level, &leader);
+                                        snapshotting.store(false, Ordering::SeqCst);
+                                    });
+                                }
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                };
 
-                for tx in added_tx {
-                    let outcome = statedb.apply(&tx).unwrap();
-                    PERFORMANCE_COUNTER.record_confirm_transaction(&tx);
+                // the transaction fee goes to the miner of the transaction block
+                let outcomes = statedb.apply_parallel(&added_tx, &info, workers).unwrap();
+                for (index, (tx, outcome)) in added_tx.iter().zip(outcomes).enumerate() {
+                    PERFORMANCE_COUNTER.record_confirm_transaction(tx);
+                    // receipts count the gas used from the start of the block, which is always 0
+                    let gas_used = outcome.receipt.gas_used;
+                    PERFORMANCE_COUNTER
+                        .record_transaction_fee(&gas_used, &gas_used.saturating_mul(tx.gas_price));
                     /*
                     // try to get address if it's create contract, useful when debugging
                     let contract_addr = match outcome.trace.get(0).map(|trace|&trace.result) {
@@ -84,35 +245,303 @@ impl LedgerManager {
                         debug!("Tx vm_trace length (number of operations) {}.", vm_trace.operations.len());
                     }
                     debug!("Tx vm trace {:?}.", outcome.vm_trace);
+                    for log in outcome.receipt.logs {
+                        level_logs.push((hash.into(), tx.hash(), index as u32, log));
+                    }
                 }
                 // after applying transactions, commit
                 statedb.commit().unwrap();
+                let record = ExecutionRecord {
+                    root,
+                    reward: tx_reward.into(),
+                    number: info.number,
+                    timestamp: info.timestamp,
+                    author: info.author,
+                    gas_limit: info.gas_limit,
+                    transactions: added_tx.iter().map(|tx| tx.hash()).collect(),
+                };
+                statedb.record_execution(&hash.into(), &record).unwrap();
             }
         });
     }
 }
 
+/// Take a snapshot of the state after the given ledger level, and advertise it to the peers.
+fn take_snapshot(statedb: &StateDatabase, server: &ServerHandle, level: u64, leader: &H256) {
+    match statedb.take_snapshot(level, &leader.into()) {
+        Ok(manifest) => {
+            info!(
+                "Took a snapshot of the state at level {} with root {:x}, {} chunks",
+                level,
+                manifest.state_root,
+                manifest.state_hashes.len()
+            );
+            server.broadcast(Message::SnapshotManifest(Some(manifest.into_rlp())));
+        }
+        Err(e) => warn!("Error taking a snapshot of the state at level {}: {}", level, e),
+    }
+}
+
 fn update_transaction_sequence(
     blockdb: &BlockDatabase,
     chain: &BlockChain,
-    sender: &channel::Sender<Vec<Transaction>>,
+    config: &BlockchainConfig,
+    restored: Option<u64>,
+    last_hashes: &mut LastHashes,
+    sender: &channel::Sender<LedgerUpdate>,
+    decoder: &channel::Sender<(H256, channel::Sender<DecodedBlock>)>,
 ) {
     let diff = chain.update_ledger().unwrap();
-    PERFORMANCE_COUNTER.record_deconfirm_transaction_blocks(diff.1.len());
+    PERFORMANCE_COUNTER.record_deconfirm_transaction_blocks(
+        diff.1.iter().map(|(_, tx_blocks)| tx_blocks.len()).sum(),
+    );
 
-    for hash in diff.0 {
-        let block = blockdb.get(&hash).unwrap().unwrap();
-        if block.header.extra_content == LAZY_ANNOTATION {
+    if !diff.1.is_empty() {
+        // the leaders of the levels before the added ones may have changed
+        last_hashes.invalidate();
+    }
+
+    // a level is added to the ledger in one piece, ending with its leader
+    let mut level_blocks: Vec<(H256, Vec<H256>)> = vec![];
+    for (proposer_hash, tx_hashes) in diff.0 {
+        let level = chain.proposer_level(&proposer_hash).unwrap();
+        level_blocks.push((proposer_hash, tx_hashes));
+        if chain.ledger_leader(level).unwrap() != Some(proposer_hash) {
             continue;
         }
-        PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
-        let content = match block.content {
-            Content::Transaction(data) => data,
-            _ => unreachable!(),
-        };
-        sender.send(content.transactions).unwrap();
+        if let Some(restored_level) = restored {
+            if level <= restored_level {
+                level_blocks.clear();
+                continue;
+            }
+        }
+        let leader = blockdb.get(&proposer_hash).unwrap().unwrap();
+        let info = env_info(
+            level,
+            &leader,
+            last_hashes.get(chain, level),
+            config.tx_block_gas_limit,
+        );
+
+        for (proposer_hash, tx_hashes) in level_blocks.drain(..) {
+            // pay the proposer block and the main chain voter blocks voting for it
+            let mut rewards: Vec<(Address, U256)> = vec![];
+            if config.proposer_reward > 0 {
+                let proposer = blockdb.get(&proposer_hash).unwrap().unwrap();
+                rewards.push((proposer.header.miner, config.proposer_reward.into()));
+            }
+            if config.voter_reward > 0 {
+                for voter_hash in chain.main_chain_voters(&proposer_hash).unwrap() {
+                    let voter = blockdb.get(&voter_hash).unwrap().unwrap();
+                    rewards.push((voter.header.miner, config.voter_reward.into()));
+                }
+            }
+            if !rewards.is_empty() {
+                sender.send(LedgerUpdate::Reward(rewards)).unwrap();
+            }
+
+            for hash in tx_hashes {
+                let (result_tx, result_rx) = channel::bounded(1);
+                decoder.send((hash, result_tx)).unwrap();
+                sender
+                    .send(LedgerUpdate::TransactionBlock(hash, info.clone(), result_rx))
+                    .unwrap();
+            }
+        }
+        sender.send(LedgerUpdate::LevelEnd(level)).unwrap();
     }
     for _hash in diff.1 {
         warn!("Deconfim (Remove) tx shouldn't happen.");
     }
+
+    // drop what is deep enough in the ledger, unless running as an archive node
+    if let Some(depth) = config.prune_depth {
+        let pruned = chain.prune(depth).unwrap();
+        if !pruned.is_empty() {
+            debug!("Pruning {} transaction blocks", pruned.len());
+            sender.send(LedgerUpdate::Prune(pruned)).unwrap();
+        }
+    }
+
+    // transaction blocks fast confirmed here are executed once they are in the ledger
+    if config.fast_confirm {
+        for hash in chain.update_fast_ledger().unwrap() {
+            // a block referred before a snapshot sync may not have arrived
+            if let Some(block) = blockdb.get(&hash).unwrap() {
+                PERFORMANCE_COUNTER.record_fast_confirm_transaction_block(&block);
+            }
+            debug!("Transaction block {} fast confirmed", hash);
+        }
+    }
+}
+
+/// The EVM block environment of the transactions confirmed on the given ledger level, without
+/// an author. Returns None if the level is beyond the ledger tip.
+pub fn level_env_info(
+    blockdb: &BlockDatabase,
+    chain: &BlockChain,
+    level: u64,
+) -> Option<vm::EnvInfo> {
+    let leader = chain.ledger_leader(level).unwrap()?;
+    let leader = blockdb.get(&leader).unwrap()?;
+    Some(env_info(
+        level,
+        &leader,
+        LastHashes::new().get(chain, level),
+        chain.config().tx_block_gas_limit,
+    ))
+}
+
+/// Replay a transaction executed from the ledger on the state before its transaction block, and
+/// trace it. Returns None if the transaction was not executed, or if the body of its block was
+/// pruned.
+pub fn trace_transaction(
+    blockdb: &BlockDatabase,
+    chain: &BlockChain,
+    statedb: &StateDatabase,
+    hash: &EthereumH256,
+    options: TraceOptions,
+) -> crate::statedb::Result<Option<TraceResults>> {
+    let (block_hash, index) = match statedb.transaction_location(hash)? {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    let record = match statedb.execution_record(&block_hash)? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+    let block = match blockdb.get(&block_hash.into()).unwrap() {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let content = match block.content {
+        Content::Transaction(data) => data,
+        _ => unreachable!(),
+    };
+    // transactions over the gas limit were skipped
+    let executed: HashSet<&EthereumH256> = record.transactions[..=index].iter().collect();
+    let txs: Vec<Transaction> = content
+        .transactions
+        .into_iter()
+        .filter(|tx| executed.contains(&tx.hash()))
+        .take(index + 1)
+        .collect();
+    let info = record.env_info(LastHashes::new().get(chain, record.number));
+    Ok(Some(statedb.replay_transaction(&record, &info, &txs, options)?))
+}
+
+/// Read a confirmed transaction block and check the signatures of its transactions, most of
+/// which are found in the sender cache. Transactions that would take the block over the gas
+/// limit are skipped.
+fn decode_transaction_block(
+    blockdb: &BlockDatabase,
+    hash: &H256,
+    gas_limit: Option<u64>,
+) -> DecodedBlock {
+    let block = match blockdb.get(hash).unwrap() {
+        Some(b) => b,
+        // pruned, so it was executed when first confirmed
+        None => return None,
+    };
+    if block.header.extra_content == LAZY_ANNOTATION {
+        return None;
+    }
+    PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
+    let content = match block.content {
+        Content::Transaction(data) => data,
+        _ => unreachable!(),
+    };
+    // the block was validated when received, but the database may have been written by another
+    // node or corrupted since
+    if !validation::check_signature_batch(&content.transactions) {
+        warn!("Transaction block {} has a wrong signature, skipping it", hash);
+        return None;
+    }
+    let mut transactions = content.transactions;
+    if let Some(gas_limit) = gas_limit {
+        let limit = U256::from(gas_limit);
+        let mut block_gas = U256::zero();
+        let num_transactions = transactions.len();
+        transactions.retain(|tx| match block_gas.checked_add(tx.gas) {
+            Some(gas) if gas <= limit => {
+                block_gas = gas;
+                true
+            }
+            _ => false,
+        });
+        let skipped = num_transactions - transactions.len();
+        if skipped > 0 {
+            warn!(
+                "Skipping {} transactions of block {} over the gas limit",
+                skipped, hash
+            );
+            PERFORMANCE_COUNTER.record_skip_transactions(skipped);
+        }
+    }
+    Some((block.header.miner, transactions))
+}
+
+/// Number of previous blocks whose hash the BLOCKHASH instruction can read.
+const LAST_HASHES: u64 = 256;
+
+/// Hashes of the leaders of the ledger levels before a given level, most recent first, which
+/// the BLOCKHASH instruction reads.
+struct LastHashes {
+    /// The level the hashes are for, or 0 if they must be read again. Level 0 is the genesis
+    /// block, which is never executed.
+    level: u64,
+    hashes: Arc<Vec<EthereumH256>>,
+}
+
+impl LastHashes {
+    fn new() -> Self {
+        Self {
+            level: 0,
+            hashes: Arc::new(vec![]),
+        }
+    }
+
+    /// Get the hashes for the given level. Levels usually come in order, so only the leader of
+    /// the level before is read.
+    fn get(&mut self, chain: &BlockChain, level: u64) -> Arc<Vec<EthereumH256>> {
+        if level == self.level {
+            return Arc::clone(&self.hashes);
+        }
+        let leader = |l: u64| -> EthereumH256 { chain.ledger_leader(l).unwrap().unwrap().into() };
+        let hashes: Vec<EthereumH256> = if self.level != 0 && level == self.level + 1 {
+            std::iter::once(leader(self.level))
+                .chain(self.hashes.iter().cloned())
+                .take(LAST_HASHES as usize)
+                .collect()
+        } else {
+            (level.saturating_sub(LAST_HASHES)..level).rev().map(leader).collect()
+        };
+        self.level = level;
+        self.hashes = Arc::new(hashes);
+        Arc::clone(&self.hashes)
+    }
+
+    fn invalidate(&mut self) {
+        self.level = 0;
+    }
+}
+
+/// The EVM block environment of the transactions confirmed on a ledger level. The level is the
+/// block number, the timestamp is the one of the leader, and the hashes of the previous blocks
+/// are the ones of the leaders of the levels before. The author, which `COINBASE` returns, is
+/// set to the miner of each transaction block.
+fn env_info(
+    level: u64,
+    leader: &Block,
+    last_hashes: Arc<Vec<EthereumH256>>,
+    gas_limit: Option<u64>,
+) -> vm::EnvInfo {
+    let mut info = vm::EnvInfo::default();
+    info.number = level;
+    // block timestamps are in milliseconds
+    info.timestamp = (leader.header.timestamp / 1000) as u64;
+    info.last_hashes = last_hashes;
+    // the limit applies to each transaction block, whose transactions were checked against it
+    info.gas_limit = gas_limit.map(U256::from).unwrap_or(U256::MAX);
+    info
 }
@@ -10,11 +10,13 @@ pub mod api;
 pub mod block;
 pub mod blockchain;
 pub mod blockdb;
+pub mod chainspec;
 pub mod config;
 pub mod crypto;
 pub mod experiment;
 pub mod handler;
 pub mod ledger_manager;
+pub mod logdb;
 pub mod miner;
 pub mod network;
 pub mod transaction;
@@ -0,0 +1,306 @@
+use crate::crypto::hash::{Address, EthereumH256};
+use bincode::{deserialize, serialize};
+use common_types::filter::Filter;
+use common_types::ids::BlockId;
+use common_types::log_entry::LogEntry;
+use ethereum_types::Bloom;
+use parity_bytes::Bytes;
+use rocksdb::{self, Direction, IteratorMode, Options, DB};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type Result<T> = std::result::Result<T, LogDatabaseError>;
+
+/// Database of the logs emitted by the transactions executed from the ledger, indexed by their
+/// position in the ledger, with a bloom filter of the logs of each ledger level.
+pub struct LogDatabase {
+    /// Logs keyed by ledger level and position in the level.
+    db: rocksdb::DB,
+    /// Bloom filter of the logs of each ledger level, indexed by level.
+    blooms: blooms_db::Database,
+    /// The last ledger level indexed.
+    latest: AtomicU64,
+}
+
+/// A log emitted by a transaction executed from the ledger, with its position.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalizedLog {
+    /// The contract emitting the log.
+    pub address: Address,
+    pub topics: Vec<EthereumH256>,
+    #[serde(with = "hex_bytes")]
+    pub data: Bytes,
+    /// Ledger level of the transaction.
+    pub level: u64,
+    /// Position of the log among the logs of the level.
+    pub log_index: u32,
+    /// Transaction block of the transaction.
+    pub block_hash: EthereumH256,
+    pub transaction_hash: EthereumH256,
+    /// Position of the transaction among the transactions executed from its block.
+    pub transaction_index: u32,
+}
+
+/// Query of logs, in the manner of `eth_getLogs`.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    /// First ledger level searched.
+    pub from_level: u64,
+    /// Last ledger level searched, the last one indexed if None.
+    pub to_level: Option<u64>,
+    /// Contracts one of which must have emitted the log, any if None.
+    pub addresses: Option<Vec<Address>>,
+    /// Topics the log must have at each position, one of each list, or any if None.
+    pub topics: Vec<Option<Vec<EthereumH256>>>,
+    /// Only the last this many logs found are returned, all if None.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum LogDatabaseError {
+    IOError(std::io::Error),
+    RocksDBError(rocksdb::Error),
+}
+
+impl std::fmt::Display for LogDatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            LogDatabaseError::IOError(ref e) => e.fmt(f),
+            LogDatabaseError::RocksDBError(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LogDatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            LogDatabaseError::IOError(ref e) => Some(e),
+            LogDatabaseError::RocksDBError(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for LogDatabaseError {
+    fn from(err: std::io::Error) -> LogDatabaseError {
+        LogDatabaseError::IOError(err)
+    }
+}
+
+impl From<rocksdb::Error> for LogDatabaseError {
+    fn from(err: rocksdb::Error) -> LogDatabaseError {
+        LogDatabaseError::RocksDBError(err)
+    }
+}
+
+impl LogFilter {
+    /// The filter in the form of parity-ethereum, which computes the blooms and matches logs.
+    fn to_filter(&self, to_level: u64) -> Filter {
+        Filter {
+            from_block: BlockId::Number(self.from_level),
+            to_block: BlockId::Number(to_level),
+            address: self.addresses.clone(),
+            topics: self.topics.clone(),
+            limit: self.limit,
+        }
+    }
+}
+
+impl LogDatabase {
+    /// Create a new database in the given directory, which holds the logs and the blooms.
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        if path.as_ref().is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, path.as_ref().join("logs"))?;
+        let blooms = blooms_db::Database::open(path.as_ref().join("blooms"))?;
+        Ok(LogDatabase {
+            db,
+            blooms,
+            latest: AtomicU64::new(0),
+        })
+    }
+
+    /// Index the logs of an executed ledger level, in the order they were emitted, each with the
+    /// hashes of its transaction block and transaction, and the position of the transaction in
+    /// the block. Levels are indexed in order.
+    pub fn insert_level(
+        &self,
+        level: u64,
+        logs: &[(EthereumH256, EthereumH256, u32, LogEntry)],
+    ) -> Result<()> {
+        let mut bloom = Bloom::default();
+        let mut batch = rocksdb::WriteBatch::default();
+        for (index, (block_hash, transaction_hash, transaction_index, entry)) in
+            logs.iter().enumerate()
+        {
+            bloom.accrue_bloom(&entry.bloom());
+            let log = LocalizedLog {
+                address: entry.address,
+                topics: entry.topics.clone(),
+                data: entry.data.clone(),
+                level,
+                log_index: index as u32,
+                block_hash: *block_hash,
+                transaction_hash: *transaction_hash,
+                transaction_index: *transaction_index,
+            };
+            batch.put(&log_key(level, index as u32), &serialize(&log).unwrap())?;
+        }
+        self.db.write(batch)?;
+        if !logs.is_empty() {
+            self.blooms.insert_blooms(level, std::iter::once(&bloom))?;
+        }
+        self.latest.store(level, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The last ledger level indexed.
+    pub fn latest_level(&self) -> u64 {
+        self.latest.load(Ordering::Relaxed)
+    }
+
+    /// The logs matching the filter, in ledger order. Only the levels whose bloom may match are
+    /// read.
+    pub fn logs(&self, filter: &LogFilter) -> Result<Vec<LocalizedLog>> {
+        let to_level = filter.to_level.unwrap_or_else(|| self.latest_level());
+        if filter.from_level > to_level {
+            return Ok(vec![]);
+        }
+        let from_level = filter.from_level;
+        let filter = filter.to_filter(to_level);
+        let possibilities = filter.bloom_possibilities();
+        let levels = self.blooms.filter(from_level, to_level, &possibilities)?;
+        let mut logs = vec![];
+        for level in levels {
+            let start = log_key(level, 0);
+            let iter = self.db.iterator(IteratorMode::From(&start, Direction::Forward));
+            for (key, value) in iter {
+                if key[..8] != start[..8] {
+                    break;
+                }
+                let log: LocalizedLog = deserialize(&value).unwrap();
+                let entry = LogEntry {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: vec![],
+                };
+                if filter.matches(&entry) {
+                    logs.push(log);
+                }
+            }
+        }
+        if let Some(limit) = filter.limit {
+            let skip = logs.len().saturating_sub(limit);
+            logs.drain(..skip);
+        }
+        Ok(logs)
+    }
+}
+
+fn log_key(level: u64, index: u32) -> Vec<u8> {
+    let mut key = level.to_be_bytes().to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Bytes as a hex string.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<u8>, D::Error> {
+        let s: String = Deserialize::deserialize(d)?;
+        hex::decode(s.trim_start_matches("0x")).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keccak_hash::keccak;
+
+    fn log(address: u64, topics: &[&str]) -> LogEntry {
+        LogEntry {
+            address: Address::from_low_u64_be(address),
+            topics: topics.iter().map(|t| keccak(t)).collect(),
+            data: vec![address as u8],
+        }
+    }
+
+    #[test]
+    fn insert_and_filter() {
+        let db = LogDatabase::new("/tmp/prism_test_logdb_insert_and_filter").unwrap();
+        let block = EthereumH256::from_low_u64_be(0xb);
+        let tx = EthereumH256::from_low_u64_be(0x7);
+        db.insert_level(1, &[(block, tx, 0, log(0xa, &["Transfer", "alice", "bob"]))])
+            .unwrap();
+        db.insert_level(2, &[]).unwrap();
+        db.insert_level(
+            3,
+            &[
+                (block, tx, 0, log(0xa, &["Approval", "alice", "bob"])),
+                (block, tx, 1, log(0xc, &["Transfer", "bob", "alice"])),
+                (block, tx, 1, log(0xa, &["Transfer", "bob", "carol"])),
+            ],
+        )
+        .unwrap();
+        assert_eq!(db.latest_level(), 3);
+
+        // everything
+        let logs = db.logs(&LogFilter::default()).unwrap();
+        assert_eq!(logs.len(), 4);
+        assert_eq!((logs[3].level, logs[3].log_index), (3, 2));
+        assert_eq!(logs[3].data, vec![0xa]);
+
+        // transfers of one token
+        let transfers = LogFilter {
+            addresses: Some(vec![Address::from_low_u64_be(0xa)]),
+            topics: vec![Some(vec![keccak("Transfer")])],
+            ..Default::default()
+        };
+        let logs = db.logs(&transfers).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].level, 1);
+        assert_eq!(logs[1].topics[2], keccak("carol"));
+
+        // by the second topic, in a range of levels
+        let from_bob = LogFilter {
+            from_level: 2,
+            topics: vec![None, Some(vec![keccak("bob")])],
+            ..Default::default()
+        };
+        let logs = db.logs(&from_bob).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().all(|l| l.level == 3));
+        let until_two = LogFilter {
+            to_level: Some(2),
+            ..transfers.clone()
+        };
+        assert_eq!(db.logs(&until_two).unwrap().len(), 1);
+
+        // the last ones
+        let last = LogFilter {
+            limit: Some(1),
+            ..transfers
+        };
+        let logs = db.logs(&last).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].topics[2], keccak("carol"));
+
+        let nobody = LogFilter {
+            addresses: Some(vec![Address::from_low_u64_be(0xd)]),
+            ..Default::default()
+        };
+        assert!(db.logs(&nobody).unwrap().is_empty());
+
+        // the log survives the JSON format
+        let json = serde_json::to_string(&logs[0]).unwrap();
+        let decoded: LocalizedLog = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, logs[0]);
+    }
+}
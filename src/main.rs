@@ -1,18 +1,24 @@
 #[macro_use]
 extern crate clap;
 
+use clap::{Arg, SubCommand};
 use crossbeam::channel;
+use ethereum_types::U256;
 use parity_crypto::publickey::{KeyPair, Random, Secret, Public, Generator};
 use log::{debug, error, info};
 use prism::api::Server as ApiServer;
+use prism::config::ConfirmationRule;
 use prism::blockchain::BlockChain;
 use prism::blockdb::BlockDatabase;
+use prism::chainspec::ChainSpec;
 use prism::config::BlockchainConfig;
 use prism::crypto::hash::{H256, Address};
 use prism::experiment::transaction_generator::TransactionGenerator;
 use prism::ledger_manager::LedgerManager;
 use prism::miner;
 use prism::miner::memory_pool::MemoryPool;
+use prism::miner::mempool_journal::MempoolJournal;
+use prism::network::message::Message;
 use prism::network::server;
 use prism::network::worker;
 use prism::visualization::Server as VisualizationServer;
@@ -24,7 +30,8 @@ use std::sync::Arc;
 use std::thread;
 use std::time;
 
-use prism::statedb::StateDatabase;
+use prism::logdb::LogDatabase;
+use prism::statedb::{SnapshotSync, StateDatabase, StateFormat};
 
 fn main() {
     // parse command line arguments
@@ -38,28 +45,99 @@ fn main() {
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg block_db: --blockdb [PATH] default_value("/tmp/prism-blocks.rocksdb") "Sets the path to the block database")
      (@arg blockchain_db: --blockchaindb [PATH] default_value("/tmp/prism-blockchain.rocksdb") "Sets the path to the blockchain database")
+     (@arg reindex: --reindex "Keeps the block database and rebuilds the blockchain database from it")
      (@arg wallet_db: --walletdb [PATH] default_value("/tmp/prism-wallet.rocksdb") "Sets the path to the wallet database")
      (@arg init_fund_addr: --("fund-addr") ... [ADDR] "Endows the given address an initial fund in the genesis block")
      (@arg init_fund_coins: --("fund-coins") [INT] default_value("50000") "Sets the number of initial coins for each address")
      (@arg load_key_path: --("load-key") ... [PATH] "Loads a key pair into the wallet from the given path")
      (@arg mempool_size: --("mempool-size") [INT] default_value("500000") "Sets the maximum number of transactions for the memory pool")
-     (@arg execution_workers: --("execution-workers") [INT] default_value("8") "Sets the number of worker threads for transaction execution")//not used for EVM
+     (@arg mempool_db: --mempooldb [PATH] "Enables the memory pool journal and sets the path to its database")
+     (@arg mempool_flush_interval: --("mempool-flush-interval") [INT] default_value("1000") "Sets the interval in milliseconds between memory pool journal flushes")
+     (@arg execution_workers: --("execution-workers") [INT] default_value("8") "Sets the number of worker threads for decoding and executing transactions")
      (@arg execution_buffer: --("execution-buffer") [INT] default_value("10") "Sets the size of the buffer between pipeline stages in transaction execution")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("8") "Sets the number of worker threads for P2P server")
-     (@arg voter_chains: --("voter-chains") [INT] default_value("1000") "Sets the number of voter chains")
-     (@arg tx_throughput: --("tx-throughput") [INT] default_value("80000") "Sets the target transaction throughput")
-     (@arg tx_block_size: --("tx-block-size") [INT] default_value("64000") "Sets the maximum size of the transaction block in Bytes")
-     (@arg proposer_mining_rate: --("proposer-mining-rate") [FLOAT] default_value("0.1") "Sets the proposer chain mining rate")
-     (@arg voter_mining_rate: --("voter-mining-rate") [FLOAT] default_value("0.1") "Sets the voter chain mining rate")
-     (@arg adv_ratio: --("adversary-ratio") [FLOAT] default_value("0.4") "Sets the ratio of adversary hashing power")
-     (@arg log_epsilon: --("confirm-confidence") [FLOAT] default_value("20.0") "Sets -log(epsilon) for confirmation")
+     (@arg mining_threads: --("mining-threads") [INT] default_value("0") "Sets the number of threads searching for nonces, 0 to try one nonce per miner loop iteration")
+     (@arg chain_spec: --chain [PATH] "Loads the chain specification from the given file, defaults to the built-in test network")
+     (@arg voter_chains: --("voter-chains") [INT] "Sets the number of voter chains, overriding the chain specification")
+     (@arg tx_throughput: --("tx-throughput") [INT] "Sets the target transaction throughput, overriding the chain specification")
+     (@arg tx_block_size: --("tx-block-size") [INT] "Sets the maximum size of the transaction block in Bytes, overriding the chain specification")
+     (@arg proposer_mining_rate: --("proposer-mining-rate") [FLOAT] "Sets the proposer chain mining rate, overriding the chain specification")
+     (@arg voter_mining_rate: --("voter-mining-rate") [FLOAT] "Sets the voter chain mining rate, overriding the chain specification")
+     (@arg adv_ratio: --("adversary-ratio") [FLOAT] "Sets the ratio of adversary hashing power, overriding the chain specification")
+     (@arg log_epsilon: --("confirm-confidence") [FLOAT] "Sets -log(epsilon) for confirmation, overriding the chain specification")
+     (@arg proposer_reward: --("proposer-reward") [INT] "Sets the reward in Wei for a confirmed proposer block, overriding the chain specification")
+     (@arg voter_reward: --("voter-reward") [INT] "Sets the reward in Wei for a main chain vote on a confirmed proposer block, overriding the chain specification")
+     (@arg tx_reward: --("tx-reward") [INT] "Sets the reward in Wei for a confirmed transaction block, overriding the chain specification")
+     (@arg tx_block_gas_limit: --("tx-block-gas-limit") [INT] "Sets the maximum total gas of the transactions in a transaction block, overriding the chain specification")
+     (@arg gas_price: --("gas-price") [INT] default_value("0") "Sets the gas price in Wei of the transactions signed by the wallet")
+     (@arg tx_gas: --("tx-gas") [INT] default_value("10000000") "Sets the gas limit of the transactions signed by the wallet")
+     (@arg confirmation_rule: --("confirmation-rule") [RULE] default_value("lcb") "Sets the rule deciding the leader of each proposer level: lcb, k-deep or longest-chain")
+     (@arg confirmation_depth: --("confirmation-depth") [INT] default_value("6") "Sets the depth used by the k-deep and longest-chain confirmation rules")
+     (@arg fast_confirm: --("fast-confirm") "Fast confirms transaction blocks that are in the ledger of every possible proposer leader")
+     (@arg prune_depth: --prune [INT] "Prunes blockchain metadata and transaction block bodies this many proposer levels behind the ledger tip, keeps everything if not set")
+     (@arg miner_addr: --("miner-addr") [ADDR] "Sets the address receiving mining rewards and fees, defaults to the first wallet address")
      (@arg utxo_db: --utxodb [PATH] default_value("/tmp/prism-utxo.rocksdb") "Sets the path to the UTXO database")//use it for statedb
+     (@arg log_db: --logdb [PATH] default_value("/tmp/prism-logs") "Sets the path to the database indexing the logs of executed transactions")
+     (@arg snapshot_interval: --("snapshot-interval") [INT] "Takes a snapshot of the state every this many ledger levels and serves it to syncing peers")
+     (@arg snapshot_sync: --("snapshot-sync") "Restores the state from the snapshot of a peer at the given level and root, and executes the ledger from there")
+     (@arg snapshot_level: --("snapshot-level") [INT] "Sets the ledger level of the snapshot to restore with --snapshot-sync")
+     (@arg snapshot_root: --("snapshot-root") [HASH] "Sets the state root of the snapshot to restore with --snapshot-sync, as taken by a trusted node")
+     (@arg keep_state: --("keep-state") "Keeps the state database, such as one seeded by state import, and starts from its genesis state")
 
      (@subcommand keygen =>
       (about: "Generates Prism wallet key pair")
       (@arg display_address: --addr "Prints the address of the key pair to STDERR")
      )
     )
+    .subcommand(
+        SubCommand::with_name("check-db")
+            .about("Checks the blockchain database against the blocks in the block database")
+            .arg(Arg::with_name("repair").long("repair").help("Fixes the discrepancies found"))
+            .arg(
+                Arg::with_name("scratch_db")
+                    .long("scratchdb")
+                    .value_name("PATH")
+                    .default_value("/tmp/prism-check-blockchain.rocksdb")
+                    .help("Sets the path to the scratch database the blocks are replayed into"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("state")
+            .about("Exports or imports the account state of the state database")
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("Writes the accounts at a committed state root to a file")
+                    .arg(Arg::with_name("file").required(true).value_name("PATH").help("Sets the path of the state file"))
+                    .arg(
+                        Arg::with_name("format")
+                            .long("format")
+                            .value_name("FORMAT")
+                            .default_value("json")
+                            .help("Sets the format of the state file: json or binary"),
+                    )
+                    .arg(
+                        Arg::with_name("level")
+                            .long("level")
+                            .value_name("INT")
+                            .required_unless("root")
+                            .conflicts_with("root")
+                            .help("Exports the state after the given ledger level was executed"),
+                    )
+                    .arg(Arg::with_name("root").long("root").value_name("HASH").help("Exports the state at the given root")),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("Creates the state database with the accounts in a file as its genesis state, to be started with --keep-state")
+                    .arg(Arg::with_name("file").required(true).value_name("PATH").help("Sets the path of the state file"))
+                    .arg(
+                        Arg::with_name("format")
+                            .long("format")
+                            .value_name("FORMAT")
+                            .default_value("json")
+                            .help("Sets the format of the state file: json or binary"),
+                    ),
+            ),
+    )
     .get_matches();
 
     // match subcommands
@@ -82,72 +160,119 @@ fn main() {
     let verbosity = matches.occurrences_of("verbose") as usize;
     stderrlog::new().verbosity(verbosity).init().unwrap();
 
-    // init config struct
-    let voter_chains: u16 = matches
-        .value_of("voter_chains")
-        .unwrap()
-        .parse()
-        .unwrap_or_else(|e| {
+    // load the chain specification
+    let mut chain_spec = match matches.value_of("chain_spec") {
+        Some(path) => ChainSpec::load(path).unwrap_or_else(|e| {
+            error!("Error loading chain specification from {}: {}", path, e);
+            process::exit(1);
+        }),
+        None => ChainSpec::prism_test(),
+    };
+    info!("Loaded chain specification of network {}", chain_spec.name);
+
+    // init config struct, consensus parameters given on the command line override the spec
+    if let Some(value) = matches.value_of("voter_chains") {
+        chain_spec.consensus.voter_chains = value.parse().unwrap_or_else(|e| {
             error!("Error parsing voter chain number: {}", e);
             process::exit(1);
         });
-    let tx_throughput = matches
-        .value_of("tx_throughput")
-        .unwrap()
-        .parse::<u32>()
-        .unwrap_or_else(|e| {
+    }
+    if let Some(value) = matches.value_of("tx_throughput") {
+        chain_spec.consensus.tx_throughput = value.parse().unwrap_or_else(|e| {
             error!("Error parsing target transaction throughput: {}", e);
             process::exit(1);
         });
-    let tx_blk_size = matches
-        .value_of("tx_block_size")
-        .unwrap()
-        .parse::<u32>()
-        .unwrap_or_else(|e| {
+    }
+    if let Some(value) = matches.value_of("tx_block_size") {
+        chain_spec.consensus.tx_block_size = value.parse().unwrap_or_else(|e| {
             error!("Error parsing transaction block size: {}", e);
             process::exit(1);
         });
-    let proposer_mining_rate = matches
-        .value_of("proposer_mining_rate")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
+    }
+    if let Some(value) = matches.value_of("proposer_mining_rate") {
+        chain_spec.consensus.proposer_mining_rate = value.parse().unwrap_or_else(|e| {
             error!("Error parsing proposer chain mining rate: {}", e);
             process::exit(1);
         });
-    let voter_mining_rate = matches
-        .value_of("voter_mining_rate")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
+    }
+    if let Some(value) = matches.value_of("voter_mining_rate") {
+        chain_spec.consensus.voter_mining_rate = value.parse().unwrap_or_else(|e| {
             error!("Error parsing voter chain mining rate: {}", e);
             process::exit(1);
         });
-    let adv_ratio = matches
-        .value_of("adv_ratio")
-        .unwrap()
-        .parse::<f32>()
-        .unwrap_or_else(|e| {
+    }
+    if let Some(value) = matches.value_of("adv_ratio") {
+        chain_spec.consensus.adversary_ratio = value.parse().unwrap_or_else(|e| {
             error!("Error parsing adversary power ratio: {}", e);
             process::exit(1);
         });
-    let log_epsilon = matches
-        .value_of("log_epsilon")
+    }
+    if let Some(value) = matches.value_of("log_epsilon") {
+        chain_spec.consensus.confirm_confidence = value.parse().unwrap_or_else(|e| {
+            error!("Error parsing confirm confidence: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(value) = matches.value_of("proposer_reward") {
+        chain_spec.consensus.proposer_reward = value.parse().unwrap_or_else(|e| {
+            error!("Error parsing proposer block reward: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(value) = matches.value_of("voter_reward") {
+        chain_spec.consensus.voter_reward = value.parse().unwrap_or_else(|e| {
+            error!("Error parsing voter block reward: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(value) = matches.value_of("tx_reward") {
+        chain_spec.consensus.tx_reward = value.parse().unwrap_or_else(|e| {
+            error!("Error parsing transaction block reward: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(value) = matches.value_of("tx_block_gas_limit") {
+        chain_spec.consensus.tx_block_gas_limit = Some(value.parse().unwrap_or_else(|e| {
+            error!("Error parsing transaction block gas limit: {}", e);
+            process::exit(1);
+        }));
+    }
+    let confirmation_depth = matches
+        .value_of("confirmation_depth")
         .unwrap()
-        .parse::<f32>()
+        .parse::<u64>()
         .unwrap_or_else(|e| {
-            error!("Error parsing confirm confidence: {}", e);
+            error!("Error parsing confirmation depth: {}", e);
             process::exit(1);
         });
-    let config = BlockchainConfig::new(
-        voter_chains,
-        tx_blk_size,
-        tx_throughput,
-        proposer_mining_rate,
-        voter_mining_rate,
-        adv_ratio,
-        log_epsilon,
-    );
+    let confirmation_rule = ConfirmationRule::from_name(
+        matches.value_of("confirmation_rule").unwrap(),
+        confirmation_depth,
+    )
+    .unwrap_or_else(|| {
+        error!("Unknown confirmation rule, expecting lcb, k-deep or longest-chain");
+        process::exit(1);
+    });
+    let prune_depth = matches.value_of("prune_depth").map(|depth| {
+        depth.parse::<u64>().unwrap_or_else(|e| {
+            error!("Error parsing pruning depth: {}", e);
+            process::exit(1);
+        })
+    });
+    let snapshot_interval = matches.value_of("snapshot_interval").map(|interval| {
+        match interval.parse::<u64>() {
+            Ok(interval) if interval > 0 => interval,
+            _ => {
+                error!("Error parsing snapshot interval {}, expecting a positive integer", interval);
+                process::exit(1);
+            }
+        }
+    });
+    let config = BlockchainConfig::from_spec(&chain_spec.consensus)
+        .with_fast_confirm(matches.is_present("fast_confirm"))
+        .with_confirmation_rule(confirmation_rule)
+        .with_prune_depth(prune_depth)
+        .with_snapshot_interval(snapshot_interval);
     info!(
         "Proposer block mining rate set to {} blks/s",
         config.proposer_mining_rate
@@ -168,6 +293,145 @@ fn main() {
         "Proposer block have {} transaction refs on max",
         config.proposer_tx_refs
     );
+    info!(
+        "Block rewards set to {} (proposer), {} (voter), {} (transaction) Wei",
+        config.proposer_reward, config.voter_reward, config.tx_reward
+    );
+    if let Some(gas_limit) = config.tx_block_gas_limit {
+        info!("Transaction block gas limit set to {}", gas_limit);
+    }
+    info!("Confirmation rule set to {}", config.confirmation_rule);
+    if config.fast_confirm {
+        info!("Fast confirmation of transaction blocks enabled");
+    }
+    match config.prune_depth {
+        Some(depth) => info!("Pruning data {} proposer levels behind the ledger tip", depth),
+        None => info!("Running in archive mode"),
+    }
+    if let Some(interval) = config.snapshot_interval {
+        info!("Taking a snapshot of the state every {} ledger levels", interval);
+    }
+
+    // check the existing databases instead of starting the client
+    if let ("check-db", Some(m)) = matches.subcommand() {
+        let blockdb = BlockDatabase::load(&matches.value_of("block_db").unwrap(), config.clone())
+            .unwrap_or_else(|e| {
+                error!("Error opening block database: {}", e);
+                process::exit(1);
+            });
+        let report = BlockChain::check(
+            &matches.value_of("blockchain_db").unwrap(),
+            &m.value_of("scratch_db").unwrap(),
+            &blockdb,
+            config.clone(),
+            m.is_present("repair"),
+        )
+        .unwrap_or_else(|e| {
+            error!("Error checking blockchain database: {}", e);
+            process::exit(1);
+        });
+        for discrepancy in &report.discrepancies {
+            println!("{}", discrepancy);
+        }
+        info!(
+            "Checked {} blocks ({} invalid, {} with missing references), {} discrepancies found",
+            report.replay.replayed_blocks
+                + report.replay.invalid_blocks
+                + report.replay.unresolved_blocks,
+            report.replay.invalid_blocks,
+            report.replay.unresolved_blocks,
+            report.discrepancies.len()
+        );
+        if report.repaired {
+            info!("Blockchain database repaired");
+        } else if !report.discrepancies.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    // export or import the state instead of starting the client
+    if let ("state", Some(m)) = matches.subcommand() {
+        let utxo_db = matches.value_of("utxo_db").unwrap();
+        match m.subcommand() {
+            ("export", Some(m)) => {
+                let format = m.value_of("format").unwrap().parse::<StateFormat>().unwrap_or_else(|e| {
+                    error!("Error parsing state file format: {}", e);
+                    process::exit(1);
+                });
+                if !std::path::Path::new(utxo_db).is_dir() {
+                    error!("State database {} not found", utxo_db);
+                    process::exit(1);
+                }
+                let statedb = StateDatabase::open(utxo_db, None, &chain_spec).unwrap_or_else(|e| {
+                    error!("Error opening state database: {}", e);
+                    process::exit(1);
+                });
+                let root = match (m.value_of("level"), m.value_of("root")) {
+                    (Some(level), _) => {
+                        let level = level.parse::<u64>().unwrap_or_else(|e| {
+                            error!("Error parsing ledger level: {}", e);
+                            process::exit(1);
+                        });
+                        statedb.level_root(level).unwrap().unwrap_or_else(|| {
+                            error!("Ledger level {} not executed", level);
+                            process::exit(1);
+                        })
+                    }
+                    (None, Some(root)) => {
+                        let raw: [u8; 32] = hex::decode(root)
+                            .ok()
+                            .and_then(|raw| raw.as_slice().try_into().ok())
+                            .unwrap_or_else(|| {
+                                error!("Error parsing state root {}", root);
+                                process::exit(1);
+                            });
+                        H256::from(raw).into()
+                    }
+                    (None, None) => unreachable!(),
+                };
+                let path = m.value_of("file").unwrap();
+                let file = std::fs::File::create(path).unwrap_or_else(|e| {
+                    error!("Error creating state file {}: {}", path, e);
+                    process::exit(1);
+                });
+                statedb
+                    .export(&root, format, std::io::BufWriter::new(file))
+                    .unwrap_or_else(|e| {
+                        error!("Error exporting state: {}", e);
+                        process::exit(1);
+                    });
+                info!("Exported the state at root {:?} to {}", root, path);
+            }
+            ("import", Some(m)) => {
+                let format = m.value_of("format").unwrap().parse::<StateFormat>().unwrap_or_else(|e| {
+                    error!("Error parsing state file format: {}", e);
+                    process::exit(1);
+                });
+                let path = m.value_of("file").unwrap();
+                let file = std::fs::File::open(path).unwrap_or_else(|e| {
+                    error!("Error opening state file {}: {}", path, e);
+                    process::exit(1);
+                });
+                let statedb = StateDatabase::new(utxo_db, &chain_spec).unwrap_or_else(|e| {
+                    error!("Error creating state database: {}", e);
+                    process::exit(1);
+                });
+                let root = statedb
+                    .import(format, std::io::BufReader::new(file))
+                    .unwrap_or_else(|e| {
+                        error!("Error importing state: {}", e);
+                        process::exit(1);
+                    });
+                info!("Imported the state at root {:?} from {}", root, path);
+            }
+            _ => {
+                error!("Missing state subcommand, export or import");
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
     // init mempool
     let mempool_size = matches
@@ -183,25 +447,83 @@ fn main() {
     debug!("Initialized mempool, maximum size set to {}", mempool_size);
 
     // init block database
-    let blockdb =
-        BlockDatabase::new(&matches.value_of("block_db").unwrap(), config.clone()).unwrap();
+    let reindex = matches.is_present("reindex");
+    let blockdb = if reindex {
+        BlockDatabase::load(&matches.value_of("block_db").unwrap(), config.clone()).unwrap()
+    } else {
+        BlockDatabase::new(&matches.value_of("block_db").unwrap(), config.clone()).unwrap()
+    };
     let blockdb = Arc::new(blockdb);
     debug!("Initialized block database");
  
-    let statedb = StateDatabase::new(&matches.value_of("utxo_db").unwrap()).unwrap();
+    let statedb = if matches.is_present("keep_state") {
+        StateDatabase::open(&matches.value_of("utxo_db").unwrap(), None, &chain_spec).unwrap()
+    } else {
+        StateDatabase::new(&matches.value_of("utxo_db").unwrap(), &chain_spec).unwrap()
+    };
     let statedb = Arc::new(statedb);
     debug!("Initialized state database");
 
-    // init blockchain database
-    let blockchain =
-        BlockChain::new(&matches.value_of("blockchain_db").unwrap(), config.clone()).unwrap();
+    // reload the memory pool from the journal and start journaling, before any thread uses the
+    // memory pool
+    if let Some(path) = matches.value_of("mempool_db") {
+        let flush_interval = matches
+            .value_of("mempool_flush_interval")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap_or_else(|e| {
+                error!("Error parsing memory pool flush interval: {}", e);
+                process::exit(1);
+            });
+        let journal = MempoolJournal::load(path).unwrap_or_else(|e| {
+            error!("Error opening memory pool journal at {}: {}", path, e);
+            process::exit(1);
+        });
+        let restored = journal.restore(&mempool, &statedb).unwrap_or_else(|e| {
+            error!("Error restoring memory pool journal at {}: {}", path, e);
+            process::exit(1);
+        });
+        info!("Restored {} transactions from memory pool journal", restored);
+        journal.start(&mempool, flush_interval);
+    }
+
+    let logdb = LogDatabase::new(&matches.value_of("log_db").unwrap()).unwrap();
+    let logdb = Arc::new(logdb);
+    debug!("Initialized log database");
+
+    // init blockchain database, the ledger of a reindexed one is recomputed by the ledger manager
+    let blockchain = if reindex {
+        let (blockchain, _) = BlockChain::reindex(
+            &matches.value_of("blockchain_db").unwrap(),
+            &blockdb,
+            config.clone(),
+        )
+        .unwrap();
+        blockchain
+    } else {
+        BlockChain::new(&matches.value_of("blockchain_db").unwrap(), config.clone()).unwrap()
+    };
     let blockchain = Arc::new(blockchain);
     debug!("Initialized blockchain database");
 
     // init wallet database
     let wallet = Wallet::new(&matches.value_of("wallet_db").unwrap()).unwrap();
+    let gas_price = U256::from_dec_str(matches.value_of("gas_price").unwrap()).unwrap_or_else(|e| {
+        error!("Error parsing gas price: {:?}", e);
+        process::exit(1);
+    });
+    let tx_gas = U256::from_dec_str(matches.value_of("tx_gas").unwrap()).unwrap_or_else(|e| {
+        error!("Error parsing transaction gas limit: {:?}", e);
+        process::exit(1);
+    });
+    wallet.set_gas(gas_price, tx_gas);
+    let chain_id = chain_spec.chain_id().unwrap_or_else(|e| {
+        error!("Error reading chain ID: {}", e);
+        process::exit(1);
+    });
+    wallet.set_chain_id(Some(chain_id));
     let wallet = Arc::new(wallet);
-    debug!("Initialized wallet");
+    debug!("Initialized wallet, signing with gas price {} and gas limit {}", gas_price, tx_gas);
 
     // load wallet keys
     if let Some(wallet_keys) = matches.values_of("load_key_path") {
@@ -254,13 +576,6 @@ fn main() {
             error!("Error parsing transaction execution buffer size: {}", e);
             process::exit(1);
         });
-    let ledger_manager = LedgerManager::new(&blockdb, &blockchain, &statedb, &wallet);
-    ledger_manager.start(tx_buffer);
-    debug!(
-        "Initialized ledger manager with buffer size {}",
-        tx_buffer 
-    );
-
     // parse p2p server address
     let p2p_addr = matches
         .value_of("peer_addr")
@@ -290,6 +605,44 @@ fn main() {
     let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
     server_ctx.start().unwrap();
 
+    // a fresh node restores the state from a snapshot before executing the ledger. Blocks do
+    // not commit to the state, so the snapshot to restore is given by the operator
+    let snapshot_sync = if matches.is_present("snapshot_sync") {
+        let (level, root) = match (matches.value_of("snapshot_level"), matches.value_of("snapshot_root")) {
+            (Some(level), Some(root)) => (level, root),
+            _ => {
+                error!("Snapshot sync needs the level and the state root of the snapshot");
+                process::exit(1);
+            }
+        };
+        let level = level.parse::<u64>().unwrap_or_else(|e| {
+            error!("Error parsing snapshot level: {}", e);
+            process::exit(1);
+        });
+        let raw: [u8; 32] = hex::decode(root.trim_start_matches("0x"))
+            .ok()
+            .and_then(|raw| raw.as_slice().try_into().ok())
+            .unwrap_or_else(|| {
+                error!("Error parsing snapshot root {}", root);
+                process::exit(1);
+            });
+        let root = H256::from(raw);
+        info!("Syncing the state from the snapshot at level {} with root {}", level, root);
+        Some(Arc::new(SnapshotSync::new(&statedb, level, root.into())))
+    } else {
+        None
+    };
+    let mut ledger_manager =
+        LedgerManager::new(&blockdb, &blockchain, &statedb, &logdb, &wallet, &server, config.clone());
+    if let Some(sync) = &snapshot_sync {
+        ledger_manager = ledger_manager.with_snapshot_sync(sync);
+    }
+    ledger_manager.start(tx_buffer, tx_workers);
+    debug!(
+        "Initialized ledger manager with buffer size {} and {} execution workers",
+        tx_buffer, tx_workers
+    );
+
     // start the worker
     let p2p_workers = matches
         .value_of("p2p_workers")
@@ -304,6 +657,8 @@ fn main() {
         msg_rx,
         &blockchain,
         &blockdb,
+        &statedb,
+        snapshot_sync.as_ref(),
         &wallet,
         &mempool,
         ctx_tx,
@@ -312,7 +667,43 @@ fn main() {
     );
     worker_ctx.start();
 
+    // create wallet key pair if there is none
+    if wallet.addresses().unwrap().is_empty() {
+        wallet.generate_keypair().unwrap();
+    }
+
+    // parse the address receiving mining rewards
+    let miner_addr: Address = match matches.value_of("miner_addr") {
+        Some(addr) => {
+            let decoded = match hex::decode(&addr.trim()) {
+                Ok(d) => d,
+                Err(e) => {
+                    error!("Error decoding miner address {}: {}", &addr.trim(), e);
+                    process::exit(1);
+                }
+            };
+            let addr_bytes: [u8; 20] = match (&decoded[..]).try_into() {
+                Ok(b) => b,
+                Err(_) => {
+                    error!("Miner address {} is not 20 bytes long", &addr.trim());
+                    process::exit(1);
+                }
+            };
+            addr_bytes.into()
+        }
+        None => wallet.addresses().unwrap()[0],
+    };
+    info!("Mining rewards go to address {:?}", &miner_addr);
+
     // start the miner
+    let mining_threads = matches
+        .value_of("mining_threads")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing mining threads: {}", e);
+            process::exit(1);
+        });
     let (miner_ctx, miner) = miner::new(
         &mempool,
         &blockchain,
@@ -320,6 +711,8 @@ fn main() {
         ctx_rx,
         &ctx_tx_miner,
         &server,
+        miner_addr,
+        mining_threads,
         config.clone(),
     );
     miner_ctx.start();
@@ -357,17 +750,28 @@ fn main() {
         });
     }
 
-    // fund the given addresses
+    // ask the peers for their latest snapshot until the state is restored or the sync is given up
+    if let Some(sync) = snapshot_sync {
+        let server = server.clone();
+        thread::spawn(move || {
+            while !sync.is_finished() {
+                server.broadcast(Message::GetSnapshotManifest);
+                thread::sleep(time::Duration::from_secs(5));
+            }
+        });
+    }
+
+    // fund the allocations of the chain specification and the given addresses
+    let num_coins = matches
+        .value_of("init_fund_coins")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing number of initial fund coins: {}", e);
+            process::exit(1);
+        });
+    let mut addrs = vec![];
     if let Some(fund_addrs) = matches.values_of("init_fund_addr") {
-        let num_coins = matches
-            .value_of("init_fund_coins")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap_or_else(|e| {
-                error!("Error parsing number of initial fund coins: {}", e);
-                process::exit(1);
-            });
-        let mut addrs = vec![];
         for addr in fund_addrs {
             let decoded = match hex::decode(&addr.trim()) {
                 Ok(d) => d,
@@ -380,6 +784,8 @@ fn main() {
             let hash: Address = addr_bytes.into();
             addrs.push(hash);
         }
+    }
+    if !addrs.is_empty() {
         info!(
             "Funding {} addresses with {} initial coins",
             addrs.len(),
@@ -388,21 +794,18 @@ fn main() {
         prism::experiment::ico(&addrs, &statedb, &wallet, num_coins).unwrap();
     }
 
-    // create wallet key pair if there is none
-    if wallet.addresses().unwrap().is_empty() {
-        wallet.generate_keypair().unwrap();
-    }
-
     // start the transaction generator in multiple threads
-    let (txgen_ctx, txgen_control_chan) = TransactionGenerator::new(&wallet, &server, &mempool);
+    let (txgen_ctx, txgen_control_chan) = TransactionGenerator::new(&wallet, &server, &mempool, &chain_spec);
     txgen_ctx.start();
 
     // start the API server
     ApiServer::start(
         api_addr,
         &statedb,
+        &logdb,
         &wallet,
         &blockchain,
+        &blockdb,
         &server,
         &miner,
         &mempool,
@@ -0,0 +1,235 @@
+use super::get_time;
+use crate::block::header::Header;
+use crate::crypto::hash::{Hashable, H256};
+use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::info;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time;
+
+/// Number of nonces a hashing thread tries before checking whether its work is still current.
+const HASH_BATCH: u64 = 1024;
+/// Minimum interval between two updates of the hash rate.
+const HASH_RATE_WINDOW: time::Duration = time::Duration::from_secs(1);
+
+/// A header whose hash is below the difficulty, found by a hashing thread.
+pub struct Solution {
+    /// The generation of the work this solution belongs to.
+    pub generation: u64,
+    /// The header with the winning nonce and timestamp filled in.
+    pub header: Header,
+    /// The hash of the header.
+    pub hash: H256,
+}
+
+/// The work shared by all hashing threads.
+struct Work {
+    /// Incremented every time the work changes, so that threads can drop outdated work.
+    generation: u64,
+    /// The header to search nonces for, None if the threads should idle.
+    header: Option<Header>,
+}
+
+struct Shared {
+    work: Mutex<Work>,
+    work_available: Condvar,
+    /// Copy of the current generation for the threads to poll without locking.
+    generation: AtomicU64,
+    /// Hashes computed since the start of the hash rate window.
+    window_hashes: AtomicUsize,
+    window_start: Mutex<time::Instant>,
+}
+
+/// A pool of threads searching disjoint nonce ranges over a snapshot of the header.
+pub struct HashingPool {
+    shared: Arc<Shared>,
+    solution_chan: Receiver<Solution>,
+}
+
+impl HashingPool {
+    /// Start the given number of hashing threads, initially idle.
+    pub fn new(num_threads: usize) -> Self {
+        let shared = Arc::new(Shared {
+            work: Mutex::new(Work {
+                generation: 0,
+                header: None,
+            }),
+            work_available: Condvar::new(),
+            generation: AtomicU64::new(0),
+            window_hashes: AtomicUsize::new(0),
+            window_start: Mutex::new(time::Instant::now()),
+        });
+        let (solution_tx, solution_rx) = unbounded();
+        // split the nonce space into disjoint ranges, one per thread
+        let span: u64 = (u64::from(std::u32::MAX) + 1) / num_threads as u64;
+        for idx in 0..num_threads {
+            let shared = Arc::clone(&shared);
+            let solution_tx = solution_tx.clone();
+            let start = span * idx as u64;
+            let end = if idx == num_threads - 1 {
+                u64::from(std::u32::MAX) + 1
+            } else {
+                start + span
+            };
+            thread::Builder::new()
+                .name(format!("miner_hasher_{}", idx))
+                .spawn(move || {
+                    hashing_loop(&shared, start, end, &solution_tx);
+                })
+                .unwrap();
+        }
+        info!("Miner hashing pool started with {} threads", num_threads);
+        Self {
+            shared,
+            solution_chan: solution_rx,
+        }
+    }
+
+    /// Replace the current work with the given header. Returns the generation of the new work.
+    pub fn submit(&self, header: Header) -> u64 {
+        let mut work = self.shared.work.lock().unwrap();
+        work.generation += 1;
+        work.header = Some(header);
+        self.shared.generation.store(work.generation, Ordering::SeqCst);
+        self.shared.work_available.notify_all();
+        work.generation
+    }
+
+    /// Stop all hashing threads until new work is submitted.
+    pub fn pause(&self) {
+        let mut work = self.shared.work.lock().unwrap();
+        if work.header.is_some() {
+            work.generation += 1;
+            work.header = None;
+            self.shared.generation.store(work.generation, Ordering::SeqCst);
+        }
+    }
+
+    /// Channel through which the solutions are delivered.
+    pub fn solutions(&self) -> &Receiver<Solution> {
+        &self.solution_chan
+    }
+}
+
+fn hashing_loop(shared: &Shared, start: u64, end: u64, solution_chan: &Sender<Solution>) {
+    let mut last_generation: u64 = 0;
+    loop {
+        // wait for a new piece of work
+        let mut work = shared.work.lock().unwrap();
+        while work.header.is_none() || work.generation == last_generation {
+            work = shared.work_available.wait(work).unwrap();
+        }
+        let generation = work.generation;
+        let mut header = work.header.unwrap();
+        drop(work);
+        last_generation = generation;
+
+        'search: loop {
+            // refresh the timestamp every time we go through the nonce range
+            header.timestamp = get_time();
+            let mut nonce = start;
+            while nonce < end {
+                let batch_end = std::cmp::min(nonce + HASH_BATCH, end);
+                for n in nonce..batch_end {
+                    header.nonce = n as u32;
+                    let hash = header.hash();
+                    if hash < header.difficulty {
+                        record_hashes(shared, (n - nonce + 1) as usize);
+                        // claim the solution, so that only one is delivered per generation
+                        let mut work = shared.work.lock().unwrap();
+                        if work.generation == generation {
+                            work.generation += 1;
+                            work.header = None;
+                            shared.generation.store(work.generation, Ordering::SeqCst);
+                            solution_chan
+                                .send(Solution {
+                                    generation,
+                                    header,
+                                    hash,
+                                })
+                                .unwrap();
+                        }
+                        break 'search;
+                    }
+                }
+                record_hashes(shared, (batch_end - nonce) as usize);
+                nonce = batch_end;
+                // give up if the context has changed
+                if shared.generation.load(Ordering::SeqCst) != generation {
+                    break 'search;
+                }
+            }
+        }
+    }
+}
+
+fn record_hashes(shared: &Shared, num_hashes: usize) {
+    PERFORMANCE_COUNTER.record_hashes(num_hashes);
+    shared.window_hashes.fetch_add(num_hashes, Ordering::Relaxed);
+    // only one thread needs to update the hash rate
+    if let Ok(mut window_start) = shared.window_start.try_lock() {
+        let elapsed = window_start.elapsed();
+        if elapsed >= HASH_RATE_WINDOW {
+            let hashes = shared.window_hashes.swap(0, Ordering::Relaxed);
+            let rate = hashes as f64 / elapsed.as_secs_f64();
+            PERFORMANCE_COUNTER.record_hash_rate(rate as usize);
+            *window_start = time::Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_DIFFICULTY;
+    use crate::crypto::hash::Address;
+
+    fn header(difficulty: H256) -> Header {
+        Header::new(
+            H256::default(),
+            0,
+            0,
+            H256::default(),
+            [0; 32],
+            difficulty,
+            Address::zero(),
+        )
+    }
+
+    #[test]
+    fn find_solution() {
+        let pool = HashingPool::new(4);
+        // one in 256 hashes is below this difficulty
+        let mut difficulty: [u8; 32] = [255; 32];
+        difficulty[0] = 0;
+        let generation = pool.submit(header(difficulty.into()));
+        let solution = pool
+            .solutions()
+            .recv_timeout(time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(solution.generation, generation);
+        assert_eq!(solution.hash, solution.header.hash());
+        assert!(solution.hash < solution.header.difficulty);
+    }
+
+    #[test]
+    fn outdated_work_is_dropped() {
+        let pool = HashingPool::new(2);
+        // impossible to solve
+        let generation = pool.submit(header([0; 32].into()));
+        let new_generation = pool.submit(header(*DEFAULT_DIFFICULTY));
+        assert!(new_generation > generation);
+        let solution = pool
+            .solutions()
+            .recv_timeout(time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(solution.generation, new_generation);
+        pool.pause();
+        assert!(pool
+            .solutions()
+            .recv_timeout(time::Duration::from_millis(100))
+            .is_err());
+    }
+}
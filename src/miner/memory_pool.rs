@@ -17,6 +17,17 @@ pub struct MemoryPool {
     by_hash: HashMap<H256, Entry>,
     /// Storage for order by storage index, it is equivalent to FIFO
     by_storage_index: BTreeMap<u64, H256>,
+    /// Insertions and removals not yet flushed to the on-disk journal, None if journaling is off
+    journal: Option<Vec<JournalEntry>>,
+}
+
+/// A change to the memory pool, recorded so that it can be replayed from disk
+#[derive(Debug, Clone)]
+pub enum JournalEntry {
+    /// A transaction is inserted
+    Insert(Transaction),
+    /// A transaction with the given hash is removed
+    Remove(H256),
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +46,22 @@ impl MemoryPool {
             counter: 0,
             by_hash: HashMap::new(),
             by_storage_index: BTreeMap::new(),
+            journal: None,
+        }
+    }
+
+    /// Start recording insertions and removals for the on-disk journal.
+    pub fn enable_journal(&mut self) {
+        if self.journal.is_none() {
+            self.journal = Some(vec![]);
+        }
+    }
+
+    /// Take the journal entries recorded since the last call.
+    pub fn drain_journal(&mut self) -> Vec<JournalEntry> {
+        match &mut self.journal {
+            Some(journal) => std::mem::replace(journal, vec![]),
+            None => vec![],
         }
     }
 
@@ -45,6 +72,9 @@ impl MemoryPool {
         }
         // assumes no duplicates nor double spends
         let hash = <Transaction as Hashable>::hash(&tx);
+        if let Some(journal) = &mut self.journal {
+            journal.push(JournalEntry::Insert(tx.clone()));
+        }
         let entry = Entry {
             transaction: tx,
             storage_index: self.counter,
@@ -75,6 +105,9 @@ impl MemoryPool {
         let entry = self.by_hash.remove(hash)?;
         self.by_storage_index.remove(&entry.storage_index);
         self.num_transactions -= 1;
+        if let Some(journal) = &mut self.journal {
+            journal.push(JournalEntry::Remove(*hash));
+        }
         Some(entry)
     }
 
@@ -92,6 +125,26 @@ impl MemoryPool {
             .collect()
     }
 
+    /// get n transaction by fifo order, skipping the ones rejected by the filter. The filter
+    /// returns None to stop the scan, when no later transaction could be accepted.
+    pub fn get_transactions_filtered<F>(&self, n: u32, mut filter: F) -> Vec<Transaction>
+    where
+        F: FnMut(&Transaction) -> Option<bool>,
+    {
+        self.by_storage_index
+            .values()
+            .map(|hash| &self.get(hash).unwrap().transaction)
+            .map(|tx| filter(tx).map(|accept| (tx, accept)))
+            .take_while(Option::is_some)
+            .filter_map(|x| match x {
+                Some((tx, true)) => Some(tx),
+                _ => None,
+            })
+            .take(n as usize)
+            .cloned()
+            .collect()
+    }
+
     /// get size/length
     pub fn len(&self) -> usize {
         self.by_hash.len()
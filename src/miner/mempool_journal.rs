@@ -0,0 +1,196 @@
+use super::memory_pool::{JournalEntry, MemoryPool};
+use crate::crypto::hash::{Hashable, H256};
+use crate::statedb::StateDatabase;
+use crate::transaction::Transaction;
+use crate::validation::{self, BlockResult};
+use bincode::{deserialize, serialize};
+use log::{debug, info, warn};
+use rocksdb::{self, ColumnFamilyDescriptor, IteratorMode, Options, WriteBatch, DB};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+const TRANSACTION_CF: &str = "TRANSACTION"; // hash to sequence number and transaction (u64, Transaction)
+
+pub type Result<T> = std::result::Result<T, rocksdb::Error>;
+
+/// On-disk journal of the pending transactions in the memory pool.
+pub struct MempoolJournal {
+    /// The underlying RocksDB handle.
+    db: DB,
+    /// Sequence number of the next journaled transaction, used to keep the FIFO order.
+    counter: AtomicU64,
+}
+
+impl MempoolJournal {
+    /// Open the journal at the given path, and create a new one if missing.
+    fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let transaction_cf = ColumnFamilyDescriptor::new(TRANSACTION_CF, Options::default());
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = DB::open_cf_descriptors(&opts, path, vec![transaction_cf])?;
+        Ok(Self {
+            db,
+            counter: AtomicU64::new(0),
+        })
+    }
+
+    /// Destroy the existing journal at the given path and create an empty one.
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        DB::destroy(&Options::default(), &path)?;
+        Self::open(path)
+    }
+
+    /// Load the journal at the given path, keeping the transactions from the last run.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let journal = Self::open(path)?;
+        let mut counter: u64 = 0;
+        for (_, seq, _) in journal.entries()? {
+            if seq >= counter {
+                counter = seq + 1;
+            }
+        }
+        journal.counter.store(counter, Ordering::Relaxed);
+        Ok(journal)
+    }
+
+    /// Write a list of memory pool changes into the journal in one atomic operation.
+    pub fn write(&self, entries: &[JournalEntry]) -> Result<()> {
+        let transaction_cf = self.db.cf_handle(TRANSACTION_CF).unwrap();
+        let mut wb = WriteBatch::default();
+        for entry in entries {
+            match entry {
+                JournalEntry::Insert(tx) => {
+                    let hash = <Transaction as Hashable>::hash(tx);
+                    let seq = self.counter.fetch_add(1, Ordering::Relaxed);
+                    wb.put_cf(
+                        transaction_cf,
+                        serialize(&hash).unwrap(),
+                        serialize(&(seq, tx)).unwrap(),
+                    )?;
+                }
+                JournalEntry::Remove(hash) => {
+                    wb.delete_cf(transaction_cf, serialize(hash).unwrap())?;
+                }
+            }
+        }
+        self.db.write(wb)?;
+        Ok(())
+    }
+
+    /// Get the journaled transactions in the order they were inserted into the memory pool.
+    pub fn transactions(&self) -> Result<Vec<Transaction>> {
+        let mut entries = self.entries()?;
+        entries.sort_unstable_by_key(|x| x.1);
+        Ok(entries.into_iter().map(|x| x.2).collect())
+    }
+
+    fn entries(&self) -> Result<Vec<(H256, u64, Transaction)>> {
+        let transaction_cf = self.db.cf_handle(TRANSACTION_CF).unwrap();
+        let iter = self.db.iterator_cf(transaction_cf, IteratorMode::Start)?;
+        let mut entries = vec![];
+        for (k, v) in iter {
+            let hash: H256 = deserialize(k.as_ref()).unwrap();
+            let (seq, tx): (u64, Transaction) = deserialize(v.as_ref()).unwrap();
+            entries.push((hash, seq, tx));
+        }
+        Ok(entries)
+    }
+
+    /// Reload the journaled transactions into the memory pool. Transactions that do not pass
+    /// validation against the current state are skipped, but kept in the journal, since the
+    /// state may not have caught up with the ledger yet. Returns the number of transactions
+    /// restored.
+    pub fn restore(&self, mempool: &Mutex<MemoryPool>, statedb: &StateDatabase) -> Result<usize> {
+        let mut restored: usize = 0;
+        let mut mempool = mempool.lock().unwrap();
+        for tx in self.transactions()? {
+            let hash = <Transaction as Hashable>::hash(&tx);
+            match validation::check_pending_transaction(&tx, statedb) {
+                BlockResult::Pass => {
+                    if !mempool.contains(&hash) {
+                        mempool.insert(tx);
+                        restored += 1;
+                    }
+                }
+                e => debug!("Skipping journaled transaction {:.8}: {}", hash, e),
+            }
+        }
+        drop(mempool);
+        Ok(restored)
+    }
+
+    /// Start the thread that flushes memory pool changes into the journal every `interval`
+    /// milliseconds.
+    pub fn start(self, mempool: &Arc<Mutex<MemoryPool>>, interval: u64) {
+        let mempool = Arc::clone(mempool);
+        mempool.lock().unwrap().enable_journal();
+        thread::Builder::new()
+            .name("mempool_journal".to_string())
+            .spawn(move || loop {
+                thread::sleep(time::Duration::from_millis(interval));
+                let entries = mempool.lock().unwrap().drain_journal();
+                if entries.is_empty() {
+                    continue;
+                }
+                if let Err(e) = self.write(&entries) {
+                    warn!("Error flushing memory pool journal: {}", e);
+                }
+            })
+            .unwrap();
+        info!("Memory pool journal flushing every {} ms", interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Action, RawTransaction};
+    use parity_crypto::publickey::{Generator, KeyPair, Random};
+
+    fn transaction(nonce: u64) -> Transaction {
+        let keypair: KeyPair = Random.generate().unwrap();
+        RawTransaction {
+            nonce: nonce.into(),
+            gas_price: 0.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: vec![],
+        }
+        .sign(keypair.secret(), None)
+    }
+
+    #[test]
+    fn insert_remove_and_load() {
+        let path = "/tmp/prism_test_mempool_journal.rocksdb";
+        let journal = MempoolJournal::new(path).unwrap();
+        let tx_1 = transaction(0);
+        let tx_2 = transaction(1);
+        let tx_3 = transaction(2);
+        journal
+            .write(&[
+                JournalEntry::Insert(tx_1.clone()),
+                JournalEntry::Insert(tx_2.clone()),
+            ])
+            .unwrap();
+        journal
+            .write(&[
+                JournalEntry::Insert(tx_3.clone()),
+                JournalEntry::Remove(<Transaction as Hashable>::hash(&tx_2)),
+            ])
+            .unwrap();
+        drop(journal);
+
+        let journal = MempoolJournal::load(path).unwrap();
+        assert_eq!(journal.transactions().unwrap(), vec![tx_1, tx_3.clone()]);
+        let tx_4 = transaction(3);
+        journal.write(&[JournalEntry::Insert(tx_4.clone())]).unwrap();
+        let txs = journal.transactions().unwrap();
+        assert_eq!(txs.len(), 3);
+        assert_eq!(txs[1], tx_3);
+        assert_eq!(txs[2], tx_4);
+    }
+}
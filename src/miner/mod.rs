@@ -1,4 +1,8 @@
+pub mod hashing;
 pub mod memory_pool;
+pub mod mempool_journal;
+pub mod strategy;
+pub mod work;
 
 use crate::block::header::Header;
 use crate::block::{proposer, transaction, voter};
@@ -6,32 +10,42 @@ use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::config::*;
-use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::hash::{Address, Hashable, H256};
 use crate::crypto::merkle::verify;
 use crate::crypto::merkle::MerkleTree;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::handler::new_validated_block;
 use crate::network::message::Message;
 use crate::network::server::Handle as ServerHandle;
+use crate::transaction::Transaction;
+use ethereum_types::U256;
 
 use log::{info, warn};
 
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use crossbeam::channel::{bounded, unbounded, Receiver, Select, Sender, TryRecvError};
+use hashing::HashingPool;
 use memory_pool::MemoryPool;
+use strategy::Strategy;
+use work::{SubmitWorkError, WorkPackage, WorkPackages};
 use std::time;
 use std::time::SystemTime;
 
 use rand::distributions::Distribution;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashSet};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 use rand::Rng;
 
+/// Gas used by the cheapest transaction, a plain transfer.
+const MIN_TX_GAS: u64 = 21_000;
+
 enum ControlSignal {
-    Start(u64, bool, f64), // the number controls the lambda of interval between block generation
+    Start(u64, bool, f64, Strategy), // the number controls the lambda of interval between block generation
     Step,
     Exit,
+    GetWork(Sender<WorkPackage>),
+    SubmitWork(H256, u32, Sender<Result<H256, SubmitWorkError>>),
 }
 
 #[derive(Ord, Eq, PartialOrd, PartialEq)]
@@ -67,6 +81,20 @@ pub struct Context {
     contents: Vec<Content>,
     content_merkle_tree: MerkleTree,
     config: BlockchainConfig,
+    /// Threads searching for nonces, None if we try one nonce per loop iteration
+    hashing_pool: Option<HashingPool>,
+    /// Generation and header of the work handed to the hashing threads
+    work: Option<(u64, Header)>,
+    /// Work packages handed to external miners
+    work_packages: WorkPackages,
+    /// Behavior of the miner
+    strategy: Strategy,
+    /// Blocks kept private by the proposer withholding strategy, in the order they were mined
+    withheld: Vec<H256>,
+    /// Level of the first withheld proposer block
+    withheld_level: Option<u64>,
+    /// Transactions left out by the censorship strategy
+    censored: HashSet<H256>,
 }
 
 #[derive(Clone)]
@@ -82,6 +110,8 @@ pub fn new(
     ctx_update_source: Receiver<ContextUpdateSignal>,
     ctx_update_tx: &Sender<ContextUpdateSignal>,
     server: &ServerHandle,
+    miner_address: Address,
+    hashing_threads: usize,
     config: BlockchainConfig,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
@@ -125,10 +155,22 @@ pub fn new(
             content_merkle_root: H256::default(),
             extra_content: [0; 32],
             difficulty: *DEFAULT_DIFFICULTY,
+            miner: miner_address,
         },
         contents,
         content_merkle_tree,
         config,
+        hashing_pool: if hashing_threads > 0 {
+            Some(HashingPool::new(hashing_threads))
+        } else {
+            None
+        },
+        work: None,
+        work_packages: WorkPackages::new(),
+        strategy: Strategy::Honest,
+        withheld: vec![],
+        withheld_level: None,
+        censored: HashSet::new(),
     };
 
     let handle = Handle {
@@ -143,15 +185,33 @@ impl Handle {
         self.control_chan.send(ControlSignal::Exit).unwrap();
     }
 
-    pub fn start(&self, lambda: u64, lazy: bool, prob: f64) {
+    pub fn start(&self, lambda: u64, lazy: bool, prob: f64, strategy: Strategy) {
         self.control_chan
-            .send(ControlSignal::Start(lambda, lazy, prob))
+            .send(ControlSignal::Start(lambda, lazy, prob, strategy))
             .unwrap();
     }
 
     pub fn step(&self) {
         self.control_chan.send(ControlSignal::Step).unwrap();
     }
+
+    /// Get a work package for an external miner, or None if the miner has shut down.
+    pub fn get_work(&self) -> Option<WorkPackage> {
+        let (reply_tx, reply_rx) = bounded(1);
+        self.control_chan
+            .send(ControlSignal::GetWork(reply_tx))
+            .ok()?;
+        reply_rx.recv().ok()
+    }
+
+    /// Submit the nonce found by an external miner. Returns the hash of the mined block.
+    pub fn submit_work(&self, work_id: H256, nonce: u32) -> Result<H256, SubmitWorkError> {
+        let (reply_tx, reply_rx) = bounded(1);
+        self.control_chan
+            .send(ControlSignal::SubmitWork(work_id, nonce, reply_tx))
+            .map_err(|_| SubmitWorkError::MinerStopped)?;
+        reply_rx.recv().map_err(|_| SubmitWorkError::MinerStopped)?
+    }
 }
 
 impl Context {
@@ -171,11 +231,15 @@ impl Context {
                 info!("Miner shutting down");
                 self.operating_state = OperatingState::ShutDown;
             }
-            ControlSignal::Start(i, l, p) => {
+            ControlSignal::Start(i, l, p, strategy) => {
                 info!(
-                    "Miner starting in continuous mode with lambda {} and lazy mode {}, lazy tx drop prob {}",
-                    i, l, p
+                    "Miner starting in continuous mode with lambda {} and lazy mode {}, lazy tx drop prob {}, strategy {}",
+                    i, l, p, strategy
                 );
+                if strategy != Strategy::WithholdProposer {
+                    self.release_withheld();
+                }
+                self.strategy = strategy;
                 if l {
                     info!(
                         "Lazy transaction block mining rate set to {} blks/s",
@@ -188,6 +252,26 @@ impl Context {
                 info!("Miner starting in stepping mode");
                 self.operating_state = OperatingState::Step;
             }
+            ControlSignal::GetWork(reply) => {
+                // bring the content up to date, since we may be paused
+                self.update_context();
+                let package =
+                    self.work_packages
+                        .get(&self.header, &self.contents, &self.content_merkle_tree);
+                // the requester may have given up waiting
+                let _ = reply.send(package);
+            }
+            ControlSignal::SubmitWork(work_id, nonce, reply) => {
+                let result = self
+                    .work_packages
+                    .submit(&work_id, nonce, &self.config)
+                    .map(|block| {
+                        info!("Accepted block {} from external miner", block.hash());
+                        self.publish_block(&block);
+                        block.hash()
+                    });
+                let _ = reply.send(result);
+            }
         }
     }
 
@@ -211,11 +295,13 @@ impl Context {
             // check and react to control signals
             match self.operating_state {
                 OperatingState::Paused => {
+                    self.pause_hashing();
                     let signal = self.control_chan.recv().unwrap();
                     self.handle_control_signal(signal);
                     continue;
                 }
                 OperatingState::ShutDown => {
+                    self.pause_hashing();
                     return;
                 }
                 _ => match self.control_chan.try_recv() {
@@ -227,262 +313,416 @@ impl Context {
                 },
             }
             if let OperatingState::ShutDown = self.operating_state {
+                self.pause_hashing();
                 return;
             }
 
-            // check whether there is new content through context update channel
-            let mut new_transaction_block: bool = false;
-            let mut new_voter_block: BTreeSet<u16> = BTreeSet::new();
-            let mut new_proposer_block: bool = false;
-            for sig in self.context_update_chan.try_iter() {
-                match sig {
-                    ContextUpdateSignal::NewProposerBlock => new_proposer_block = true,
-                    ContextUpdateSignal::NewVoterBlock(chain) => {
-                        new_voter_block.insert(chain);
+            // apply the new content from the context update channel
+            self.update_context();
+            self.release_if_caught_up();
+
+            let header_hash: Option<H256> = if self.hashing_pool.is_none() {
+                // try a new nonce, and update the timestamp
+                self.header.nonce = rng.gen();
+                self.header.timestamp = get_time();
+
+                // lazy annotate whether to skip this block in extra_content
+                self.annotate_lazy(&mut rng);
+
+                // Check if we successfully mined a block
+                let header_hash = self.header.hash();
+                if header_hash < self.header.difficulty {
+                    Some(header_hash)
+                } else {
+                    None
+                }
+            } else {
+                // hand the header over to the hashing threads if the context has changed
+                let outdated = match &self.work {
+                    Some((_, h)) => {
+                        h.parent != self.header.parent
+                            || h.content_merkle_root != self.header.content_merkle_root
+                            || h.difficulty != self.header.difficulty
                     }
-                    ContextUpdateSignal::NewTransactionBlock => new_transaction_block = true,
+                    None => true,
+                };
+                if outdated {
+                    self.annotate_lazy(&mut rng);
+                    let generation = self.hashing_pool.as_ref().unwrap().submit(self.header);
+                    self.work = Some((generation, self.header));
                 }
-            }
 
-            // handle context updates
-            let mut touched_content: BTreeSet<u16> = BTreeSet::new();
-            let mut voter_shift = false;
-            // update voter parents
-            for voter_chain in new_voter_block.iter() {
-                let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
-                let voter_parent = self.blockchain.best_voter(*voter_chain as usize);
-                if let Content::Voter(c) = &mut self.contents[chain_id] {
-                    if voter_parent != c.voter_parent {
-                        c.voter_parent = voter_parent;
-                        // mark that we have shifted a vote
-                        voter_shift = true;
-                        touched_content.insert(chain_id as u16);
+                // wait until a nonce is found or the context has to be updated
+                let pool = self.hashing_pool.as_ref().unwrap();
+                let mut sel = Select::new();
+                sel.recv(&self.control_chan);
+                sel.recv(&self.context_update_chan);
+                sel.recv(pool.solutions());
+                sel.ready();
+                match pool.solutions().try_recv() {
+                    Ok(solution) if Some(solution.generation) == self.work.map(|w| w.0) => {
+                        self.header = solution.header;
+                        // the hashing threads are idle after finding a solution
+                        self.work = None;
+                        Some(solution.hash)
                     }
-                } else {
-                    unreachable!();
+                    _ => None,
                 }
-            }
+            };
 
-            // update transaction block content
-            if new_transaction_block {
-                let mempool = self.mempool.lock().unwrap();
-                let transactions = mempool.get_transactions(self.config.tx_txs);
-                drop(mempool);
-                let _chain_id: usize = TRANSACTION_INDEX as usize;
-                if let Content::Transaction(c) = &mut self.contents[TRANSACTION_INDEX as usize] {
-                    c.transactions = transactions;
-                    touched_content.insert(TRANSACTION_INDEX);
-                } else {
-                    unreachable!();
+            if let Some(header_hash) = header_hash {
+                // Create a block
+                let mined_block: Block = self.produce_block(header_hash);
+                self.publish_block(&mined_block);
+                // if we are stepping, pause the miner loop
+                if let OperatingState::Step = self.operating_state {
+                    self.operating_state = OperatingState::Paused;
                 }
             }
 
-            // append transaction references
-            // FIXME: we are now refreshing the whole tree
-            // note that if there are new proposer blocks, we will need to refresh tx refs in the
-            // next step. In that case, don't bother doing it here.
-            if new_transaction_block && !new_proposer_block {
-                if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
-                    // only update the references if we are not running out of quota
-                    if c.transaction_refs.len() < self.config.proposer_tx_refs as usize {
-                        let mut refs = self.blockchain.unreferred_transactions();
-                        refs.truncate(self.config.proposer_tx_refs as usize);
-                        c.transaction_refs = refs;
-                        touched_content.insert(PROPOSER_INDEX);
+            if let OperatingState::Run(i, _, _) = self.operating_state {
+                // with the hashing threads, only throttle after a block is mined
+                if i != 0 && (self.hashing_pool.is_none() || header_hash.is_some()) {
+                    let interval_dist = rand::distributions::Exp::new(1.0 / (i as f64));
+                    let interval = interval_dist.sample(&mut rng);
+                    let interval = time::Duration::from_micros(interval as u64);
+                    let time_spent = time::Instant::now().duration_since(block_start);
+                    if interval > time_spent {
+                        thread::sleep(interval - time_spent);
                     }
-                } else {
-                    unreachable!();
                 }
             }
+        }
+    }
 
-            // update the best proposer
-            if new_proposer_block {
-                self.header.parent = self.blockchain.best_proposer().unwrap();
+    /// Update the block content and the header according to the signals in the context update
+    /// channel.
+    fn update_context(&mut self) {
+        // check whether there is new content through context update channel
+        let mut new_transaction_block: bool = false;
+        let mut new_voter_block: BTreeSet<u16> = BTreeSet::new();
+        let mut new_proposer_block: bool = false;
+        for sig in self.context_update_chan.try_iter() {
+            match sig {
+                ContextUpdateSignal::NewProposerBlock => new_proposer_block = true,
+                ContextUpdateSignal::NewVoterBlock(chain) => {
+                    new_voter_block.insert(chain);
+                }
+                ContextUpdateSignal::NewTransactionBlock => new_transaction_block = true,
+            }
+        }
+
+        // handle context updates
+        let mut touched_content: BTreeSet<u16> = BTreeSet::new();
+        let mut voter_shift = false;
+        // update voter parents
+        for voter_chain in new_voter_block.iter() {
+            let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
+            let voter_parent = self.blockchain.best_voter(*voter_chain as usize);
+            if let Content::Voter(c) = &mut self.contents[chain_id] {
+                if voter_parent != c.voter_parent {
+                    c.voter_parent = voter_parent;
+                    // mark that we have shifted a vote
+                    voter_shift = true;
+                    touched_content.insert(chain_id as u16);
+                }
+            } else {
+                unreachable!();
             }
+        }
 
-            // update the best proposer and the proposer/transaction refs. Note that if the best
-            // proposer block is updated, we will update the proposer/transaction refs. But we also
-            // need to make sure that the best proposer is still the best at the end of this
-            // process. Otherwise, we risk having voter/transaction blocks that have a parent
-            // deeper than ours
-            // sadly, we still may have race condition where the best proposer is updated, but the
-            // blocks it refers to have not been removed from unreferred_{proposer, transaction}.
-            // but this is pretty much the only race condition that we still have.
-            loop {
-                // first refresh the transaction and proposer refs if there has been a new proposer
-                // block
-                if new_proposer_block {
-                    if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
-                        let mut refs = self.blockchain.unreferred_transactions();
-                        refs.truncate(self.config.proposer_tx_refs as usize);
-                        c.transaction_refs = refs;
-                        c.proposer_refs = self.blockchain.unreferred_proposers();
-                        let parent = self.header.parent;
-                        c.proposer_refs.retain(|&x| x != parent);
-                        touched_content.insert(PROPOSER_INDEX);
-                    } else {
-                        unreachable!();
+        // update transaction block content
+        if new_transaction_block {
+            let mempool = self.mempool.lock().unwrap();
+            // skip the transactions that would take the block over the gas limit, and stop
+            // once not even a plain transfer fits
+            let gas_limit = self.config.tx_block_gas_limit.map(U256::from);
+            let mut block_gas = U256::zero();
+            let mut fits_gas_limit = |tx: &Transaction| match gas_limit {
+                None => Some(true),
+                Some(limit) if limit - block_gas < MIN_TX_GAS.into() => None,
+                Some(limit) => match block_gas.checked_add(tx.gas) {
+                    Some(gas) if gas <= limit => {
+                        block_gas = gas;
+                        Some(true)
                     }
+                    _ => Some(false),
+                },
+            };
+            let transactions = match &self.strategy {
+                Strategy::Censor(addrs) => {
+                    let censored = &mut self.censored;
+                    mempool.get_transactions_filtered(self.config.tx_txs, |tx| {
+                        if addrs.contains(&tx.sender()) {
+                            if censored.insert(<Transaction as Hashable>::hash(tx)) {
+                                PERFORMANCE_COUNTER.record_censor_transaction();
+                            }
+                            Some(false)
+                        } else {
+                            fits_gas_limit(tx)
+                        }
+                    })
+                }
+                _ if gas_limit.is_some() => {
+                    mempool.get_transactions_filtered(self.config.tx_txs, fits_gas_limit)
                 }
+                _ => mempool.get_transactions(self.config.tx_txs),
+            };
+            drop(mempool);
+            let _chain_id: usize = TRANSACTION_INDEX as usize;
+            if let Content::Transaction(c) = &mut self.contents[TRANSACTION_INDEX as usize] {
+                c.transactions = transactions;
+                touched_content.insert(TRANSACTION_INDEX);
+            } else {
+                unreachable!();
+            }
+        }
 
-                // then check whether our proposer parent is really the best
-                let best_proposer = self.blockchain.best_proposer().unwrap();
-                if self.header.parent == best_proposer {
-                    break;
-                } else {
-                    new_proposer_block = true;
-                    self.header.parent = best_proposer;
-                    continue;
+        // append transaction references
+        // FIXME: we are now refreshing the whole tree
+        // note that if there are new proposer blocks, we will need to refresh tx refs in the
+        // next step. In that case, don't bother doing it here.
+        if new_transaction_block && !new_proposer_block {
+            if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
+                // only update the references if we are not running out of quota
+                if c.transaction_refs.len() < self.config.proposer_tx_refs as usize {
+                    let mut refs = self.blockchain.unreferred_transactions();
+                    refs.truncate(self.config.proposer_tx_refs as usize);
+                    c.transaction_refs = refs;
+                    touched_content.insert(PROPOSER_INDEX);
                 }
+            } else {
+                unreachable!();
             }
+        }
 
-            // update the votes
+        // update the best proposer
+        if new_proposer_block {
+            self.header.parent = self.blockchain.best_proposer().unwrap();
+        }
+
+        // update the best proposer and the proposer/transaction refs. Note that if the best
+        // proposer block is updated, we will update the proposer/transaction refs. But we also
+        // need to make sure that the best proposer is still the best at the end of this
+        // process. Otherwise, we risk having voter/transaction blocks that have a parent
+        // deeper than ours
+        // sadly, we still may have race condition where the best proposer is updated, but the
+        // blocks it refers to have not been removed from unreferred_{proposer, transaction}.
+        // but this is pretty much the only race condition that we still have.
+        loop {
+            // first refresh the transaction and proposer refs if there has been a new proposer
+            // block
             if new_proposer_block {
-                for voter_chain in 0..self.config.voter_chains {
-                    let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
-                    let voter_parent = if let Content::Voter(c) = &self.contents[chain_id] {
-                        c.voter_parent
-                    } else {
-                        unreachable!();
-                    };
-                    if let Content::Voter(c) = &mut self.contents[chain_id] {
-                        c.votes = self
+                if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
+                    let mut refs = self.blockchain.unreferred_transactions();
+                    refs.truncate(self.config.proposer_tx_refs as usize);
+                    c.transaction_refs = refs;
+                    c.proposer_refs = self.blockchain.unreferred_proposers();
+                    let parent = self.header.parent;
+                    c.proposer_refs.retain(|&x| x != parent);
+                    touched_content.insert(PROPOSER_INDEX);
+                } else {
+                    unreachable!();
+                }
+            }
+
+            // then check whether our proposer parent is really the best
+            let best_proposer = self.blockchain.best_proposer().unwrap();
+            if self.header.parent == best_proposer {
+                break;
+            } else {
+                new_proposer_block = true;
+                self.header.parent = best_proposer;
+                continue;
+            }
+        }
+
+        // update the votes
+        if new_proposer_block {
+            for voter_chain in 0..self.config.voter_chains {
+                let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
+                let voter_parent = if let Content::Voter(c) = &self.contents[chain_id] {
+                    c.voter_parent
+                } else {
+                    unreachable!();
+                };
+                if let Content::Voter(c) = &mut self.contents[chain_id] {
+                    let votes = match self.strategy {
+                        Strategy::BalanceVoter => self
                             .blockchain
-                            .unvoted_proposer(&voter_parent, &self.header.parent)
-                            .unwrap();
+                            .least_voted_proposer(&voter_parent, &self.header.parent),
+                        _ => self
+                            .blockchain
+                            .unvoted_proposer(&voter_parent, &self.header.parent),
+                    }
+                    .unwrap();
+                    // a proposer block forking an existing level leaves the votes unchanged
+                    if votes != c.votes {
+                        c.votes = votes;
                         touched_content.insert(chain_id as u16);
-                    } else {
-                        unreachable!();
                     }
+                } else {
+                    unreachable!();
                 }
-            } else if !new_voter_block.is_empty() {
-                for voter_chain in 0..self.config.voter_chains {
-                    let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
-                    let voter_parent = if let Content::Voter(c) = &self.contents[chain_id] {
-                        c.voter_parent
-                    } else {
-                        unreachable!();
-                    };
-                    if let Content::Voter(c) = &mut self.contents[chain_id] {
-                        let votes = self
+            }
+        } else if !new_voter_block.is_empty() {
+            for voter_chain in 0..self.config.voter_chains {
+                let chain_id: usize = (FIRST_VOTER_INDEX + voter_chain) as usize;
+                let voter_parent = if let Content::Voter(c) = &self.contents[chain_id] {
+                    c.voter_parent
+                } else {
+                    unreachable!();
+                };
+                if let Content::Voter(c) = &mut self.contents[chain_id] {
+                    let votes = match self.strategy {
+                        Strategy::BalanceVoter => self
                             .blockchain
-                            .unvoted_proposer(&voter_parent, &self.header.parent)
-                            .unwrap();
-                        // check whether the votes is actually updated
-                        if votes.len() != c.votes.len() || votes.iter().zip(c.votes.iter()).any(|(&a, &b)|a!=b) {
-                            c.votes = votes;
-                            touched_content.insert(chain_id as u16);
-                        }
-                    } else {
-                        unreachable!();
+                            .least_voted_proposer(&voter_parent, &self.header.parent),
+                        _ => self
+                            .blockchain
+                            .unvoted_proposer(&voter_parent, &self.header.parent),
+                    }
+                    .unwrap();
+                    // check whether the votes is actually updated
+                    if votes.len() != c.votes.len() || votes.iter().zip(c.votes.iter()).any(|(&a, &b)|a!=b) {
+                        c.votes = votes;
+                        touched_content.insert(chain_id as u16);
                     }
+                } else {
+                    unreachable!();
                 }
             }
+        }
 
-            // update the difficulty
-            self.header.difficulty = self.get_difficulty(&self.header.parent);
+        // update the difficulty
+        self.header.difficulty = self.get_difficulty(&self.header.parent);
 
-            // update or rebuild the merkle tree according to what we did in the last stage
-            if new_proposer_block {//|| voter_shift {
-                // if there has been a new proposer block, simply rebuild the merkle tree
-                self.content_merkle_tree = MerkleTree::new(&self.contents);
-            } else {
-                // if there has not been a new proposer block, update individual entries
-                // TODO: add batch updating to merkle tree
-                for chain_id in touched_content.iter() {
-                    let chain_id = *chain_id as usize;
-                    self.content_merkle_tree
-                        .update(chain_id, &self.contents[chain_id]);
-                }
+        // update the merkle tree according to what we did in the last stage. the number of
+        // contents never changes, so we only need to rehash the entries we touched
+        let touched_content: Vec<usize> = touched_content.iter().map(|&c| c as usize).collect();
+        self.content_merkle_tree
+            .batch_update(&self.contents, &touched_content);
 
-            }
+        // always update root
+        self.header.content_merkle_root = self.content_merkle_tree.root();
+    }
 
-            // always update root
-            self.header.content_merkle_root = self.content_merkle_tree.root();
-
-            // try a new nonce, and update the timestamp
-            self.header.nonce = rng.gen();
-            self.header.timestamp = get_time();
-            
-            // lazy annotate whether to skip this block in extra_content
-            if let OperatingState::Run(_, true, prob) = self.operating_state {
-                if rng.gen_bool(prob) {
-                    self.header.extra_content = LAZY_ANNOTATION;
-                } else {
-                    self.header.extra_content = [0;32];
-                }
-            }
+    /// Release the private proposer chain once the public proposer chain reaches the level of
+    /// its first block
+    fn release_if_caught_up(&mut self) {
+        let level = match self.withheld_level {
+            Some(l) => l,
+            None => return,
+        };
+        let caught_up = self
+            .blockchain
+            .proposers_at_level(level)
+            .unwrap()
+            .iter()
+            .any(|h| !self.withheld.contains(h));
+        if caught_up {
+            info!("Public proposer chain reached level {}, releasing private blocks", level);
+            self.release_withheld();
+        }
+    }
 
-            // Check if we successfully mined a block
-            let header_hash = self.header.hash();
-            if header_hash < self.header.difficulty {
-                // Create a block
-                let mined_block: Block = self.produce_block(header_hash);
-                PERFORMANCE_COUNTER.record_mine_block(&mined_block);
-                self.blockdb.insert(&mined_block).unwrap();
-                new_validated_block(
-                    &mined_block,
-                    &self.mempool,
-                    &self.blockdb,
-                    &self.blockchain,
-                    &self.server,
-                );
-                // broadcast after adding the new block to the blockchain, in case a peer mines
-                // a block immediately after we broadcast, leaving us non time to insert into
-                // the blockchain
-                self.server
-                    .broadcast(Message::NewBlockHashes(vec![header_hash]));
-                // if we are stepping, pause the miner loop
-                if let OperatingState::Step = self.operating_state {
-                    self.operating_state = OperatingState::Paused;
-                }
-                // after we mined this block, we update the context based on this block
-                match &mined_block.content {
-                    Content::Proposer(_) => self
-                        .context_update_tx
-                        .send(ContextUpdateSignal::NewProposerBlock)
-                        .unwrap(),
-                    Content::Voter(content) => self
-                        .context_update_tx
-                        .send(ContextUpdateSignal::NewVoterBlock(content.chain_number))
-                        .unwrap(),
-                    Content::Transaction(_) => self
-                        .context_update_tx
-                        .send(ContextUpdateSignal::NewTransactionBlock)
-                        .unwrap(),
-                }
-            }
+    /// Broadcast all the withheld blocks
+    fn release_withheld(&mut self) {
+        if self.withheld.is_empty() {
+            return;
+        }
+        PERFORMANCE_COUNTER.record_release_blocks(self.withheld.len());
+        let withheld = std::mem::replace(&mut self.withheld, vec![]);
+        self.server.broadcast(Message::NewBlockHashes(withheld));
+        self.withheld_level = None;
+    }
 
-            if let OperatingState::Run(i, _, _) = self.operating_state {
-                if i != 0 {
-                    let interval_dist = rand::distributions::Exp::new(1.0 / (i as f64));
-                    let interval = interval_dist.sample(&mut rng);
-                    let interval = time::Duration::from_micros(interval as u64);
-                    let time_spent = time::Instant::now().duration_since(block_start);
-                    if interval > time_spent {
-                        thread::sleep(interval - time_spent);
-                    }
-                }
+    /// Lazy annotate whether to skip this block in extra_content
+    fn annotate_lazy<R: Rng>(&mut self, rng: &mut R) {
+        if let OperatingState::Run(_, true, prob) = self.operating_state {
+            if rng.gen_bool(prob) {
+                self.header.extra_content = LAZY_ANNOTATION;
+            } else {
+                self.header.extra_content = [0;32];
             }
         }
     }
 
+    /// Stop the hashing threads, if any, until the miner hands over new work
+    fn pause_hashing(&mut self) {
+        if let Some(pool) = &self.hashing_pool {
+            pool.pause();
+        }
+        self.work = None;
+    }
+
     /// Given a valid header, sortition its hash and create the block
     fn produce_block(&self, header_hash: H256) -> Block {
-        // Get sortition ID
-        let sortition_id = self
-            .config
-            .sortition_hash(&header_hash, &self.header.difficulty)
-            .expect("Block Hash should <= Difficulty");
-        // Create a block
-        // get the merkle proof
-        let sortition_proof: Vec<H256> = self.content_merkle_tree.proof(sortition_id as usize);
-        let block = Block::from_header(
+        assemble_block(
+            &self.config,
             self.header,
-            self.contents[sortition_id as usize].clone(),
-            sortition_proof,
+            header_hash,
+            &self.contents,
+            &self.content_merkle_tree,
+        )
+    }
+
+    /// Insert a block we mined into the blockchain, broadcast it, and update the context
+    fn publish_block(&mut self, mined_block: &Block) {
+        let header_hash = mined_block.hash();
+        PERFORMANCE_COUNTER.record_mine_block(&mined_block);
+        self.blockdb.insert(&mined_block).unwrap();
+        new_validated_block(
+            &mined_block,
+            &self.mempool,
+            &self.blockdb,
+            &self.blockchain,
+            &self.server,
         );
-        block
+        // broadcast after adding the new block to the blockchain, in case a peer mines
+        // a block immediately after we broadcast, leaving us non time to insert into
+        // the blockchain
+        let withhold = match (&self.strategy, &mined_block.content) {
+            (Strategy::WithholdProposer, Content::Proposer(_)) => true,
+            // blocks mined on top of the private proposer chain are private, too
+            (Strategy::WithholdProposer, _) => !self.withheld.is_empty(),
+            _ => false,
+        };
+        if withhold {
+            if self.withheld_level.is_none() {
+                self.withheld_level = Some(self.blockchain.proposer_level(&header_hash).unwrap());
+            }
+            self.withheld.push(header_hash);
+            PERFORMANCE_COUNTER.record_withhold_block();
+        } else if let (Strategy::DelayVoter(delay), Content::Voter(_)) =
+            (&self.strategy, &mined_block.content)
+        {
+            PERFORMANCE_COUNTER.record_delay_voter_block();
+            let delay = time::Duration::from_millis(*delay);
+            let server = self.server.clone();
+            thread::spawn(move || {
+                thread::sleep(delay);
+                server.broadcast(Message::NewBlockHashes(vec![header_hash]));
+            });
+        } else {
+            self.server
+                .broadcast(Message::NewBlockHashes(vec![header_hash]));
+        }
+        // after we mined this block, we update the context based on this block
+        match &mined_block.content {
+            Content::Proposer(_) => self
+                .context_update_tx
+                .send(ContextUpdateSignal::NewProposerBlock)
+                .unwrap(),
+            Content::Voter(content) => self
+                .context_update_tx
+                .send(ContextUpdateSignal::NewVoterBlock(content.chain_number))
+                .unwrap(),
+            Content::Transaction(_) => self
+                .context_update_tx
+                .send(ContextUpdateSignal::NewTransactionBlock)
+                .unwrap(),
+        }
     }
 
     /// Calculate the difficulty for the block to be mined
@@ -496,6 +736,28 @@ impl Context {
     }
 }
 
+/// Given a valid header, sortition its hash and create the block from the content candidates
+fn assemble_block(
+    config: &BlockchainConfig,
+    header: Header,
+    header_hash: H256,
+    contents: &[Content],
+    content_merkle_tree: &MerkleTree,
+) -> Block {
+    // Get sortition ID
+    let sortition_id = config
+        .sortition_hash(&header_hash, &header.difficulty)
+        .expect("Block Hash should <= Difficulty");
+    // Create a block
+    // get the merkle proof
+    let sortition_proof: Vec<H256> = content_merkle_tree.proof(sortition_id as usize);
+    Block::from_header(
+        header,
+        contents[sortition_id as usize].clone(),
+        sortition_proof,
+    )
+}
+
 /// Get the current UNIX timestamp
 fn get_time() -> u128 {
     let cur_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
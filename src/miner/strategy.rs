@@ -0,0 +1,123 @@
+use crate::crypto::hash::Address;
+use std::collections::{HashMap, HashSet};
+
+/// The behavior of the miner, used to run security experiments against the confirmation rule.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Strategy {
+    /// Follow the protocol.
+    Honest,
+    /// Keep proposer blocks, and every block mined on top of them, on a private chain. The private
+    /// chain is released once the public proposer chain catches up with its first block.
+    WithholdProposer,
+    /// Vote for the proposer block with the fewest votes on each level, so that competing
+    /// proposer blocks stay balanced and the leader takes longer to confirm.
+    BalanceVoter,
+    /// Leave the transactions sent by the given addresses out of transaction blocks.
+    Censor(HashSet<Address>),
+    /// Hold mined voter blocks for the given number of milliseconds before broadcasting them.
+    DelayVoter(u64),
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Honest
+    }
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Strategy::Honest => write!(f, "honest"),
+            Strategy::WithholdProposer => write!(f, "withhold-proposer"),
+            Strategy::BalanceVoter => write!(f, "balance-voter"),
+            Strategy::Censor(addrs) => write!(f, "censor ({} addresses)", addrs.len()),
+            Strategy::DelayVoter(delay) => write!(f, "delay-voter ({} ms)", delay),
+        }
+    }
+}
+
+impl Strategy {
+    /// Parse the strategy from the parameters of the `/miner/start` API. The strategy name is
+    /// given by `strategy`, the censored addresses by `censor` as comma-separated hex strings, and
+    /// the voter block delay by `delay` in milliseconds.
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, String> {
+        let name = match params.get("strategy") {
+            Some(v) => v.as_str(),
+            None => return Ok(Strategy::Honest),
+        };
+        match name {
+            "honest" => Ok(Strategy::Honest),
+            "withhold-proposer" => Ok(Strategy::WithholdProposer),
+            "balance-voter" => Ok(Strategy::BalanceVoter),
+            "censor" => {
+                let addrs = match params.get("censor") {
+                    Some(v) => v,
+                    None => return Err("missing censored addresses".to_string()),
+                };
+                let mut censored: HashSet<Address> = HashSet::new();
+                for addr in addrs.split(',') {
+                    let decoded = hex::decode(addr.trim())
+                        .map_err(|e| format!("error decoding address {}: {}", addr, e))?;
+                    if decoded.len() != Address::len_bytes() {
+                        return Err(format!("address {} is not 20 bytes long", addr));
+                    }
+                    censored.insert(Address::from_slice(&decoded));
+                }
+                Ok(Strategy::Censor(censored))
+            }
+            "delay-voter" => {
+                let delay = match params.get("delay") {
+                    Some(v) => v,
+                    None => return Err("missing voter block delay".to_string()),
+                };
+                let delay = delay
+                    .parse::<u64>()
+                    .map_err(|e| format!("error parsing voter block delay: {}", e))?;
+                Ok(Strategy::DelayVoter(delay))
+            }
+            _ => Err(format!("unknown strategy {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Strategy::from_params(&params(&[])), Ok(Strategy::Honest));
+        assert_eq!(
+            Strategy::from_params(&params(&[("strategy", "withhold-proposer")])),
+            Ok(Strategy::WithholdProposer)
+        );
+        assert_eq!(
+            Strategy::from_params(&params(&[("strategy", "delay-voter"), ("delay", "500")])),
+            Ok(Strategy::DelayVoter(500))
+        );
+        let censored = Strategy::from_params(&params(&[
+            ("strategy", "censor"),
+            (
+                "censor",
+                "000000000000000000000000000000000000000a,000000000000000000000000000000000000000b",
+            ),
+        ]))
+        .unwrap();
+        match censored {
+            Strategy::Censor(addrs) => {
+                assert_eq!(addrs.len(), 2);
+                assert!(addrs.contains(&Address::from_low_u64_be(0xa)));
+            }
+            _ => panic!("wrong strategy"),
+        }
+        assert!(Strategy::from_params(&params(&[("strategy", "censor")])).is_err());
+        assert!(Strategy::from_params(&params(&[("strategy", "selfish")])).is_err());
+    }
+}
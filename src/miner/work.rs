@@ -0,0 +1,209 @@
+use super::{assemble_block, get_time};
+use crate::block::header::Header;
+use crate::block::{Block, Content};
+use crate::config::BlockchainConfig;
+use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::merkle::MerkleTree;
+use std::collections::VecDeque;
+
+/// Number of recent work packages we keep, so that nonces found on slightly outdated work are
+/// still accepted.
+const MAX_WORK_PACKAGES: usize = 16;
+/// Byte offset of the nonce in the serialized header: after the parent hash (32 bytes) and the
+/// timestamp (16 bytes).
+pub const NONCE_OFFSET: usize = 48;
+
+/// A header template handed to an external miner. The miner searches for a nonce such that the
+/// keccak hash of the serialized header, with the nonce filled in, is below the difficulty.
+#[derive(Serialize, Clone, Debug)]
+pub struct WorkPackage {
+    /// Identifier of the work package, to be passed back when submitting a nonce.
+    pub work_id: String,
+    /// Hex of the bincode-serialized header, with zero nonce.
+    pub header: String,
+    /// Byte offset of the nonce (little-endian u32) in the serialized header.
+    pub nonce_offset: usize,
+    /// Hex of the difficulty, as a big-endian number.
+    pub difficulty: String,
+}
+
+#[derive(Debug)]
+pub enum SubmitWorkError {
+    UnknownWork,
+    InsufficientWork,
+    MinerStopped,
+}
+
+impl std::fmt::Display for SubmitWorkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            SubmitWorkError::UnknownWork => write!(f, "unknown or outdated work package"),
+            SubmitWorkError::InsufficientWork => write!(f, "header hash is above the difficulty"),
+            SubmitWorkError::MinerStopped => write!(f, "miner has shut down"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitWorkError {}
+
+/// The header and the block content a work package commits to.
+struct Template {
+    header: Header,
+    contents: Vec<Content>,
+    content_merkle_tree: MerkleTree,
+}
+
+/// The recent work packages handed to external miners.
+#[derive(Default)]
+pub struct WorkPackages {
+    templates: VecDeque<(H256, Template)>,
+}
+
+impl WorkPackages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a work package for the given header and content. The latest work package is reused if
+    /// the header has not changed since.
+    pub fn get(
+        &mut self,
+        header: &Header,
+        contents: &[Content],
+        content_merkle_tree: &MerkleTree,
+    ) -> WorkPackage {
+        let reuse = match self.templates.back() {
+            Some((_, t)) => {
+                t.header.parent == header.parent
+                    && t.header.content_merkle_root == header.content_merkle_root
+                    && t.header.difficulty == header.difficulty
+                    && t.header.extra_content == header.extra_content
+                    && t.header.miner == header.miner
+            }
+            None => false,
+        };
+        if !reuse {
+            let mut template_header = *header;
+            template_header.nonce = 0;
+            template_header.timestamp = get_time();
+            let template = Template {
+                header: template_header,
+                contents: contents.to_vec(),
+                content_merkle_tree: content_merkle_tree.clone(),
+            };
+            self.templates.push_back((template_header.hash(), template));
+            if self.templates.len() > MAX_WORK_PACKAGES {
+                self.templates.pop_front();
+            }
+        }
+        let (work_id, template) = self.templates.back().unwrap();
+        WorkPackage {
+            work_id: work_id.to_string(),
+            header: hex::encode(bincode::serialize(&template.header).unwrap()),
+            nonce_offset: NONCE_OFFSET,
+            difficulty: template.header.difficulty.to_string(),
+        }
+    }
+
+    /// Check the nonce submitted for a work package, and assemble the mined block.
+    pub fn submit(
+        &self,
+        work_id: &H256,
+        nonce: u32,
+        config: &BlockchainConfig,
+    ) -> Result<Block, SubmitWorkError> {
+        let template = match self.templates.iter().find(|(id, _)| id == work_id) {
+            Some((_, t)) => t,
+            None => return Err(SubmitWorkError::UnknownWork),
+        };
+        let mut header = template.header;
+        header.nonce = nonce;
+        let header_hash = header.hash();
+        if header_hash >= header.difficulty {
+            return Err(SubmitWorkError::InsufficientWork);
+        }
+        Ok(assemble_block(
+            config,
+            header,
+            header_hash,
+            &template.contents,
+            &template.content_merkle_tree,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::header::tests::sample_header;
+    use crate::block::{proposer, transaction, voter};
+    use crate::config::{BlockchainConfig, DEFAULT_DIFFICULTY};
+    use crate::crypto::merkle::verify;
+    use std::convert::TryInto;
+
+    fn contents(config: &BlockchainConfig) -> Vec<Content> {
+        let mut contents = vec![
+            Content::Proposer(proposer::Content {
+                transaction_refs: vec![],
+                proposer_refs: vec![],
+            }),
+            Content::Transaction(transaction::Content {
+                transactions: vec![],
+            }),
+        ];
+        for chain_number in 0..config.voter_chains {
+            contents.push(Content::Voter(voter::Content {
+                chain_number,
+                voter_parent: config.voter_genesis[chain_number as usize],
+                votes: vec![],
+            }));
+        }
+        contents
+    }
+
+    #[test]
+    fn nonce_offset() {
+        let mut header = sample_header();
+        header.nonce = 0x0403_0201;
+        let serialized = bincode::serialize(&header).unwrap();
+        assert_eq!(&serialized[NONCE_OFFSET..NONCE_OFFSET + 4], &[1u8, 2, 3, 4]);
+    }
+
+    #[test]
+    fn get_and_submit() {
+        let config = BlockchainConfig::new(10, 168, 70000, 0.1, 0.1, 0.4, 20.0);
+        let contents = contents(&config);
+        let tree = MerkleTree::new(&contents);
+        let mut header = sample_header();
+        header.content_merkle_root = tree.root();
+        header.difficulty = *DEFAULT_DIFFICULTY;
+
+        let mut packages = WorkPackages::new();
+        let package = packages.get(&header, &contents, &tree);
+        // the same context gives the same work package
+        assert_eq!(packages.get(&header, &contents, &tree).work_id, package.work_id);
+        let work_id: [u8; 32] = hex::decode(&package.work_id)
+            .unwrap()
+            .as_slice()
+            .try_into()
+            .unwrap();
+        let work_id: H256 = work_id.into();
+
+        // any nonce passes the default difficulty
+        let block = packages.submit(&work_id, 42, &config).unwrap();
+        assert_eq!(block.header.nonce, 42);
+        assert_eq!(block.header.content_merkle_root, tree.root());
+        let sortition_id = config
+            .sortition_hash(&block.hash(), &block.header.difficulty)
+            .unwrap();
+        assert!(verify(
+            &tree.root(),
+            &block.content.hash(),
+            &block.sortition_proof,
+            sortition_id as usize,
+            contents.len(),
+        ));
+
+        assert!(packages.submit(&H256::default(), 42, &config).is_err());
+    }
+}
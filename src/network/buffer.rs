@@ -65,4 +65,34 @@ impl BlockBuffer {
         }
         resolved_blocks
     }
+
+    /// Keep only the dependencies that `waive` returns out of those of each buffered block, and
+    /// return the blocks left without any.
+    pub fn waive<F>(&mut self, mut waive: F) -> Vec<Block>
+    where
+        F: FnMut(&Block, Vec<H256>) -> Vec<H256>,
+    {
+        let mut resolved_blocks: Vec<Block> = vec![];
+        let hashes: Vec<H256> = self.blocks.keys().cloned().collect();
+        for hash in hashes {
+            let dependency = self.dependency.get_mut(&hash).unwrap();
+            let remaining: HashSet<H256> =
+                waive(&self.blocks[&hash], dependency.iter().cloned().collect())
+                    .into_iter()
+                    .collect();
+            for dep_hash in dependency.difference(&remaining) {
+                let dependent = self.dependent.get_mut(dep_hash).unwrap();
+                dependent.remove(&hash);
+                if dependent.is_empty() {
+                    self.dependent.remove(dep_hash);
+                }
+            }
+            *dependency = remaining;
+            if dependency.is_empty() {
+                self.dependency.remove(&hash).unwrap();
+                resolved_blocks.push(self.blocks.remove(&hash).unwrap());
+            }
+        }
+        resolved_blocks
+    }
 }
@@ -12,4 +12,12 @@ pub enum Message {
     GetTransactions(Vec<H256>),
     Transactions(Vec<Transaction>),
     Bootstrap(H256),
+    GetSnapshotManifest,
+    /// The RLP-encoded manifest of the latest state snapshot, if any.
+    SnapshotManifest(Option<Vec<u8>>),
+    GetSnapshotChunks(Vec<H256>),
+    SnapshotChunks(Vec<Vec<u8>>),
+    /// Ask for the blocks to sync the blockchain above a proposer level from.
+    GetBlockIndex(u64),
+    BlockIndex(Vec<H256>),
 }
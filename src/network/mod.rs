@@ -1,4 +1,4 @@
-mod buffer;
+pub mod buffer;
 pub mod message;
 pub mod peer;
 pub mod server;
@@ -12,22 +12,29 @@ use crate::handler::new_validated_block;
 use crate::miner::memory_pool::MemoryPool;
 use crate::miner::ContextUpdateSignal;
 use crate::network::server::Handle as ServerHandle;
+use crate::statedb::{SnapshotSync, StateDatabase};
 use crate::validation::{self, BlockResult};
 use crate::wallet::Wallet;
+use common_types::snapshot::ManifestData;
 use crossbeam::channel;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use std::collections::HashSet;
 
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Instant;
 
+/// Number of snapshot chunks asked in one message.
+const SNAPSHOT_CHUNK_BATCH: usize = 16;
+
 #[derive(Clone)]
 pub struct Context {
     msg_chan: channel::Receiver<(Vec<u8>, peer::Handle)>,
     num_worker: usize,
     chain: Arc<BlockChain>,
     blockdb: Arc<BlockDatabase>,
+    statedb: Arc<StateDatabase>,
+    snapshot_sync: Option<Arc<SnapshotSync>>,
     wallet: Arc<Wallet>,
     mempool: Arc<Mutex<MemoryPool>>,
     context_update_chan: channel::Sender<ContextUpdateSignal>,
@@ -43,6 +50,8 @@ pub fn new(
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
     blockchain: &Arc<BlockChain>,
     blockdb: &Arc<BlockDatabase>,
+    statedb: &Arc<StateDatabase>,
+    snapshot_sync: Option<&Arc<SnapshotSync>>,
     wallet: &Arc<Wallet>,
     mempool: &Arc<Mutex<MemoryPool>>,
     ctx_update_sink: channel::Sender<ContextUpdateSignal>,
@@ -54,6 +63,8 @@ pub fn new(
         num_worker,
         chain: Arc::clone(blockchain),
         blockdb: Arc::clone(blockdb),
+        statedb: Arc::clone(statedb),
+        snapshot_sync: snapshot_sync.cloned(),
         wallet: Arc::clone(wallet),
         mempool: Arc::clone(mempool),
         context_update_chan: ctx_update_sink,
@@ -122,7 +133,7 @@ impl Context {
                         new_transaction(transaction, &self.mempool, &self.server);
                     }
                 }
-                Message::NewBlockHashes(hashes) => {
+                Message::NewBlockHashes(hashes) | Message::BlockIndex(hashes) => {
                     debug!("Got {} new block hashes", hashes.len());
                     let mut hashes_to_request = vec![];
                     for hash in hashes {
@@ -234,107 +245,204 @@ impl Context {
                         .broadcast(Message::NewBlockHashes(hashes.clone()));
 
                     // process each block
-                    let mut to_process: Vec<Block> = blocks;
-                    let mut to_request: Vec<H256> = vec![];
-                    let mut context_update_sig = vec![];
-                    while let Some(block) = to_process.pop() {
-                        // check data availability
-                        // make sure checking data availability and buffering are one atomic
-                        // operation. see the comments in buffer.rs
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let data_availability =
-                            validation::check_data_availability(&block, &self.chain, &self.blockdb);
-                        match data_availability {
-                            BlockResult::Pass => drop(buffer),
-                            BlockResult::MissingReferences(r) => {
-                                debug!(
-                                    "Missing {} referred blocks for block {:.8}",
-                                    r.len(),
-                                    block.hash()
-                                );
-                                buffer.insert(block, &r);
-                                to_request.extend_from_slice(&r);
-                                drop(buffer);
-                                continue;
-                            }
-                            _ => unreachable!(),
+                    self.process_blocks(blocks, &peer);
+                }
+                Message::Bootstrap(after) => {
+                    debug!("Asked for all blocks after {}", &after);
+                    /*
+                     * TODO: recover this message
+                    for batch in self.blockdb.blocks_after(&after, 500) {
+                        peer.write(Message::Blocks(batch));
+                    }
+                    */
+                }
+                Message::GetSnapshotManifest => {
+                    debug!("Asked for the snapshot manifest");
+                    let manifest = self.statedb.snapshot_manifest().unwrap();
+                    peer.write(Message::SnapshotManifest(manifest.map(|m| m.into_rlp())));
+                }
+                Message::SnapshotManifest(manifest) => {
+                    let sync = match &self.snapshot_sync {
+                        Some(sync) => sync,
+                        None => continue,
+                    };
+                    let manifest = match manifest.map(|m| ManifestData::from_rlp(&m)) {
+                        Some(Ok(manifest)) => manifest,
+                        Some(Err(e)) => {
+                            warn!("Ignoring invalid snapshot manifest: {}", e);
+                            continue;
                         }
-
-                        // check sortition proof and content semantics
-                        let sortition_proof =
-                            validation::check_sortition_proof(&block, &self.config);
-                        match sortition_proof {
-                            BlockResult::Pass => {}
-                            _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    sortition_proof
-                                );
-                                continue;
+                        None => continue,
+                    };
+                    debug!("Got the manifest of the snapshot at level {}", manifest.block_number);
+                    let level = manifest.block_number;
+                    match sync.begin(manifest) {
+                        Ok(Some(hashes)) => {
+                            info!(
+                                "Restoring the state at level {}, {} snapshot chunks to download",
+                                level,
+                                hashes.len()
+                            );
+                            for batch in hashes.chunks(SNAPSHOT_CHUNK_BATCH) {
+                                peer.write(Message::GetSnapshotChunks(
+                                    batch.iter().map(|h| h.into()).collect(),
+                                ));
                             }
                         }
-                        let content_semantic =
-                            validation::check_content_semantic(&block, &self.chain, &self.blockdb);
-                        match content_semantic {
-                            BlockResult::Pass => {}
-                            _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    content_semantic
-                                );
-                                continue;
+                        Ok(None) => {}
+                        Err(e) => warn!("Error restoring the state: {}", e),
+                    }
+                }
+                Message::GetSnapshotChunks(hashes) => {
+                    debug!("Asked for {} snapshot chunks", hashes.len());
+                    let mut chunks = vec![];
+                    for hash in hashes {
+                        if let Some(chunk) = self.statedb.snapshot_chunk(&hash.into()).unwrap() {
+                            chunks.push(chunk);
+                        }
+                    }
+                    peer.write(Message::SnapshotChunks(chunks));
+                }
+                Message::SnapshotChunks(chunks) => {
+                    debug!("Got {} snapshot chunks", chunks.len());
+                    if let Some(sync) = &self.snapshot_sync {
+                        match sync.feed(&chunks) {
+                            Ok(true) => {
+                                info!("State restored from the snapshot");
+                                let level = sync.restored_level().unwrap();
+                                // blocks buffered for what the snapshot made unnecessary
+                                let mut buffer = self.buffer.lock().unwrap();
+                                let released = buffer.waive(|block, missing| {
+                                    validation::waive_snapshot_references(
+                                        block,
+                                        &self.chain,
+                                        level,
+                                        missing,
+                                    )
+                                });
+                                drop(buffer);
+                                self.process_blocks(released, &peer);
+                                // sync the blockchain above the snapshot
+                                self.server.broadcast(Message::GetBlockIndex(level));
                             }
+                            Ok(false) => {}
+                            Err(e) => warn!("Error restoring the state, snapshot abandoned: {}", e),
                         }
+                    }
+                }
+                Message::GetBlockIndex(level) => {
+                    debug!("Asked for the blocks above level {}", level);
+                    peer.write(Message::BlockIndex(self.chain.blocks_above(level).unwrap()));
+                }
+            }
+        }
+    }
 
-                        debug!("Processing block {:.8}", block.hash());
-                        new_validated_block(
-                            &block,
-                            &self.mempool,
-                            &self.blockdb,
-                            &self.chain,
-                            &self.server,
-                        );
-                        context_update_sig.push(match &block.content {
-                            Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
-                            Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
-                            Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
-                        });
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let mut resolved_by_current = buffer.satisfy(block.hash());
+    /// Validate and insert the blocks, along with the buffered blocks they resolve. Blocks with
+    /// missing parents or references are buffered, and the missing blocks asked from the peer.
+    fn process_blocks(&self, blocks: Vec<Block>, peer: &peer::Handle) {
+        let mut to_process: Vec<Block> = blocks;
+        let mut to_request: Vec<H256> = vec![];
+        let mut context_update_sig = vec![];
+        while let Some(block) = to_process.pop() {
+            // check data availability
+            // make sure checking data availability and buffering are one atomic
+            // operation. see the comments in buffer.rs
+            let mut buffer = self.buffer.lock().unwrap();
+            let data_availability =
+                validation::check_data_availability(&block, &self.chain, &self.blockdb);
+            match data_availability {
+                BlockResult::Pass => drop(buffer),
+                BlockResult::MissingReferences(r) => {
+                    debug!(
+                        "Missing {} referred blocks for block {:.8}",
+                        r.len(),
+                        block.hash()
+                    );
+                    to_request.extend_from_slice(&r);
+                    let r = self.waive_snapshot_references(&block, r);
+                    if !r.is_empty() {
+                        buffer.insert(block, &r);
                         drop(buffer);
-                        if !resolved_by_current.is_empty() {
-                            debug!(
-                                "Resolved dependency for {} buffered blocks",
-                                resolved_by_current.len()
-                            );
-                        }
-                        for b in resolved_by_current.drain(..) {
-                            to_process.push(b);
-                        }
-                    }
-                    // tell the miner to update the context
-                    for sig in context_update_sig {
-                        self.context_update_chan.send(sig).unwrap();
+                        continue;
                     }
+                    drop(buffer);
+                }
+                _ => unreachable!(),
+            }
 
-                    if !to_request.is_empty() {
-                        to_request.sort();
-                        to_request.dedup();
-                        peer.write(Message::GetBlocks(to_request));
-                    }
+            // check sortition proof and content semantics
+            let sortition_proof = validation::check_sortition_proof(&block, &self.config);
+            match sortition_proof {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!(
+                        "Ignoring invalid block {:.8}: {}",
+                        block.hash(),
+                        sortition_proof
+                    );
+                    continue;
                 }
-                Message::Bootstrap(after) => {
-                    debug!("Asked for all blocks after {}", &after);
-                    /*
-                     * TODO: recover this message
-                    for batch in self.blockdb.blocks_after(&after, 500) {
-                        peer.write(Message::Blocks(batch));
-                    }
-                    */
+            }
+            let content_semantic =
+                validation::check_content_semantic(&block, &self.chain, &self.blockdb);
+            match content_semantic {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!(
+                        "Ignoring invalid block {:.8}: {}",
+                        block.hash(),
+                        content_semantic
+                    );
+                    continue;
                 }
             }
+
+            debug!("Processing block {:.8}", block.hash());
+            new_validated_block(
+                &block,
+                &self.mempool,
+                &self.blockdb,
+                &self.chain,
+                &self.server,
+            );
+            context_update_sig.push(match &block.content {
+                Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
+                Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
+                Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
+            });
+            let mut buffer = self.buffer.lock().unwrap();
+            let mut resolved_by_current = buffer.satisfy(block.hash());
+            drop(buffer);
+            if !resolved_by_current.is_empty() {
+                debug!(
+                    "Resolved dependency for {} buffered blocks",
+                    resolved_by_current.len()
+                );
+            }
+            for b in resolved_by_current.drain(..) {
+                to_process.push(b);
+            }
+        }
+        // tell the miner to update the context
+        for sig in context_update_sig {
+            self.context_update_chan.send(sig).unwrap();
+        }
+
+        if !to_request.is_empty() {
+            to_request.sort();
+            to_request.dedup();
+            peer.write(Message::GetBlocks(to_request));
+        }
+    }
+
+    /// Drop the missing references a block need not wait for after a snapshot sync.
+    fn waive_snapshot_references(&self, block: &Block, missing: Vec<H256>) -> Vec<H256> {
+        match self.snapshot_sync.as_ref().and_then(|sync| sync.restored_level()) {
+            Some(level) => {
+                validation::waive_snapshot_references(block, &self.chain, level, missing)
+            }
+            None => missing,
         }
     }
 }
@@ -0,0 +1,446 @@
+use super::*;
+use account_state::state::State;
+use common_types::errors::{EthcoreError, SnapshotError};
+use common_types::ids::BlockId;
+use common_types::snapshot::{ManifestData, Progress};
+use hash_db::AsHashDB;
+use keccak_hash::keccak;
+use kvdb::KeyValueDB;
+use log::warn;
+use snapshot::{SnapshotWriter, StateRebuilder};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Condvar;
+use std::time::{Duration, Instant};
+
+/// Prefix of the keys of the chunks of the latest snapshot.
+const CHUNK_PREFIX: u8 = b'c';
+/// Key of the manifest of the latest snapshot.
+const MANIFEST_KEY: &[u8] = b"manifest";
+
+/// Writes the state chunks of a snapshot to the database as they are made.
+struct ChunkWriter(Arc<dyn KeyValueDB>);
+
+impl SnapshotWriter for ChunkWriter {
+    fn write_state_chunk(&mut self, hash: H256, chunk: &[u8]) -> std::io::Result<()> {
+        let mut batch = DBTransaction::new();
+        batch.put(COL_EXTRA, &chunk_key(&hash), chunk);
+        self.0.write(batch)
+    }
+
+    // a Prism snapshot has no block chunks, the blockchain is synced from blocks
+    fn write_block_chunk(&mut self, _hash: H256, _chunk: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(self, _manifest: ManifestData) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StateDatabase {
+    /// Take a snapshot of the state after the given ledger level was executed, in the state
+    /// chunk format of parity-ethereum, and keep it as the latest snapshot in place of the one
+    /// before. `leader` is the leader of the level, which the manifest names.
+    pub fn take_snapshot(&self, level: u64, leader: &H256) -> Result<ManifestData> {
+        let root = match self.level_root(level)? {
+            Some(root) => root,
+            None => {
+                return Err(EthcoreError::from(SnapshotError::InvalidStartingBlock(
+                    BlockId::Number(level),
+                ))
+                .into())
+            }
+        };
+        let backing = self.backing();
+        // reading through a journal db of its own does not hold up the execution
+        let journal_db = journaldb::new(Arc::clone(&backing), journaldb::Algorithm::Archive, 0);
+        let writer = parking_lot::Mutex::new(ChunkWriter(Arc::clone(&backing)));
+        let progress = parking_lot::RwLock::new(Progress::new());
+        let state_hashes =
+            snapshot::chunk_state(journal_db.as_hash_db(), &root, &writer, &progress, None, 0)
+                .map_err(EthcoreError::from)?;
+        let manifest = ManifestData {
+            version: snapshot::io::SNAPSHOT_VERSION,
+            state_hashes,
+            block_hashes: vec![],
+            state_root: root,
+            block_number: level,
+            block_hash: *leader,
+        };
+
+        let mut batch = DBTransaction::new();
+        if let Some(old) = self.snapshot_manifest()? {
+            let kept: HashSet<&H256> = manifest.state_hashes.iter().collect();
+            for hash in old.state_hashes.iter().filter(|h| !kept.contains(h)) {
+                batch.delete(COL_EXTRA, &chunk_key(hash));
+            }
+        }
+        batch.put(COL_EXTRA, MANIFEST_KEY, &manifest.clone().into_rlp());
+        backing.write(batch)?;
+        Ok(manifest)
+    }
+
+    /// The manifest of the latest snapshot, or None if no snapshot was taken.
+    pub fn snapshot_manifest(&self) -> Result<Option<ManifestData>> {
+        let value = self.backing().get(COL_EXTRA, MANIFEST_KEY)?;
+        Ok(value.map(|v| ManifestData::from_rlp(&v).unwrap()))
+    }
+
+    /// A compressed state chunk of the latest snapshot.
+    pub fn snapshot_chunk(&self, hash: &H256) -> Result<Option<Bytes>> {
+        Ok(self.backing().get(COL_EXTRA, &chunk_key(hash))?)
+    }
+
+    /// Start restoring the state from the snapshot of the manifest. The chunks are written to
+    /// the database as they are fed, and the state is replaced when the restoration finishes.
+    pub fn begin_restoration(&self, manifest: ManifestData) -> StateRestoration {
+        StateRestoration {
+            pending: manifest.state_hashes.iter().cloned().collect(),
+            rebuilder: StateRebuilder::new(self.backing(), journaldb::Algorithm::Archive),
+            manifest,
+        }
+    }
+
+    /// Check the root of a restoration that was fed every chunk, and replace the state with the
+    /// restored one, as the state after the ledger level of the snapshot. Snapshots carry no
+    /// preimages of the hashed keys, so exporting the restored accounts fails with
+    /// `InvalidStateFile`.
+    pub fn finish_restoration(&self, restoration: StateRestoration) -> Result<()> {
+        let manifest = restoration.manifest;
+        if !restoration.pending.is_empty() {
+            return Err(EthcoreError::from(SnapshotError::WrongChunkFormat(format!(
+                "{} chunks not restored",
+                restoration.pending.len()
+            )))
+            .into());
+        }
+        let root = restoration.rebuilder.state_root();
+        if root != manifest.state_root {
+            return Err(
+                EthcoreError::from(SnapshotError::WrongStateRoot(manifest.state_root, root)).into(),
+            );
+        }
+        let journal_db = restoration
+            .rebuilder
+            .finalize(manifest.block_number, manifest.block_hash)?;
+        journal_db.backing().flush()?;
+        let client_config = ethcore::client::ClientConfig::default();
+        // a new state db loads the account bloom the restoration wrote
+        let state_db = state_db::StateDB::new(journal_db, client_config.state_cache_size);
+        let mut state = self.state.lock().unwrap();
+        *state = State::from_existing(
+            state_db,
+            root,
+            self.machine.params().account_start_nonce,
+            factories(),
+        )?;
+        drop(state);
+        self.record_level_root(manifest.block_number, &root)?;
+        Ok(())
+    }
+
+    fn backing(&self) -> Arc<dyn KeyValueDB> {
+        let state = self.state.lock().unwrap();
+        Arc::clone(state.db().journal_db().backing())
+    }
+}
+
+/// Restoration of the state from the chunks of a snapshot, which are fed in any order.
+pub struct StateRestoration {
+    manifest: ManifestData,
+    pending: HashSet<H256>,
+    rebuilder: StateRebuilder,
+}
+
+impl StateRestoration {
+    pub fn manifest(&self) -> &ManifestData {
+        &self.manifest
+    }
+
+    /// Chunks not fed yet.
+    pub fn pending(&self) -> Vec<H256> {
+        self.pending.iter().cloned().collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Feed a compressed chunk. Returns false if it is not a chunk of the snapshot or was
+    /// already fed. After an error, the restoration must be abandoned.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<bool> {
+        let hash = keccak(chunk);
+        if !self.pending.remove(&hash) {
+            return Ok(false);
+        }
+        let raw = snappy::decompress(chunk).map_err(|_| {
+            EthcoreError::from(SnapshotError::WrongChunkFormat(format!(
+                "chunk {:?} is not compressed",
+                hash
+            )))
+        })?;
+        self.rebuilder.feed(&raw, &AtomicBool::new(true))?;
+        Ok(true)
+    }
+}
+
+/// Snapshot sync of a fresh node. The state is restored from the snapshot of a peer at a given
+/// ledger level and state root, and the ledger is executed from the level after it. Blocks do
+/// not commit to the state, so the level and the root come from the operator, who takes them
+/// from a trusted node. The chunks are checked against the manifest and the root. Only the state
+/// is synced this way: the blockchain index above the level is still built from the blocks.
+pub struct SnapshotSync {
+    statedb: Arc<StateDatabase>,
+    /// Ledger level and state root of the snapshot to restore.
+    level: u64,
+    root: H256,
+    restoration: Mutex<Option<StateRestoration>>,
+    /// When the last restoration began or was fed a chunk.
+    progress: Mutex<Instant>,
+    /// Level of the restored snapshot.
+    restored: Mutex<Option<u64>>,
+    restored_cvar: Condvar,
+    /// Whether the sync was given up, after which manifests are ignored.
+    abandoned: AtomicBool,
+}
+
+impl SnapshotSync {
+    pub fn new(statedb: &Arc<StateDatabase>, level: u64, root: H256) -> Self {
+        Self {
+            statedb: Arc::clone(statedb),
+            level,
+            root,
+            restoration: Mutex::new(None),
+            progress: Mutex::new(Instant::now()),
+            restored: Mutex::new(None),
+            restored_cvar: Condvar::new(),
+            abandoned: AtomicBool::new(false),
+        }
+    }
+
+    /// Start restoring from the snapshot of the manifest. Returns the chunks to download, or
+    /// None if the manifest is ignored, as it is unless it is of the snapshot to restore. The
+    /// manifest of the restoration under way gives the chunks still missing, so that they can be
+    /// asked again.
+    pub fn begin(&self, manifest: ManifestData) -> Result<Option<Vec<H256>>> {
+        if self.is_restored()
+            || manifest.version != snapshot::io::SNAPSHOT_VERSION
+            || manifest.block_number != self.level
+            || manifest.state_root != self.root
+        {
+            return Ok(None);
+        }
+        let mut restoration = self.restoration.lock().unwrap();
+        if self.abandoned.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        if let Some(current) = restoration.as_ref() {
+            if current.manifest() == &manifest {
+                return Ok(Some(current.pending()));
+            }
+            return Ok(None);
+        }
+        let started = self.statedb.begin_restoration(manifest);
+        let pending = started.pending();
+        if started.is_complete() {
+            self.finish(started)?;
+        } else {
+            *restoration = Some(started);
+            *self.progress.lock().unwrap() = Instant::now();
+        }
+        Ok(Some(pending))
+    }
+
+    /// Feed downloaded chunks into the restoration under way. Returns true if they finished it.
+    /// After an error, the restoration is abandoned so that another manifest can be tried.
+    pub fn feed(&self, chunks: &[Bytes]) -> Result<bool> {
+        let mut restoration = self.restoration.lock().unwrap();
+        let mut current = match restoration.take() {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        let mut fed = false;
+        for chunk in chunks {
+            fed |= current.feed(chunk)?;
+        }
+        if fed {
+            *self.progress.lock().unwrap() = Instant::now();
+        }
+        if current.is_complete() {
+            self.finish(current)?;
+            Ok(true)
+        } else {
+            *restoration = Some(current);
+            Ok(false)
+        }
+    }
+
+    pub fn is_restored(&self) -> bool {
+        self.restored.lock().unwrap().is_some()
+    }
+
+    /// The level of the restored snapshot, or None if the state is not restored yet.
+    pub fn restored_level(&self) -> Option<u64> {
+        *self.restored.lock().unwrap()
+    }
+
+    /// Whether the state was restored or the sync given up.
+    pub fn is_finished(&self) -> bool {
+        self.is_restored() || self.abandoned.load(Ordering::SeqCst)
+    }
+
+    /// Block until the state is restored, and return the level of the snapshot. A restoration
+    /// that is not fed a chunk within `timeout` is dropped, so that the manifest of another peer
+    /// can be tried. Returns None if no restoration began within `timeout`, after which the sync
+    /// is given up, so that the ledger can be executed from the start instead.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<u64> {
+        loop {
+            let deadline = *self.progress.lock().unwrap() + timeout;
+            let mut restored = self.restored.lock().unwrap();
+            while restored.is_none() {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                restored = self.restored_cvar.wait_timeout(restored, deadline - now).unwrap().0;
+            }
+            if let Some(level) = *restored {
+                return Some(level);
+            }
+            drop(restored);
+            // a restoration finishes with its lock held, which is taken first
+            let mut restoration = self.restoration.lock().unwrap();
+            if let Some(level) = *self.restored.lock().unwrap() {
+                return Some(level);
+            }
+            let mut progress = self.progress.lock().unwrap();
+            if *progress + timeout > Instant::now() {
+                // a chunk was fed since the deadline was read
+                continue;
+            }
+            if restoration.is_none() {
+                self.abandoned.store(true, Ordering::SeqCst);
+                return None;
+            }
+            warn!("Snapshot restoration stalled, waiting for another manifest");
+            *restoration = None;
+            *progress = Instant::now();
+        }
+    }
+
+    fn finish(&self, restoration: StateRestoration) -> Result<()> {
+        let level = restoration.manifest.block_number;
+        self.statedb.finish_restoration(restoration)?;
+        *self.restored.lock().unwrap() = Some(level);
+        self.restored_cvar.notify_all();
+        Ok(())
+    }
+}
+
+fn chunk_key(hash: &H256) -> Vec<u8> {
+    let mut key = vec![CHUNK_PREFIX];
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_and_restore() {
+        let spec = ChainSpec::prism_test();
+        let statedb =
+            StateDatabase::new("/tmp/prism_test_statedb_chunks_snapshot.rocksdb", &spec).unwrap();
+        let keypair: KeyPair = Random.generate().unwrap();
+        statedb.add_balance(&keypair.address(), &1_000_000.into()).unwrap();
+        for i in 0..100 {
+            statedb.add_balance(&Address::from_low_u64_be(i + 1), &i.into()).unwrap();
+        }
+        /* the constructor stores 0x2a at 1
+         * PUSH1 0x2a PUSH1 1 SSTORE
+         */
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: hex::decode("602a600155").unwrap(),
+        }
+        .sign(&keypair.secret(), None);
+        statedb.apply(&t).unwrap();
+        let (contract, _) = machine::executive::contract_address(
+            vm::CreateContractAddress::FromSenderAndNonce,
+            &keypair.address(),
+            &0.into(),
+            &[],
+        );
+        statedb.commit().unwrap();
+        let root = statedb.root();
+        let leader = H256::from_low_u64_be(0x1e);
+        assert!(statedb.take_snapshot(5, &leader).is_err());
+        statedb.record_level_root(5, &root).unwrap();
+        let manifest = statedb.take_snapshot(5, &leader).unwrap();
+        assert_eq!(statedb.snapshot_manifest().unwrap(), Some(manifest.clone()));
+        assert_eq!(manifest.state_root, root);
+
+        let fresh =
+            StateDatabase::new("/tmp/prism_test_statedb_chunks_restore.rocksdb", &spec).unwrap();
+        let fresh = Arc::new(fresh);
+        let sync = SnapshotSync::new(&fresh, 5, root);
+        let pending = sync.begin(manifest.clone()).unwrap().unwrap();
+        assert_eq!(pending.len(), manifest.state_hashes.len());
+        // the same manifest gives the missing chunks again, another one is ignored
+        assert_eq!(sync.begin(manifest.clone()).unwrap().unwrap().len(), pending.len());
+        let mut earlier = manifest.clone();
+        earlier.block_number = 4;
+        assert!(sync.begin(earlier).unwrap().is_none());
+        let mut forged = manifest.clone();
+        forged.state_root = H256::from_low_u64_be(1);
+        assert!(sync.begin(forged).unwrap().is_none());
+        let chunks: Vec<Bytes> = pending
+            .iter()
+            .map(|h| statedb.snapshot_chunk(h).unwrap().unwrap())
+            .collect();
+        // a chunk of something else is ignored
+        assert!(!sync.feed(&[b"junk".to_vec()]).unwrap());
+        assert!(sync.feed(&chunks).unwrap());
+        assert_eq!(sync.wait_timeout(Duration::from_secs(0)), Some(5));
+        assert!(sync.begin(manifest.clone()).unwrap().is_none());
+
+        assert_eq!(fresh.root(), root);
+        assert_eq!(fresh.level_root(5).unwrap(), Some(root));
+        assert_eq!(fresh.balance(&keypair.address()).unwrap(), 1_000_000.into());
+        assert_eq!(fresh.balance(&Address::from_low_u64_be(50)).unwrap(), 49.into());
+        assert_eq!(
+            fresh.storage_at(&contract, &H256::from_low_u64_be(1)).unwrap(),
+            H256::from_low_u64_be(0x2a)
+        );
+        for addr in spec.contracts.values() {
+            assert_eq!(fresh.code(addr).unwrap(), statedb.code(addr).unwrap());
+        }
+        // the restored tries have no preimages of the hashed addresses to export
+        match fresh.export(&root, StateFormat::Json, &mut vec![]) {
+            Err(StateDatabaseError::InvalidStateFile(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // chunks that do not restore the pinned root are rejected
+        let mut wrong = manifest.clone();
+        wrong.state_root = H256::from_low_u64_be(1);
+        let fresh = StateDatabase::new("/tmp/prism_test_statedb_chunks_wrong.rocksdb", &spec).unwrap();
+        let sync = SnapshotSync::new(&Arc::new(fresh), 5, wrong.state_root);
+        assert!(sync.begin(manifest.clone()).unwrap().is_none());
+        sync.begin(wrong).unwrap().unwrap();
+        assert!(sync.feed(&chunks).is_err());
+        assert!(!sync.is_restored());
+        assert!(!sync.is_finished());
+
+        // without a manifest, the sync is given up and later manifests are ignored
+        assert_eq!(sync.wait_timeout(Duration::from_millis(10)), None);
+        assert!(sync.is_finished());
+        assert!(sync.begin(manifest).unwrap().is_none());
+    }
+}
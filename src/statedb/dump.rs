@@ -0,0 +1,353 @@
+use super::*;
+use account_state::state::State;
+use common_types::basic_account::BasicAccount;
+use ethereum_types::BigEndianHash;
+use hash_db::{AsHashDB, HashDB};
+use keccak_hash::{keccak, KECCAK_EMPTY};
+use patricia_trie_ethereum::trie::Trie;
+use pod::{PodAccount, PodState};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+
+/// Magic bytes at the start of a binary state file.
+const BINARY_MAGIC: &[u8; 8] = b"PRISMSTA";
+
+/// Number of accounts imported between commits, so that the imported state is not held in
+/// memory all at once.
+const IMPORT_BATCH: usize = 1000;
+
+/// Format of a state file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StateFormat {
+    /// A JSON object of the accounts by address, in the format of `PodState`.
+    Json,
+    /// The state root followed by the accounts one after another, encoded with bincode. Unlike
+    /// JSON, it is also imported one account at a time.
+    Binary,
+}
+
+impl std::str::FromStr for StateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StateFormat::Json),
+            "binary" => Ok(StateFormat::Binary),
+            _ => Err(format!("unknown state file format {}", s)),
+        }
+    }
+}
+
+/// An account in a binary state file.
+#[derive(Serialize, Deserialize)]
+struct AccountRecord {
+    address: Address,
+    balance: U256,
+    nonce: U256,
+    code: Bytes,
+    storage: Vec<(H256, H256)>,
+    version: U256,
+}
+
+/// An account in a JSON state file, as `PodAccount` is serialized.
+#[derive(Deserialize)]
+struct JsonAccount {
+    balance: U256,
+    nonce: U256,
+    code: String,
+    storage: BTreeMap<H256, H256>,
+    #[serde(default)]
+    version: U256,
+}
+
+impl StateDatabase {
+    /// Write every account of the state at `root`, with its code and storage, to `writer`. The
+    /// accounts are read from the database one at a time, in the order of their hashed
+    /// addresses.
+    pub fn export<W: Write>(&self, root: &H256, format: StateFormat, mut writer: W) -> Result<()> {
+        let state = self.state_at(root)?;
+        match format {
+            StateFormat::Json => {
+                writer.write_all(b"{")?;
+                let mut first = true;
+                for_each_account(&state, |address, account| {
+                    if !first {
+                        writer.write_all(b",")?;
+                    }
+                    first = false;
+                    serde_json::to_writer(&mut writer, &address)?;
+                    writer.write_all(b":")?;
+                    serde_json::to_writer(&mut writer, &account)?;
+                    Ok(())
+                })?;
+                writer.write_all(b"}")?;
+            }
+            StateFormat::Binary => {
+                writer.write_all(BINARY_MAGIC)?;
+                bincode::serialize_into(&mut writer, root)?;
+                for_each_account(&state, |address, account| {
+                    let record = AccountRecord {
+                        address,
+                        balance: account.balance,
+                        nonce: account.nonce,
+                        code: account.code.unwrap_or_default(),
+                        storage: account.storage.into_iter().collect(),
+                        version: account.version,
+                    };
+                    bincode::serialize_into(&mut writer, &record)?;
+                    Ok(())
+                })?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replace the state with the accounts read from `reader`, commit it, and record it as the
+    /// genesis state of the database, which the node starts from when it keeps the database.
+    /// Returns the new state root. The root of a binary file is checked.
+    pub fn import<R: BufRead>(&self, format: StateFormat, mut reader: R) -> Result<H256> {
+        let mut state = self.state.lock().unwrap();
+        *state = State::new(
+            state.db().boxed_clone(),
+            self.machine.params().account_start_nonce,
+            factories(),
+        );
+        let mut pending: BTreeMap<Address, PodAccount> = BTreeMap::new();
+        let mut add = |state: &mut State<state_db::StateDB>,
+                       address: Address,
+                       account: PodAccount|
+         -> Result<()> {
+            pending.insert(address, account);
+            if pending.len() >= IMPORT_BATCH {
+                state.populate_from(PodState::from(std::mem::replace(&mut pending, BTreeMap::new())));
+                commit_state(state)?;
+            }
+            Ok(())
+        };
+        let expected = match format {
+            StateFormat::Json => {
+                let accounts: BTreeMap<Address, JsonAccount> = serde_json::from_reader(&mut reader)?;
+                for (address, account) in accounts {
+                    let code = hex::decode(account.code.trim_start_matches("0x"))
+                        .map_err(|e| StateDatabaseError::InvalidStateFile(e.to_string()))?;
+                    let account = PodAccount {
+                        balance: account.balance,
+                        nonce: account.nonce,
+                        code: Some(code),
+                        storage: account.storage,
+                        version: account.version,
+                    };
+                    add(&mut *state, address, account)?;
+                }
+                None
+            }
+            StateFormat::Binary => {
+                let mut magic = [0u8; 8];
+                reader.read_exact(&mut magic)?;
+                if &magic != BINARY_MAGIC {
+                    return Err(StateDatabaseError::InvalidStateFile(
+                        "not a binary state file".to_string(),
+                    ));
+                }
+                let root: H256 = bincode::deserialize_from(&mut reader)?;
+                while !reader.fill_buf()?.is_empty() {
+                    let record: AccountRecord = bincode::deserialize_from(&mut reader)?;
+                    let account = PodAccount {
+                        balance: record.balance,
+                        nonce: record.nonce,
+                        code: Some(record.code),
+                        storage: record.storage.into_iter().collect(),
+                        version: record.version,
+                    };
+                    add(&mut *state, record.address, account)?;
+                }
+                Some(root)
+            }
+        };
+        state.populate_from(PodState::from(pending));
+        commit_state(&mut state)?;
+        let root = *state.root();
+        if let Some(expected) = expected {
+            if root != expected {
+                return Err(StateDatabaseError::InvalidStateFile(format!(
+                    "state root {:?} of the accounts differs from {:?} in the file",
+                    root, expected
+                )));
+            }
+        }
+        let mut batch = DBTransaction::new();
+        batch.put(COL_EXTRA, &view::level_key(0), root.as_bytes());
+        state.db().journal_db().backing().write(batch)?;
+        Ok(root)
+    }
+}
+
+/// Call `f` on every account of the state, with all its storage.
+fn for_each_account<F>(state: &State<state_db::StateDB>, mut f: F) -> Result<()>
+where
+    F: FnMut(Address, PodAccount) -> Result<()>,
+{
+    let factories = factories();
+    // the tries are walked by hashed key, and the keys are looked up under the hash of the
+    // hashed key, where the fat trie writes them. Tries restored from snapshots or written before
+    // the state database used fat tries have none, which is reported instead of panicking.
+    let trie_factory = TrieFactory::new(TrieSpec::Generic, patricia_trie_ethereum::Layout);
+    let db = &state.db().as_hash_db();
+    let trie = trie_factory.readonly(db, state.root())?;
+    for item in trie.iter()? {
+        let (address_hash, value) = item?;
+        let address = match db.get(&keccak(&address_hash), hash_db::EMPTY_PREFIX) {
+            Some(address) => Address::from_slice(&address),
+            None => {
+                return Err(StateDatabaseError::InvalidStateFile(format!(
+                    "address of account hash 0x{} is missing",
+                    hex::encode(&address_hash)
+                )))
+            }
+        };
+        let account: BasicAccount = rlp::decode(&value)
+            .map_err(|e| StateDatabaseError::InvalidStateFile(e.to_string()))?;
+        let account_db = factories.accountdb.readonly(state.db().as_hash_db(), keccak(address));
+        let account_db = &account_db.as_hash_db();
+        let code = if account.code_hash == KECCAK_EMPTY {
+            vec![]
+        } else {
+            match account_db.get(&account.code_hash, hash_db::EMPTY_PREFIX) {
+                Some(code) => code.to_vec(),
+                None => {
+                    return Err(StateDatabaseError::InvalidStateFile(format!(
+                        "code of account {:?} is missing",
+                        address
+                    )))
+                }
+            }
+        };
+        let mut storage = BTreeMap::new();
+        let storage_trie = trie_factory.readonly(account_db, &account.storage_root)?;
+        for item in storage_trie.iter()? {
+            let (key_hash, value) = item?;
+            let key = match account_db.get(&keccak(&key_hash), hash_db::EMPTY_PREFIX) {
+                Some(key) => H256::from_slice(&key),
+                None => {
+                    return Err(StateDatabaseError::InvalidStateFile(format!(
+                        "storage key of hash 0x{} of account {:?} is missing",
+                        hex::encode(&key_hash),
+                        address
+                    )))
+                }
+            };
+            let value: U256 = rlp::decode(&value)
+                .map_err(|e| StateDatabaseError::InvalidStateFile(e.to_string()))?;
+            storage.insert(key, BigEndianHash::from_uint(&value));
+        }
+        f(
+            address,
+            PodAccount {
+                balance: account.balance,
+                nonce: account.nonce,
+                code: Some(code),
+                storage,
+                version: account.code_version,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Commit the changes in the cache of the state to the database.
+fn commit_state(state: &mut State<state_db::StateDB>) -> Result<()> {
+    state.commit()?;
+    state.clear();
+    let db = state.db_mut();
+    let mut batch = DBTransaction::new();
+    db.journal_under(&mut batch, 0, &Default::default())?;
+    db.journal_db().backing().write(batch)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_and_import() {
+        let spec = ChainSpec::prism_test();
+        let statedb = StateDatabase::new("/tmp/prism_test_statedb_dump_export.rocksdb", &spec).unwrap();
+        let keypair: KeyPair = Random.generate().unwrap();
+        statedb.add_balance(&keypair.address(), &1_000_000.into()).unwrap();
+        /* the constructor stores 0x2a at 1
+         * PUSH1 0x2a PUSH1 1 SSTORE
+         */
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: hex::decode("602a600155").unwrap(),
+        }
+        .sign(&keypair.secret(), None);
+        statedb.apply(&t).unwrap();
+        let (contract, _) = machine::executive::contract_address(
+            vm::CreateContractAddress::FromSenderAndNonce,
+            &keypair.address(),
+            &0.into(),
+            &[],
+        );
+        statedb.commit().unwrap();
+        let root = statedb.root();
+
+        for (format, name) in &[(StateFormat::Json, "json"), (StateFormat::Binary, "binary")] {
+            let mut file = vec![];
+            statedb.export(&root, *format, &mut file).unwrap();
+            let path = format!("/tmp/prism_test_statedb_dump_import_{}.rocksdb", name);
+            let imported = StateDatabase::new(&path, &spec).unwrap();
+            assert_eq!(imported.import(*format, &file[..]).unwrap(), root);
+            assert_eq!(imported.root(), root);
+            assert_eq!(imported.balance(&keypair.address()).unwrap(), 1_000_000.into());
+            assert_eq!(imported.nonce(&keypair.address()).unwrap(), 1.into());
+            assert_eq!(
+                imported.storage_at(&contract, &H256::from_low_u64_be(1)).unwrap(),
+                H256::from_low_u64_be(0x2a)
+            );
+            // the genesis contracts were exported with their code
+            for addr in spec.contracts.values() {
+                assert_eq!(imported.code(addr).unwrap(), statedb.code(addr).unwrap());
+            }
+            // a node keeping the database starts from the imported state
+            drop(imported);
+            let reopened = StateDatabase::open(&path, None, &spec).unwrap();
+            assert_eq!(reopened.root(), root);
+            assert_eq!(reopened.level_root(0).unwrap(), Some(root));
+        }
+
+        // a binary file must hold the accounts of its root
+        let mut file = vec![];
+        statedb.export(&root, StateFormat::Binary, &mut file).unwrap();
+        let header = BINARY_MAGIC.len() + bincode::serialized_size(&root).unwrap() as usize;
+        let mut corrupt = BINARY_MAGIC.to_vec();
+        bincode::serialize_into(&mut corrupt, &H256::zero()).unwrap();
+        corrupt.extend_from_slice(&file[header..]);
+        let imported =
+            StateDatabase::new("/tmp/prism_test_statedb_dump_import_corrupt.rocksdb", &spec).unwrap();
+        assert!(imported.import(StateFormat::Binary, &corrupt[..]).is_err());
+        assert!(imported.import(StateFormat::Binary, &b"not a state file"[..]).is_err());
+    }
+
+    #[test]
+    fn export_without_preimages() {
+        // the test state uses a plain trie, which keeps no preimages of the hashed addresses
+        let statedb = StateDatabase {
+            state: Mutex::new(ethcore::test_helpers::get_temp_state()),
+            machine: ChainSpec::prism_test().machine().unwrap(),
+        };
+        statedb.add_balance(&Address::from_low_u64_be(0xa), &100.into()).unwrap();
+        statedb.commit().unwrap();
+        let mut file = vec![];
+        match statedb.export(&statedb.root(), StateFormat::Json, &mut file) {
+            Err(StateDatabaseError::InvalidStateFile(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -1,13 +1,29 @@
+use crate::chainspec::{ChainSpec, ChainSpecError};
 use crate::crypto::hash::{Address, EthereumH256 as H256};
 use crate::transaction::{Action, Transaction, RawTransaction};
 use std::sync::{Arc, Mutex};
 
+use ethcore_db::COL_EXTRA;
 use kvdb::DBTransaction;
+use patricia_trie_ethereum::trie::{TrieFactory, TrieSpec};
 use ethereum_types::U256;
 use executive_state::ExecutiveState;
 use parity_bytes::Bytes;
 use parity_crypto::publickey::{KeyPair, Random, Secret, Public, Generator};
 
+mod chunks;
+mod dump;
+mod parallel;
+mod proof;
+mod replay;
+mod view;
+
+pub use chunks::{SnapshotSync, StateRestoration};
+pub use dump::StateFormat;
+pub use proof::{AccountProof, StorageProof};
+pub use replay::{ExecutionRecord, TraceOptions, TraceResults};
+pub use view::StateView;
+
 pub type Result<T> = std::result::Result<T, StateDatabaseError>;
 
 pub struct StateDatabase {
@@ -29,6 +45,10 @@ pub enum StateDatabaseError {
     IOError(std::io::Error),
     EthcoreError(common_types::errors::EthcoreError),
     TrieError(Box<patricia_trie_ethereum::TrieError>),
+    ChainSpecError(ChainSpecError),
+    JsonError(serde_json::Error),
+    BincodeError(Box<bincode::ErrorKind>),
+    InvalidStateFile(String),
     Trace(String,String),
 }
 
@@ -38,6 +58,10 @@ impl std::fmt::Display for StateDatabaseError {
             StateDatabaseError::IOError(ref e) => e.fmt(f),
             StateDatabaseError::EthcoreError(ref e) => e.fmt(f),
             StateDatabaseError::TrieError(ref e) => e.fmt(f),
+            StateDatabaseError::ChainSpecError(ref e) => e.fmt(f),
+            StateDatabaseError::JsonError(ref e) => e.fmt(f),
+            StateDatabaseError::BincodeError(ref e) => e.fmt(f),
+            StateDatabaseError::InvalidStateFile(ref e) => write!(f, "Invalid state file: {}", e),
             StateDatabaseError::Trace(ref e, ref t) => write!(f, "Trace: {}\n\nVMTrace: {}", e,t),
         }
     }
@@ -49,6 +73,9 @@ impl std::error::Error for StateDatabaseError {
             StateDatabaseError::IOError(ref e) => Some(e),
             StateDatabaseError::EthcoreError(ref e) => Some(e),
             StateDatabaseError::TrieError(ref e) => Some(e),
+            StateDatabaseError::ChainSpecError(ref e) => Some(e),
+            StateDatabaseError::JsonError(ref e) => Some(e),
+            StateDatabaseError::BincodeError(ref e) => Some(e),
             _ => None,
         }
     }
@@ -72,23 +99,52 @@ impl From<Box<patricia_trie_ethereum::TrieError>> for StateDatabaseError {
     }
 }
 
+impl From<ChainSpecError> for StateDatabaseError {
+    fn from(err: ChainSpecError) -> StateDatabaseError {
+        StateDatabaseError::ChainSpecError(err)
+    }
+}
+
+impl From<serde_json::Error> for StateDatabaseError {
+    fn from(err: serde_json::Error) -> StateDatabaseError {
+        StateDatabaseError::JsonError(err)
+    }
+}
+
+impl From<Box<bincode::ErrorKind>> for StateDatabaseError {
+    fn from(err: Box<bincode::ErrorKind>) -> StateDatabaseError {
+        StateDatabaseError::BincodeError(err)
+    }
+}
+
+/// Factories of the state. The tries are fat, storing the preimages of their hashed keys too,
+/// so that the accounts and their storage can be listed to export the state. The roots are the
+/// same as with secure tries.
+fn factories() -> trie_vm_factories::Factories {
+    trie_vm_factories::Factories {
+        trie: TrieFactory::new(TrieSpec::Fat, patricia_trie_ethereum::Layout),
+        ..Default::default()
+    }
+}
+
 impl StateDatabase {
-    /// Open the database at the given path, and create a new one if one is missing.
-    pub fn open<P: AsRef<std::path::Path>>(path: P, root: Option<H256>) -> Result<Self> {
-        // use this spec and the corresponding machine since it looks fine, may create our spec in the future
-        let mut spec = spec::new_prism_test();
-        let machine = spec::new_prism_test_machine();
+    /// Open the database at the given path, and create a new one with the genesis state of the
+    /// chain specification if one is missing. Without a root, the state starts from the genesis
+    /// state of the database, which may have been imported.
+    pub fn open<P: AsRef<std::path::Path>>(path: P, root: Option<H256>, chain_spec: &ChainSpec) -> Result<Self> {
+        let mut spec = chain_spec.evm_spec()?;
+        let machine = chain_spec.machine()?;
         if let Some(root) = root {
             spec.state_root = root;
         }
-        // default factories will do
-	let factories = trie_vm_factories::Factories::default();
+	let factories = factories();
         let client_config = ethcore::client::ClientConfig::default();
 
         let restoration_db_handler = parity_ethereum::db::restoration_db_handler(path.as_ref(), &client_config);
         let app_db = restoration_db_handler.open(path.as_ref())?;
-        // A journal db using app_db's kvdb as backing and column family is 0
-	let journal_db = journaldb::new(Arc::clone(app_db.key_value()), client_config.pruning, 0);
+        // A journal db using app_db's kvdb as backing and column family is 0. It is an archive,
+        // which never deletes trie nodes, so that the states of past ledger levels can be read.
+	let journal_db = journaldb::new(Arc::clone(app_db.key_value()), journaldb::Algorithm::Archive, 0);
         // state_db created by new() has parent_hash==None, so it doesn't use local (dirty) cache
 	let mut state_db = state_db::StateDB::new(journal_db, client_config.state_cache_size);
         if state_db.journal_db().is_empty() {
@@ -96,7 +152,12 @@ impl StateDatabase {
             state_db = spec.ensure_db_good(state_db, &factories)?;
             let mut batch = DBTransaction::new();
             state_db.journal_under(&mut batch, 0, &spec.genesis_header().hash())?;
+            batch.put(COL_EXTRA, &view::level_key(0), spec.state_root.as_bytes());
             state_db.journal_db().backing().write(batch)?;
+        } else if root.is_none() {
+            if let Some(genesis) = state_db.journal_db().backing().get(COL_EXTRA, &view::level_key(0))? {
+                spec.state_root = H256::from_slice(&genesis);
+            }
         }
         let state = account_state::state::State::from_existing(
             state_db,
@@ -109,12 +170,12 @@ impl StateDatabase {
     }
 
     /// Create a new database at the given path, and initialize the content.
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+    pub fn new<P: AsRef<std::path::Path>>(path: P, chain_spec: &ChainSpec) -> Result<Self> {
         //kvdb rocksdb don't have destroy??? so we use std::fs::remove_dir_all
         if path.as_ref().is_dir() {
             std::fs::remove_dir_all(&path)?;
         }
-        let db = Self::open(&path, None)?;
+        let db = Self::open(&path, None, chain_spec)?;
 
         Ok(db)
     }
@@ -124,6 +185,18 @@ impl StateDatabase {
         state.root().clone()
     }
 
+    /// Open the state at the given root, which must have been committed. Changes to it are not
+    /// written to the database.
+    pub fn state_at(&self, root: &H256) -> Result<account_state::state::State<state_db::StateDB>> {
+        let state = self.state.lock().unwrap();
+        Ok(account_state::state::State::from_existing(
+            state.db().boxed_clone(),
+            *root,
+            self.machine.params().account_start_nonce,
+            factories(),
+        )?)
+    }
+
 
     /// Increase the balance in state, use NoEmpty mode.
     pub fn add_balance(&self, a: &Address, incr: &U256) -> Result<()> {
@@ -156,27 +229,35 @@ impl StateDatabase {
         &self.machine
     }
 
+    /// Apply a transaction in a default block environment.
     pub fn apply(&self, t: &Transaction) -> Result<executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>> {
-        self.apply_with_env_info(t, None)
-    }
-
-    fn apply_with_env_info(&self, t: &Transaction, env_info: Option<&vm::EnvInfo>) -> Result<executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>> {
-        let mut default_info: vm::EnvInfo;
-        let info = match env_info {
-            Some(info) => info,
-            None => {
-                default_info = vm::EnvInfo::default();
-                // we don't care about gas limit now, so set it to max
-                default_info.gas_limit = U256::MAX;
-                // info.gas_used = gas already used of this block (we don't care so set to 0)
-                // set the block number to 1 in order to be compatible with cryptokitties
-                default_info.number = 1;
-                // give a default hash. hash doesn't affect the execution time of contracts
-                default_info.last_hashes = Arc::new(vec![Default::default()]);
-                // info.author = block miner, whom fees goes to
-                &default_info
-            }
-        };
+        self.apply_with_env_info(t, &Self::default_env_info())
+    }
+
+    /// Apply a transaction and pay the transaction fee to the given author (the block miner).
+    pub fn apply_with_author(&self, t: &Transaction, author: &Address) -> Result<executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>> {
+        let mut info = Self::default_env_info();
+        info.author = *author;
+        self.apply_with_env_info(t, &info)
+    }
+
+    /// Block environment for transactions applied outside of the ledger, such as in tests and
+    /// experiments.
+    pub fn default_env_info() -> vm::EnvInfo {
+        let mut default_info = vm::EnvInfo::default();
+        // we don't care about gas limit now, so set it to max
+        default_info.gas_limit = U256::MAX;
+        // info.gas_used = gas already used of this block (we don't care so set to 0)
+        // set the block number to 1 in order to be compatible with cryptokitties
+        default_info.number = 1;
+        // give a default hash. hash doesn't affect the execution time of contracts
+        default_info.last_hashes = Arc::new(vec![Default::default()]);
+        // info.author = block miner, whom fees goes to
+        default_info
+    }
+
+    /// Apply a transaction in the given block environment. The fee goes to `info.author`.
+    pub fn apply_with_env_info(&self, t: &Transaction, info: &vm::EnvInfo) -> Result<executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>> {
         let mut state = self.state.lock().unwrap();
         // set tracing=false
         let apply_outcome = state.apply(info, &self.machine, t, false)?;
@@ -247,7 +328,7 @@ impl StateDatabase {
 pub fn get_temp_state_database() -> StateDatabase {
     let state = ethcore::test_helpers::get_temp_state();
     let state = Mutex::new(state);
-    let machine = spec::new_prism_test_machine();
+    let machine = ChainSpec::prism_test().machine().unwrap();
     StateDatabase {
         state,
         machine,
@@ -257,16 +338,17 @@ pub fn get_temp_state_database() -> StateDatabase {
 #[cfg(test)]
 mod test {
     use super::StateDatabase;
+    use crate::chainspec::ChainSpec;
     use crate::crypto::hash::{Address, EthereumH256 as H256};
     use crate::transaction::{Action, RawTransaction};
     use ethereum_types::U256;
     use parity_crypto::publickey::{KeyPair, Random, Secret, Public, Generator};
-    use std::sync::Mutex;
+    use std::sync::{Arc, Mutex};
 
     fn get_temp_state_database() -> StateDatabase {
         let state = ethcore::test_helpers::get_temp_state();
         let state = Mutex::new(state);
-        let machine = spec::new_prism_test_machine();
+        let machine = ChainSpec::prism_test().machine().unwrap();
         StateDatabase {
             state,
             machine,
@@ -316,6 +398,70 @@ mod test {
         assert_eq!(statedb.balance(&receiver_addr).unwrap(), (100*2).into());
     }
 
+    #[test]
+    fn apply_payment_with_fee() {
+        let statedb = get_temp_state_database();
+        let keypair: KeyPair = Random.generate().unwrap();
+	let addr = keypair.address();
+        let value: U256 = 100_000.into();
+        statedb.add_balance(&addr, &value).unwrap();
+        let receiver_addr = Address::from_low_u64_be(0xa);
+        let author = Address::from_low_u64_be(0xb);
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 2.into(),
+            gas: 100_000.into(),
+            action: Action::Call(receiver_addr.clone()),
+            value: 100.into(),
+            data: vec![],
+        }.sign(&keypair.secret(), None);
+        let outcome = statedb.apply_with_author(&t, &author).unwrap();
+        // a plain payment costs 21000 gas
+        let fee = U256::from(21_000 * 2);
+        assert_eq!(outcome.receipt.gas_used, 21_000.into());
+        assert_eq!(statedb.balance(&addr).unwrap(), value - U256::from(100) - fee);
+        assert_eq!(statedb.balance(&receiver_addr).unwrap(), 100.into());
+        assert_eq!(statedb.balance(&author).unwrap(), fee);
+    }
+
+    #[test]
+    fn apply_with_env_info() {
+        let statedb = get_temp_state_database();
+        let keypair: KeyPair = Random.generate().unwrap();
+        /* the constructor stores the block context
+         * NUMBER PUSH1 0 SSTORE
+         * TIMESTAMP PUSH1 1 SSTORE
+         * COINBASE PUSH1 2 SSTORE
+         * PUSH1 1 NUMBER SUB BLOCKHASH PUSH1 3 SSTORE: the hash of the previous block
+         */
+        let init_code = hex::decode("436000554260015541600255600143034060035500").unwrap();
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 200_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: init_code,
+        }.sign(&keypair.secret(), None);
+        let mut info = StateDatabase::default_env_info();
+        info.number = 5;
+        info.timestamp = 1234;
+        info.author = Address::from_low_u64_be(0xb);
+        info.last_hashes = Arc::new(vec![H256::from_low_u64_be(4), H256::from_low_u64_be(3)]);
+        statedb.apply_with_env_info(&t, &info).unwrap();
+        let (contract_addr, _) = machine::executive::contract_address(
+            vm::CreateContractAddress::FromSenderAndNonce,
+            &keypair.address(),
+            &0.into(),
+            &[],
+        );
+        let storage = |key: u64| statedb.storage_at(&contract_addr, &H256::from_low_u64_be(key)).unwrap();
+        assert_eq!(storage(0), H256::from_low_u64_be(5));
+        assert_eq!(storage(1), H256::from_low_u64_be(1234));
+        assert_eq!(storage(2), H256::from(info.author));
+        assert_eq!(storage(3), H256::from_low_u64_be(4));
+    }
+
     #[test]
     fn apply_create_simple_contract() {
         let statedb = get_temp_state_database();
@@ -0,0 +1,401 @@
+use super::*;
+use account_state::state::State;
+use log::debug;
+use machine::executive::cleanup_mode;
+use machine::substate::Substate;
+use state_db::StateDB;
+use std::collections::HashSet;
+use vm::CleanDustMode;
+
+type ApplyOutcome = executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>;
+
+const COINBASE: u8 = 0x41;
+const PUSH1: u8 = 0x60;
+const PUSH32: u8 = 0x7f;
+
+lazy_static! {
+    /// Block author during speculative execution. Fees go to it instead of the real author, so
+    /// that transactions of the same block do not all conflict on the author's balance.
+    static ref SPECULATIVE_AUTHOR: Address =
+        Address::from_slice(&keccak_hash::keccak(b"prism speculative author").as_bytes()[12..]);
+}
+
+impl StateDatabase {
+    /// Apply the transactions of a block in order in the given block environment, paying the
+    /// fees to `info.author`, using `workers` threads. Every transaction is first executed
+    /// speculatively on its own copy of the state, recording the accounts it accesses. Then in
+    /// order, the changes of a transaction are copied into the state if it accessed no account
+    /// modified by the transactions before it, and otherwise it is executed again. The resulting
+    /// state is the same as applying the transactions one by one.
+    pub fn apply_parallel(
+        &self,
+        txs: &[Transaction],
+        info: &vm::EnvInfo,
+        workers: usize,
+    ) -> Result<Vec<ApplyOutcome>> {
+        // before EIP-658 receipts hold the state root, so the state is committed after every
+        // transaction and the copies would miss the changes
+        if txs.len() <= 1 || workers <= 1 || info.number < self.machine.params().eip658_transition
+        {
+            return txs.iter().map(|t| self.apply_with_env_info(t, info)).collect();
+        }
+        let (outcomes, reexecuted) = self.apply_speculative(txs, info, workers)?;
+        debug!(
+            "Applied {} transactions in parallel, {} executed again",
+            txs.len(),
+            reexecuted
+        );
+        Ok(outcomes)
+    }
+
+    /// Apply the transactions speculatively as in `apply_parallel`, and return the number of
+    /// transactions that had to be executed again.
+    fn apply_speculative(
+        &self,
+        txs: &[Transaction],
+        info: &vm::EnvInfo,
+        workers: usize,
+    ) -> Result<(Vec<ApplyOutcome>, usize)> {
+        let author = &info.author;
+        let mut speculative_info = info.clone();
+        speculative_info.author = *SPECULATIVE_AUTHOR;
+
+        let mut state = self.state.lock().unwrap();
+        // changes pending before the block, such as the block reward, would be copied into
+        // every snapshot and look like accesses, so they are committed to the trie first. They
+        // are written to disk with the next commit.
+        state.commit()?;
+        state.clear();
+        let mut snapshots: Vec<State<StateDB>> = txs.iter().map(|_| state.clone()).collect();
+        let chunk_size = (txs.len() + workers - 1) / workers;
+        let machine = &self.machine;
+        let speculative_info = &speculative_info;
+        // an error may come from stale balances or nonces, so it is only reported by the
+        // execution in order
+        let speculative: Vec<Option<ApplyOutcome>> = crossbeam::scope(|s| {
+            let handles: Vec<_> = txs
+                .chunks(chunk_size)
+                .zip(snapshots.chunks_mut(chunk_size))
+                .map(|(txs, snapshots)| {
+                    s.spawn(move |_| {
+                        txs.iter()
+                            .zip(snapshots.iter_mut())
+                            .map(|(t, snapshot)| snapshot.apply(speculative_info, machine, t, false).ok())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+        .unwrap();
+
+        let schedule = self.machine.schedule(info.number);
+        let mut modified = modified_accounts(&state);
+        let mut outcomes = Vec::with_capacity(txs.len());
+        let mut reexecuted = 0;
+        for ((t, outcome), snapshot) in txs.iter().zip(speculative).zip(snapshots.iter()) {
+            let fee = match &outcome {
+                Some(outcome) if !modified.contains(&SPECULATIVE_AUTHOR) => {
+                    speculative_fee(&state, snapshot, t, outcome, info)?
+                }
+                _ => None,
+            };
+            let outcome = match (outcome, fee) {
+                (Some(outcome), Some(fee))
+                    if !conflicts(&state, snapshot, t, author, &modified)? =>
+                {
+                    for (addr, dirty) in snapshot.cached_accounts() {
+                        if dirty && addr != *SPECULATIVE_AUTHOR {
+                            state.copy_account_from(snapshot, &addr);
+                            modified.insert(addr);
+                        }
+                    }
+                    // pay the author the way the transaction would have
+                    let mut substate = Substate::new();
+                    state.add_balance(author, &fee, cleanup_mode(&mut substate, &schedule))?;
+                    let min_balance = if schedule.kill_dust != CleanDustMode::Off {
+                        Some(U256::from(schedule.tx_gas).overflowing_mul(t.gas_price).0)
+                    } else {
+                        None
+                    };
+                    state.kill_garbage(
+                        &substate.touched,
+                        schedule.kill_empty,
+                        &min_balance,
+                        schedule.kill_dust == CleanDustMode::WithCodeAndStorage,
+                    )?;
+                    outcome
+                }
+                _ => {
+                    let outcome = state.apply(info, &self.machine, t, false)?;
+                    modified = modified_accounts(&state);
+                    reexecuted += 1;
+                    outcome
+                }
+            };
+            outcomes.push(outcome);
+        }
+        Ok((outcomes, reexecuted))
+    }
+}
+
+/// Accounts modified in the state since it was last committed.
+fn modified_accounts(state: &State<StateDB>) -> HashSet<Address> {
+    state
+        .cached_accounts()
+        .into_iter()
+        .filter(|(_, dirty)| *dirty)
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+/// The fee the speculative execution of a transaction paid to `SPECULATIVE_AUTHOR`, or None if
+/// the transaction did anything else to that account, such as sending it value. Anyone can fund
+/// the account, so its balance is compared to the one in `state`, which the transactions before
+/// did not modify.
+fn speculative_fee(
+    state: &State<StateDB>,
+    snapshot: &State<StateDB>,
+    t: &Transaction,
+    outcome: &ApplyOutcome,
+    info: &vm::EnvInfo,
+) -> Result<Option<U256>> {
+    let author = &*SPECULATIVE_AUTHOR;
+    // the gas used in the receipt includes the gas used by the block before
+    let gas_used = outcome.receipt.gas_used - info.gas_used;
+    let fee = gas_used.overflowing_mul(t.gas_price).0;
+    // only code can change the storage of the account
+    if snapshot.code(author)?.map_or(false, |code| !code.is_empty())
+        || snapshot.nonce(author)? != state.nonce(author)?
+    {
+        return Ok(None);
+    }
+    match state.balance(author)?.checked_add(fee) {
+        Some(balance) if balance == snapshot.balance(author)? => Ok(Some(fee)),
+        _ => Ok(None),
+    }
+}
+
+/// Whether the speculative execution of a transaction may differ from executing it on `state`:
+/// it accessed an account modified by the transactions before it, or it depends on the block
+/// author, which it did not see.
+fn conflicts(
+    state: &State<StateDB>,
+    snapshot: &State<StateDB>,
+    t: &Transaction,
+    author: &Address,
+    modified: &HashSet<Address>,
+) -> Result<bool> {
+    if let Action::Create = t.action {
+        return Ok(true);
+    }
+    for (addr, dirty) in snapshot.cached_accounts() {
+        // checked by `speculative_fee`
+        if addr == *SPECULATIVE_AUTHOR {
+            continue;
+        }
+        if addr == *author || modified.contains(&addr) {
+            return Ok(true);
+        }
+        // the code of a new contract is not what its constructor ran
+        if dirty && snapshot.code_hash(&addr)? != state.code_hash(&addr)? {
+            return Ok(true);
+        }
+        if let Some(code) = snapshot.code(&addr)? {
+            if uses_coinbase(&code) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether the code may read the block author. Push data is skipped, but anything after the
+/// code, such as embedded contracts, is scanned too.
+fn uses_coinbase(code: &[u8]) -> bool {
+    let mut i = 0;
+    while i < code.len() {
+        match code[i] {
+            COINBASE => return true,
+            op @ PUSH1..=PUSH32 => i += (op - PUSH1) as usize + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_temp_state_database() -> StateDatabase {
+        let state = ethcore::test_helpers::get_temp_state();
+        let state = Mutex::new(state);
+        let machine = ChainSpec::prism_test().machine().unwrap();
+        StateDatabase { state, machine }
+    }
+
+    #[test]
+    fn apply_parallel() {
+        let serial = get_temp_state_database();
+        let parallel = get_temp_state_database();
+        let keypairs: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        for statedb in &[&serial, &parallel] {
+            for keypair in &keypairs {
+                statedb.add_balance(&keypair.address(), &1_000_000.into()).unwrap();
+            }
+            statedb.commit().unwrap();
+        }
+        let author = Address::from_low_u64_be(0xb);
+        let new_account = Address::from_low_u64_be(0xa);
+
+        // an independent payment, a payment to a new account and one from the same account,
+        // a payment to the author, and one to the account created earlier in the block
+        let payments = vec![
+            (0, keypairs[1].address()),
+            (2, new_account),
+            (2, keypairs[3].address()),
+            (1, author),
+            (3, new_account),
+        ];
+        let mut nonces = vec![0u64; keypairs.len()];
+        let txs: Vec<Transaction> = payments
+            .into_iter()
+            .map(|(from, to)| {
+                let t = RawTransaction {
+                    nonce: nonces[from].into(),
+                    gas_price: 2.into(),
+                    gas: 100_000.into(),
+                    action: Action::Call(to),
+                    value: 100.into(),
+                    data: vec![],
+                }
+                .sign(&keypairs[from].secret(), None);
+                nonces[from] += 1;
+                t
+            })
+            .collect();
+
+        let serial_outcomes: Vec<_> = txs
+            .iter()
+            .map(|t| serial.apply_with_author(t, &author).unwrap())
+            .collect();
+        let mut info = StateDatabase::default_env_info();
+        info.author = author;
+        let parallel_outcomes = parallel.apply_parallel(&txs, &info, 4).unwrap();
+        for (s, p) in serial_outcomes.iter().zip(parallel_outcomes.iter()) {
+            assert_eq!(s.receipt.gas_used, p.receipt.gas_used);
+            assert_eq!(s.receipt.outcome, p.receipt.outcome);
+        }
+        serial.commit().unwrap();
+        parallel.commit().unwrap();
+        assert_eq!(serial.root(), parallel.root());
+        assert_eq!(parallel.balance(&new_account).unwrap(), 200.into());
+        assert_eq!(
+            parallel.balance(&author).unwrap(),
+            U256::from(100 + 5 * 21_000 * 2)
+        );
+    }
+
+    #[test]
+    fn apply_parallel_after_reward() {
+        let statedb = get_temp_state_database();
+        let keypairs: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        for keypair in &keypairs {
+            statedb.add_balance(&keypair.address(), &1_000_000.into()).unwrap();
+        }
+        statedb.commit().unwrap();
+        let author = Address::from_low_u64_be(0xb);
+        // the reward of the block is credited but not committed
+        statedb.add_balance(&author, &1_000.into()).unwrap();
+
+        // independent payments to new accounts
+        let txs: Vec<Transaction> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(i, keypair)| {
+                RawTransaction {
+                    nonce: 0.into(),
+                    gas_price: 2.into(),
+                    gas: 100_000.into(),
+                    action: Action::Call(Address::from_low_u64_be(0x100 + i as u64)),
+                    value: 100.into(),
+                    data: vec![],
+                }
+                .sign(&keypair.secret(), None)
+            })
+            .collect();
+        let mut info = StateDatabase::default_env_info();
+        info.author = author;
+        let (_, reexecuted) = statedb.apply_speculative(&txs, &info, 4).unwrap();
+        assert_eq!(reexecuted, 0);
+        statedb.commit().unwrap();
+        assert_eq!(
+            statedb.balance(&author).unwrap(),
+            U256::from(1_000 + 4 * 21_000 * 2)
+        );
+    }
+
+    #[test]
+    fn apply_parallel_speculative_author() {
+        let serial = get_temp_state_database();
+        let parallel = get_temp_state_database();
+        let keypairs: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        for statedb in &[&serial, &parallel] {
+            for keypair in &keypairs {
+                statedb.add_balance(&keypair.address(), &1_000_000.into()).unwrap();
+            }
+            // anyone can fund the speculative author
+            statedb.add_balance(&SPECULATIVE_AUTHOR, &5_000.into()).unwrap();
+            statedb.commit().unwrap();
+        }
+        let author = Address::from_low_u64_be(0xb);
+
+        // a payment to the speculative author between independent payments
+        let recipients = vec![
+            Address::from_low_u64_be(0x100),
+            *SPECULATIVE_AUTHOR,
+            Address::from_low_u64_be(0x101),
+            Address::from_low_u64_be(0x102),
+        ];
+        let txs: Vec<Transaction> = keypairs
+            .iter()
+            .zip(recipients)
+            .map(|(keypair, to)| {
+                RawTransaction {
+                    nonce: 0.into(),
+                    gas_price: 2.into(),
+                    gas: 100_000.into(),
+                    action: Action::Call(to),
+                    value: 100.into(),
+                    data: vec![],
+                }
+                .sign(&keypair.secret(), None)
+            })
+            .collect();
+
+        for t in &txs {
+            serial.apply_with_author(t, &author).unwrap();
+        }
+        let mut info = StateDatabase::default_env_info();
+        info.author = author;
+        parallel.apply_parallel(&txs, &info, 4).unwrap();
+        serial.commit().unwrap();
+        parallel.commit().unwrap();
+        assert_eq!(serial.root(), parallel.root());
+        assert_eq!(parallel.balance(&SPECULATIVE_AUTHOR).unwrap(), 5_100.into());
+        assert_eq!(parallel.balance(&author).unwrap(), U256::from(4 * 21_000 * 2));
+    }
+
+    #[test]
+    fn coinbase() {
+        // PUSH1 0x41 POP, the push data is not an instruction
+        assert!(!uses_coinbase(&[0x60, 0x41, 0x50]));
+        // COINBASE PUSH1 0 SSTORE
+        assert!(uses_coinbase(&[0x41, 0x60, 0x00, 0x55]));
+    }
+}
@@ -0,0 +1,208 @@
+use super::*;
+use common_types::basic_account::BasicAccount;
+use hash_db::HashDB;
+use keccak_hash::{keccak, KECCAK_EMPTY, KECCAK_NULL_RLP};
+use patricia_trie_ethereum::trie::Trie;
+use patricia_trie_ethereum::TrieDB;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Merkle proof of an account and some of its storage at a state root, in the format of
+/// `eth_getProof`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountProof {
+    pub address: Address,
+    pub balance: U256,
+    pub nonce: U256,
+    pub code_hash: H256,
+    /// Root of the storage trie of the account.
+    pub storage_hash: H256,
+    /// Nodes of the state trie on the path to the account, from the root.
+    #[serde(with = "hex_nodes")]
+    pub account_proof: Vec<Bytes>,
+    pub storage_proof: Vec<StorageProof>,
+}
+
+/// Merkle proof of a storage value of an account.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StorageProof {
+    pub key: H256,
+    pub value: U256,
+    /// Nodes of the storage trie on the path to the value, from the root.
+    #[serde(with = "hex_nodes")]
+    pub proof: Vec<Bytes>,
+}
+
+impl StateDatabase {
+    /// Prove the account at the given address, and the given keys of its storage, in the state
+    /// at `root`. A missing account or key is proven missing, with the values of an empty account.
+    pub fn prove(&self, root: &H256, address: &Address, keys: &[H256]) -> Result<AccountProof> {
+        let state = self.state_at(root)?;
+        let (account_proof, account) = state.prove_account(keccak(address))?;
+        let mut storage_proof = Vec::with_capacity(keys.len());
+        for key in keys {
+            let (proof, value) = state.prove_storage(keccak(address), keccak(key))?;
+            storage_proof.push(StorageProof {
+                key: *key,
+                value: U256::from_big_endian(value.as_bytes()),
+                proof,
+            });
+        }
+        Ok(AccountProof {
+            address: *address,
+            balance: account.balance,
+            nonce: account.nonce,
+            code_hash: account.code_hash,
+            storage_hash: account.storage_root,
+            account_proof,
+            storage_proof,
+        })
+    }
+}
+
+impl AccountProof {
+    /// Check the proof against a state root the verifier trusts. The nonce of a missing account
+    /// is the account start nonce of the network, which the proof cannot show.
+    pub fn verify(&self, root: &H256) -> bool {
+        match trie_get(&self.account_proof, root, &keccak(self.address)) {
+            Some(Some(value)) => match rlp::decode::<BasicAccount>(&value) {
+                Ok(account) => {
+                    if account.balance != self.balance
+                        || account.nonce != self.nonce
+                        || account.code_hash != self.code_hash
+                        || account.storage_root != self.storage_hash
+                    {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            },
+            Some(None) => {
+                if !self.balance.is_zero()
+                    || self.code_hash != KECCAK_EMPTY
+                    || self.storage_hash != KECCAK_NULL_RLP
+                {
+                    return false;
+                }
+            }
+            None => return false,
+        }
+        self.storage_proof.iter().all(|p| {
+            match trie_get(&p.proof, &self.storage_hash, &keccak(p.key)) {
+                Some(Some(value)) => rlp::decode::<U256>(&value).ok() == Some(p.value),
+                Some(None) => p.value.is_zero(),
+                None => false,
+            }
+        })
+    }
+}
+
+/// Look up a key in a trie with only the nodes of a proof. Returns None if the nodes do not
+/// lead from the root to the key or to where it would be.
+fn trie_get(nodes: &[Bytes], root: &H256, key: &H256) -> Option<Option<Vec<u8>>> {
+    let mut db = journaldb::new_memory_db();
+    for node in nodes {
+        db.insert(hash_db::EMPTY_PREFIX, &node[..]);
+    }
+    let trie = TrieDB::new(&db, root).ok()?;
+    trie.get(key.as_bytes()).ok().map(|value| value.map(|v| v.to_vec()))
+}
+
+/// Trie nodes as a list of hex strings.
+mod hex_nodes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(nodes: &[Bytes], s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.collect_seq(nodes.iter().map(|n| format!("0x{}", hex::encode(n))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<Bytes>, D::Error> {
+        let nodes: Vec<String> = Deserialize::deserialize(d)?;
+        nodes
+            .iter()
+            .map(|n| hex::decode(n.trim_start_matches("0x")).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_temp_state_database() -> StateDatabase {
+        let state = ethcore::test_helpers::get_temp_state();
+        let state = Mutex::new(state);
+        let machine = ChainSpec::prism_test().machine().unwrap();
+        StateDatabase { state, machine }
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let statedb = get_temp_state_database();
+        let keypair: KeyPair = Random.generate().unwrap();
+        let addr = keypair.address();
+        statedb.add_balance(&addr, &1_000_000.into()).unwrap();
+        for i in 0..20 {
+            statedb.add_balance(&Address::from_low_u64_be(i + 1), &i.into()).unwrap();
+        }
+        /* the constructor stores 0x2a at 1
+         * PUSH1 0x2a PUSH1 1 SSTORE
+         */
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: hex::decode("602a600155").unwrap(),
+        }
+        .sign(&keypair.secret(), None);
+        statedb.apply(&t).unwrap();
+        let (contract, _) = machine::executive::contract_address(
+            vm::CreateContractAddress::FromSenderAndNonce,
+            &addr,
+            &0.into(),
+            &[],
+        );
+        statedb.commit().unwrap();
+        let root = statedb.root();
+
+        let keys = [H256::from_low_u64_be(1), H256::from_low_u64_be(2)];
+        let proof = statedb.prove(&root, &contract, &keys).unwrap();
+        assert_eq!(proof.storage_proof[0].value, 0x2a.into());
+        assert_eq!(proof.storage_proof[1].value, 0.into());
+        assert_ne!(proof.storage_hash, KECCAK_NULL_RLP);
+        assert!(proof.verify(&root));
+
+        // the proof survives the JSON format
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: AccountProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, proof);
+
+        // tampered values and a wrong root are rejected
+        let mut tampered = proof.clone();
+        tampered.storage_proof[0].value = 0x2b.into();
+        assert!(!tampered.verify(&root));
+        let mut tampered = proof.clone();
+        tampered.balance = 1.into();
+        assert!(!tampered.verify(&root));
+        let mut tampered = proof.clone();
+        tampered.account_proof.pop();
+        assert!(!tampered.verify(&root));
+        assert!(!proof.verify(&H256::from_low_u64_be(1)));
+
+        let proof = statedb.prove(&root, &addr, &[]).unwrap();
+        assert_eq!(proof.balance, 1_000_000.into());
+        assert_eq!(proof.nonce, 1.into());
+        assert!(proof.verify(&root));
+
+        // a missing account is proven missing
+        let missing = Address::from_low_u64_be(0xdead);
+        let proof = statedb.prove(&root, &missing, &keys).unwrap();
+        assert_eq!(proof.balance, 0.into());
+        assert!(proof.verify(&root));
+        let mut tampered = proof.clone();
+        tampered.balance = 1.into();
+        assert!(!tampered.verify(&root));
+    }
+}
@@ -0,0 +1,583 @@
+use super::*;
+use super::view::credit_shortfall;
+use account_state::state::State;
+use bincode::{deserialize, serialize};
+use common_types::account_diff;
+use ethcore_db::COL_EXTRA;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use state_db::StateDB;
+use std::collections::BTreeMap;
+use trace::trace::{Action as TraceAction, Res};
+
+/// Prefix of the keys of execution records, by transaction block hash. Records are kept in the
+/// column ethcore stores block details in, which the state database does not use otherwise.
+const BLOCK_RECORD_PREFIX: u8 = b'b';
+/// Prefix of the keys of the position of executed transactions, by transaction hash.
+const TRANSACTION_RECORD_PREFIX: u8 = b't';
+
+/// How a confirmed transaction block was executed, which is enough to replay its transactions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExecutionRecord {
+    /// State root before the block was executed.
+    pub root: H256,
+    /// Reward credited to the miner of the block before its transactions.
+    pub reward: U256,
+    /// Ledger level of the block, which is the block number.
+    pub number: u64,
+    pub timestamp: u64,
+    /// Miner of the block, to whom the transaction fees go.
+    pub author: Address,
+    pub gas_limit: U256,
+    /// Hashes of the transactions executed, in order. Transactions over the gas limit are not.
+    pub transactions: Vec<H256>,
+}
+
+impl ExecutionRecord {
+    /// The block environment the transactions were executed in, given the hashes of the leaders
+    /// of the levels before.
+    pub fn env_info(&self, last_hashes: Arc<Vec<H256>>) -> vm::EnvInfo {
+        let mut info = vm::EnvInfo::default();
+        info.number = self.number;
+        info.timestamp = self.timestamp;
+        info.author = self.author;
+        info.gas_limit = self.gas_limit;
+        info.last_hashes = last_hashes;
+        info
+    }
+}
+
+/// What to return when tracing a transaction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceOptions {
+    /// The calls and contract creations made.
+    pub trace: bool,
+    /// Every instruction executed.
+    pub vm_trace: bool,
+    /// The changes to the accounts.
+    pub state_diff: bool,
+}
+
+impl StateDatabase {
+    /// Record how a transaction block was executed. The record of a transaction included in
+    /// several blocks points to the last one.
+    pub fn record_execution(&self, block: &H256, record: &ExecutionRecord) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut batch = DBTransaction::new();
+        batch.put(COL_EXTRA, &record_key(BLOCK_RECORD_PREFIX, block), &serialize(record).unwrap());
+        for (index, hash) in record.transactions.iter().enumerate() {
+            batch.put(
+                COL_EXTRA,
+                &record_key(TRANSACTION_RECORD_PREFIX, hash),
+                &serialize(&(block, index as u32)).unwrap(),
+            );
+        }
+        state.db().journal_db().backing().write(batch)?;
+        Ok(())
+    }
+
+    /// Delete the execution record of a transaction block, and the locations of its
+    /// transactions unless they point to a later block.
+    pub fn prune_execution(&self, block: &H256) -> Result<()> {
+        let record = match self.execution_record(block)? {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+        let mut batch = DBTransaction::new();
+        batch.delete(COL_EXTRA, &record_key(BLOCK_RECORD_PREFIX, block));
+        for hash in &record.transactions {
+            if let Some((b, _)) = self.transaction_location(hash)? {
+                if b == *block {
+                    batch.delete(COL_EXTRA, &record_key(TRANSACTION_RECORD_PREFIX, hash));
+                }
+            }
+        }
+        let state = self.state.lock().unwrap();
+        state.db().journal_db().backing().write(batch)?;
+        Ok(())
+    }
+
+    /// How the given transaction block was executed, or None if it was not.
+    pub fn execution_record(&self, block: &H256) -> Result<Option<ExecutionRecord>> {
+        let state = self.state.lock().unwrap();
+        let value = state
+            .db()
+            .journal_db()
+            .backing()
+            .get(COL_EXTRA, &record_key(BLOCK_RECORD_PREFIX, block))?;
+        Ok(value.map(|v| deserialize(&v).unwrap()))
+    }
+
+    /// The transaction block the given transaction was executed in, and its position among the
+    /// transactions executed, or None if it was not executed.
+    pub fn transaction_location(&self, hash: &H256) -> Result<Option<(H256, usize)>> {
+        let state = self.state.lock().unwrap();
+        let value = state
+            .db()
+            .journal_db()
+            .backing()
+            .get(COL_EXTRA, &record_key(TRANSACTION_RECORD_PREFIX, hash))?;
+        Ok(value.map(|v| {
+            let (block, index): (H256, u32) = deserialize(&v).unwrap();
+            (block, index as usize)
+        }))
+    }
+
+    /// Replay the last of `txs`, the transactions of a block up to the traced one as recorded,
+    /// and trace it. The transactions before it are applied to the state before the block
+    /// without tracing. The state of the database is left untouched.
+    pub fn replay_transaction(
+        &self,
+        record: &ExecutionRecord,
+        info: &vm::EnvInfo,
+        txs: &[Transaction],
+        options: TraceOptions,
+    ) -> Result<TraceResults> {
+        let (t, before) = match txs.split_last() {
+            Some(split) => split,
+            None => return Ok(TraceResults::default()),
+        };
+        let mut state = self.state_at(&record.root)?;
+        if !record.reward.is_zero() {
+            state.add_balance(&record.author, &record.reward, account_state::CleanupMode::NoEmpty)?;
+        }
+        for t in before {
+            state.apply(info, &self.machine, t, false)?;
+        }
+        self.trace(state, info, t, options)
+    }
+
+    /// Trace a transaction on top of the current state, without changing it. The sender does
+    /// not need to sign the transaction or to afford it: it is credited what it lacks first.
+    pub fn trace_call(
+        &self,
+        t: &Transaction,
+        info: &vm::EnvInfo,
+        options: TraceOptions,
+    ) -> Result<TraceResults> {
+        let mut state = self.state.lock().unwrap().clone();
+        credit_shortfall(&mut state, t)?;
+        self.trace(state, info, t, options)
+    }
+
+    fn trace(
+        &self,
+        mut state: State<StateDB>,
+        info: &vm::EnvInfo,
+        t: &Transaction,
+        options: TraceOptions,
+    ) -> Result<TraceResults> {
+        let original = if options.state_diff {
+            Some(state.clone())
+        } else {
+            None
+        };
+        let outcome = state.apply(info, &self.machine, t, true)?;
+        let state_diff = match original {
+            Some(original) => Some(
+                state
+                    .diff_from(original)?
+                    .raw
+                    .into_iter()
+                    .map(|(addr, diff)| (addr, diff.into()))
+                    .collect(),
+            ),
+            None => None,
+        };
+        Ok(TraceResults {
+            output: outcome.output,
+            trace: if options.trace {
+                outcome.trace.into_iter().map(CallTrace).collect()
+            } else {
+                vec![]
+            },
+            vm_trace: if options.vm_trace {
+                outcome.vm_trace.map(Into::into)
+            } else {
+                None
+            },
+            state_diff,
+        })
+    }
+}
+
+fn record_key(prefix: u8, hash: &H256) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(hash.as_bytes());
+    key
+}
+
+fn serialize_bytes<S: Serializer>(bytes: &[u8], s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
+/// Result of tracing a transaction, in the format of `trace_replayTransaction` of Parity.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceResults {
+    /// Data returned by the call, or the code of the contract created.
+    #[serde(serialize_with = "serialize_bytes")]
+    pub output: Bytes,
+    pub trace: Vec<CallTrace>,
+    pub vm_trace: Option<VmTrace>,
+    pub state_diff: Option<BTreeMap<Address, AccountDiff>>,
+}
+
+/// A call or contract creation made by a transaction.
+#[derive(Debug)]
+pub struct CallTrace(pub trace::FlatTrace);
+
+impl Serialize for CallTrace {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        let t = &self.0;
+        let mut st = s.serialize_struct("CallTrace", 5)?;
+        match t.action {
+            TraceAction::Call(ref call) => {
+                let call_type = match call.call_type {
+                    vm::CallType::None => "none",
+                    vm::CallType::Call => "call",
+                    vm::CallType::CallCode => "callcode",
+                    vm::CallType::DelegateCall => "delegatecall",
+                    vm::CallType::StaticCall => "staticcall",
+                };
+                st.serialize_field("type", "call")?;
+                st.serialize_field(
+                    "action",
+                    &serde_json::json!({
+                        "callType": call_type,
+                        "from": call.from,
+                        "to": call.to,
+                        "value": call.value,
+                        "gas": call.gas,
+                        "input": format!("0x{}", hex::encode(&call.input)),
+                    }),
+                )?;
+            }
+            TraceAction::Create(ref create) => {
+                st.serialize_field("type", "create")?;
+                st.serialize_field(
+                    "action",
+                    &serde_json::json!({
+                        "from": create.from,
+                        "value": create.value,
+                        "gas": create.gas,
+                        "init": format!("0x{}", hex::encode(&create.init)),
+                    }),
+                )?;
+            }
+            TraceAction::Suicide(ref suicide) => {
+                st.serialize_field("type", "suicide")?;
+                st.serialize_field(
+                    "action",
+                    &serde_json::json!({
+                        "address": suicide.address,
+                        "refundAddress": suicide.refund_address,
+                        "balance": suicide.balance,
+                    }),
+                )?;
+            }
+            // rewards are credited outside of the EVM, so transactions never have these
+            TraceAction::Reward(ref reward) => {
+                st.serialize_field("type", "reward")?;
+                st.serialize_field(
+                    "action",
+                    &serde_json::json!({
+                        "author": reward.author,
+                        "value": reward.value,
+                        "rewardType": "external",
+                    }),
+                )?;
+            }
+        }
+        match t.result {
+            Res::Call(ref res) => st.serialize_field(
+                "result",
+                &serde_json::json!({
+                    "gasUsed": res.gas_used,
+                    "output": format!("0x{}", hex::encode(&res.output)),
+                }),
+            )?,
+            Res::Create(ref res) => st.serialize_field(
+                "result",
+                &serde_json::json!({
+                    "gasUsed": res.gas_used,
+                    "code": format!("0x{}", hex::encode(&res.code)),
+                    "address": res.address,
+                }),
+            )?,
+            Res::FailedCall(ref e) | Res::FailedCreate(ref e) => {
+                st.serialize_field("error", &e.to_string())?
+            }
+            Res::None => st.serialize_field("result", &None as &Option<u8>)?,
+        }
+        st.serialize_field("subtraces", &t.subtraces)?;
+        st.serialize_field("traceAddress", &t.trace_address)?;
+        st.end()
+    }
+}
+
+/// The instructions executed by a call or contract creation.
+#[derive(Serialize, Debug)]
+pub struct VmTrace {
+    #[serde(serialize_with = "serialize_bytes")]
+    pub code: Bytes,
+    pub ops: Vec<VmOperation>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VmOperation {
+    /// The program counter.
+    pub pc: usize,
+    /// Gas cost of the instruction.
+    pub cost: u64,
+    /// Effects of the instruction, or None if it failed.
+    pub ex: Option<VmExecutedOperation>,
+    /// The instructions of the call or contract creation the instruction made.
+    pub sub: Option<VmTrace>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VmExecutedOperation {
+    /// Gas left after the instruction.
+    pub used: u64,
+    /// Items pushed on the stack.
+    pub push: Vec<U256>,
+    pub mem: Option<MemoryDiff>,
+    pub store: Option<StorageDiff>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MemoryDiff {
+    pub off: usize,
+    #[serde(serialize_with = "serialize_bytes")]
+    pub data: Bytes,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StorageDiff {
+    pub key: U256,
+    pub val: U256,
+}
+
+impl From<trace::VMTrace> for VmTrace {
+    fn from(t: trace::VMTrace) -> Self {
+        // sub traces are in the order of the instructions making them
+        let mut subs = t.subs.into_iter().peekable();
+        let ops = t
+            .operations
+            .into_iter()
+            .enumerate()
+            .map(|(step, op)| {
+                let sub = match subs.peek() {
+                    Some(sub) if sub.parent_step == step => subs.next().map(Into::into),
+                    _ => None,
+                };
+                VmOperation {
+                    pc: op.pc,
+                    cost: op.gas_cost.low_u64(),
+                    ex: op.executed.map(|ex| VmExecutedOperation {
+                        used: ex.gas_used.low_u64(),
+                        push: ex.stack_push,
+                        mem: ex.mem_diff.map(|m| MemoryDiff {
+                            off: m.offset,
+                            data: m.data,
+                        }),
+                        store: ex.store_diff.map(|s| StorageDiff {
+                            key: s.location,
+                            val: s.value,
+                        }),
+                    }),
+                    sub,
+                }
+            })
+            .collect();
+        Self { code: t.code, ops }
+    }
+}
+
+/// Change of a value: `"="` if unchanged, `{"+": new}` if created, `{"-": old}` if deleted, and
+/// `{"*": {"from": old, "to": new}}` otherwise.
+#[derive(Serialize, Debug)]
+pub enum Diff<T: Serialize> {
+    #[serde(rename = "=")]
+    Same,
+    #[serde(rename = "+")]
+    Born(T),
+    #[serde(rename = "-")]
+    Died(T),
+    #[serde(rename = "*")]
+    Changed { from: T, to: T },
+}
+
+impl<T: Serialize> Diff<T> {
+    fn from_diff<U, F: Fn(U) -> T>(d: account_diff::Diff<U>, f: F) -> Self {
+        match d {
+            account_diff::Diff::Same => Diff::Same,
+            account_diff::Diff::Born(v) => Diff::Born(f(v)),
+            account_diff::Diff::Died(v) => Diff::Died(f(v)),
+            account_diff::Diff::Changed(from, to) => Diff::Changed {
+                from: f(from),
+                to: f(to),
+            },
+        }
+    }
+}
+
+/// Changes to an account made by a transaction.
+#[derive(Serialize, Debug)]
+pub struct AccountDiff {
+    pub balance: Diff<U256>,
+    pub nonce: Diff<U256>,
+    /// The code, hex-encoded.
+    pub code: Diff<String>,
+    pub storage: BTreeMap<H256, Diff<H256>>,
+}
+
+impl From<account_diff::AccountDiff> for AccountDiff {
+    fn from(d: account_diff::AccountDiff) -> Self {
+        Self {
+            balance: Diff::from_diff(d.balance, |v| v),
+            nonce: Diff::from_diff(d.nonce, |v| v),
+            code: Diff::from_diff(d.code, |c| format!("0x{}", hex::encode(c))),
+            storage: d
+                .storage
+                .into_iter()
+                .map(|(k, v)| (k, Diff::from_diff(v, |v| v)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_temp_state_database() -> StateDatabase {
+        let state = ethcore::test_helpers::get_temp_state();
+        let state = Mutex::new(state);
+        let machine = ChainSpec::prism_test().machine().unwrap();
+        StateDatabase { state, machine }
+    }
+
+    #[test]
+    fn replay_transaction() {
+        let statedb = get_temp_state_database();
+        let keypair: KeyPair = Random.generate().unwrap();
+        let addr = keypair.address();
+        statedb.add_balance(&addr, &1_000_000.into()).unwrap();
+        statedb.commit().unwrap();
+        let root = statedb.root();
+
+        /* the constructor stores the caller at 0
+         * CALLER PUSH1 0 SSTORE
+         */
+        let txs: Vec<Transaction> = (0..2)
+            .map(|nonce: u64| {
+                RawTransaction {
+                    nonce: nonce.into(),
+                    gas_price: 1.into(),
+                    gas: 100_000.into(),
+                    action: Action::Create,
+                    value: 0.into(),
+                    data: hex::decode("33600055").unwrap(),
+                }
+                .sign(&keypair.secret(), None)
+            })
+            .collect();
+        let mut info = StateDatabase::default_env_info();
+        info.author = Address::from_low_u64_be(0xb);
+        let record = ExecutionRecord {
+            root,
+            reward: 10.into(),
+            number: info.number,
+            timestamp: info.timestamp,
+            author: info.author,
+            gas_limit: info.gas_limit,
+            transactions: txs.iter().map(|t| t.hash()).collect(),
+        };
+        statedb.add_balance(&info.author, &record.reward).unwrap();
+        for t in &txs {
+            statedb.apply_with_env_info(t, &info).unwrap();
+        }
+        statedb.commit().unwrap();
+        let block = H256::from_low_u64_be(0x1234);
+        statedb.record_execution(&block, &record).unwrap();
+        assert_eq!(statedb.execution_record(&block).unwrap(), Some(record.clone()));
+        assert_eq!(statedb.transaction_location(&txs[1].hash()).unwrap(), Some((block, 1)));
+        assert_eq!(statedb.transaction_location(&H256::zero()).unwrap(), None);
+
+        let options = TraceOptions {
+            trace: true,
+            vm_trace: true,
+            state_diff: true,
+        };
+        let results = statedb.replay_transaction(&record, &info, &txs, options).unwrap();
+        let contract = match results.trace[0].0.result {
+            Res::Create(ref res) => res.address,
+            _ => unreachable!(),
+        };
+        let state_diff = results.state_diff.as_ref().unwrap();
+        match state_diff[&contract].storage[&H256::zero()] {
+            Diff::Born(v) => assert_eq!(v, H256::from(addr)),
+            ref d => panic!("unexpected diff {:?}", d),
+        }
+        match state_diff[&addr].nonce {
+            Diff::Changed { from, to } => assert_eq!((from, to), (1.into(), 2.into())),
+            ref d => panic!("unexpected diff {:?}", d),
+        }
+        // CALLER PUSH1 SSTORE STOP
+        assert_eq!(results.vm_trace.as_ref().unwrap().ops.len(), 4);
+
+        let json = serde_json::to_value(&results).unwrap();
+        assert_eq!(json["trace"][0]["type"], "create");
+        assert_eq!(json["trace"][0]["action"]["init"], "0x33600055");
+        assert_eq!(json["vmTrace"]["ops"][2]["ex"]["store"]["key"], "0x0");
+        assert!(json["stateDiff"][format!("{:?}", addr)]["balance"]["*"]["from"].is_string());
+        // replaying leaves the state alone
+        assert_eq!(statedb.nonce(&addr).unwrap(), 2.into());
+
+        // the location of a transaction executed again in a later block is kept
+        let later = H256::from_low_u64_be(0x5678);
+        let later_record = ExecutionRecord {
+            transactions: vec![txs[1].hash()],
+            ..record
+        };
+        statedb.record_execution(&later, &later_record).unwrap();
+        statedb.prune_execution(&block).unwrap();
+        assert_eq!(statedb.execution_record(&block).unwrap(), None);
+        assert_eq!(statedb.transaction_location(&txs[0].hash()).unwrap(), None);
+        assert_eq!(statedb.transaction_location(&txs[1].hash()).unwrap(), Some((later, 0)));
+    }
+
+    #[test]
+    fn trace_call() {
+        let statedb = get_temp_state_database();
+        let sender = Address::from_low_u64_be(0xa);
+        let receiver = Address::from_low_u64_be(0xc);
+        // the sender has nothing and does not sign
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 1.into(),
+            gas: 100_000.into(),
+            action: Action::Call(receiver),
+            value: 100.into(),
+            data: vec![],
+        }
+        .fake_sign(sender);
+        let options = TraceOptions {
+            trace: true,
+            vm_trace: false,
+            state_diff: true,
+        };
+        let results = statedb
+            .trace_call(&t, &StateDatabase::default_env_info(), options)
+            .unwrap();
+        assert!(results.vm_trace.is_none());
+        let json = serde_json::to_value(&results).unwrap();
+        assert_eq!(json["trace"][0]["type"], "call");
+        assert_eq!(json["trace"][0]["action"]["callType"], "call");
+        assert_eq!(json["trace"][0]["result"]["gasUsed"], "0x0");
+        assert_eq!(json["stateDiff"][format!("{:?}", receiver)]["balance"]["+"], "0x64");
+        assert_eq!(statedb.balance(&sender).unwrap(), 0.into());
+        assert_eq!(statedb.balance(&receiver).unwrap(), 0.into());
+    }
+}
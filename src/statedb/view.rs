@@ -0,0 +1,176 @@
+use super::*;
+use account_state::state::State;
+use ethcore_db::COL_EXTRA;
+use state_db::StateDB;
+
+/// Prefix of the keys of the state roots after each executed ledger level, next to the execution
+/// records of transaction blocks.
+const LEVEL_ROOT_PREFIX: u8 = b'l';
+
+/// Read-only view of the state at a committed root.
+pub struct StateView<'a> {
+    state: State<StateDB>,
+    machine: &'a machine::Machine,
+}
+
+impl StateDatabase {
+    /// Record the state root after the given ledger level was executed.
+    pub fn record_level_root(&self, level: u64, root: &H256) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut batch = DBTransaction::new();
+        batch.put(COL_EXTRA, &level_key(level), root.as_bytes());
+        state.db().journal_db().backing().write(batch)?;
+        Ok(())
+    }
+
+    /// The state root after the given ledger level was executed, or None if it was not.
+    pub fn level_root(&self, level: u64) -> Result<Option<H256>> {
+        let state = self.state.lock().unwrap();
+        let value = state.db().journal_db().backing().get(COL_EXTRA, &level_key(level))?;
+        Ok(value.map(|v| H256::from_slice(&v)))
+    }
+
+    /// Open a view of the state at the given root.
+    pub fn view_at(&self, root: &H256) -> Result<StateView> {
+        Ok(StateView {
+            state: self.state_at(root)?,
+            machine: &self.machine,
+        })
+    }
+
+    /// Open a view of the state after the given ledger level was executed, or None if it was not.
+    pub fn view_at_level(&self, level: u64) -> Result<Option<StateView>> {
+        match self.level_root(level)? {
+            Some(root) => Ok(Some(self.view_at(&root)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> StateView<'a> {
+    pub fn root(&self) -> H256 {
+        *self.state.root()
+    }
+
+    pub fn balance(&self, a: &Address) -> Result<U256> {
+        Ok(self.state.balance(a)?)
+    }
+
+    pub fn nonce(&self, a: &Address) -> Result<U256> {
+        Ok(self.state.nonce(a)?)
+    }
+
+    pub fn code(&self, a: &Address) -> Result<Option<Arc<Bytes>>> {
+        Ok(self.state.code(a)?)
+    }
+
+    pub fn storage_at(&self, a: &Address, key: &H256) -> Result<H256> {
+        Ok(self.state.storage_at(a, key)?)
+    }
+
+    /// Execute a transaction on a copy of the state, which is then dropped. The sender does not
+    /// need to sign the transaction or to afford it.
+    pub fn call(
+        &self,
+        t: &Transaction,
+        info: &vm::EnvInfo,
+    ) -> Result<executive_state::ApplyOutcome<trace::FlatTrace, trace::VMTrace>> {
+        let mut state = self.state.clone();
+        credit_shortfall(&mut state, t)?;
+        Ok(state.apply(info, self.machine, t, false)?)
+    }
+}
+
+/// Credit the sender of a transaction what it lacks to pay for it.
+pub fn credit_shortfall(state: &mut State<StateDB>, t: &Transaction) -> Result<()> {
+    let cost = t.gas.saturating_mul(t.gas_price).saturating_add(t.value);
+    let balance = state.balance(&t.sender())?;
+    if balance < cost {
+        state.add_balance(&t.sender(), &(cost - balance), account_state::CleanupMode::NoEmpty)?;
+    }
+    Ok(())
+}
+
+pub(super) fn level_key(level: u64) -> Vec<u8> {
+    let mut key = vec![LEVEL_ROOT_PREFIX];
+    key.extend_from_slice(&level.to_be_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_temp_state_database() -> StateDatabase {
+        let state = ethcore::test_helpers::get_temp_state();
+        let state = Mutex::new(state);
+        let machine = ChainSpec::prism_test().machine().unwrap();
+        StateDatabase { state, machine }
+    }
+
+    #[test]
+    fn view_at_level() {
+        let statedb = get_temp_state_database();
+        let addr = Address::from_low_u64_be(0xa);
+        for level in 1..=3 {
+            statedb.add_balance(&addr, &100.into()).unwrap();
+            statedb.commit().unwrap();
+            statedb.record_level_root(level, &statedb.root()).unwrap();
+        }
+        assert!(statedb.view_at_level(4).unwrap().is_none());
+        for level in 1..=3 {
+            let view = statedb.view_at_level(level).unwrap().unwrap();
+            assert_eq!(view.root(), statedb.level_root(level).unwrap().unwrap());
+            assert_eq!(view.balance(&addr).unwrap(), U256::from(100 * level));
+        }
+        assert_eq!(statedb.balance(&addr).unwrap(), 300.into());
+    }
+
+    #[test]
+    fn call() {
+        let statedb = get_temp_state_database();
+        /* the contract returns the value stored at 0
+         * PUSH1 0 SLOAD PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+         */
+        let code = "60005460005260206000f3";
+        let keypair: KeyPair = Random.generate().unwrap();
+        /* the constructor stores 7 at 0 and returns the code after it
+         * PUSH1 7 PUSH1 0 SSTORE
+         * PUSH1 11 DUP1 PUSH1 16 PUSH1 0 CODECOPY PUSH1 0 RETURN
+         */
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 0.into(),
+            gas: 200_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: hex::decode(format!("6007600055600b8060106000396000f3{}", code)).unwrap(),
+        }
+        .sign(&keypair.secret(), None);
+        statedb.apply(&t).unwrap();
+        let (contract, _) = machine::executive::contract_address(
+            vm::CreateContractAddress::FromSenderAndNonce,
+            &keypair.address(),
+            &0.into(),
+            &[],
+        );
+        statedb.commit().unwrap();
+        statedb.record_level_root(1, &statedb.root()).unwrap();
+
+        let view = statedb.view_at_level(1).unwrap().unwrap();
+        assert_eq!(view.storage_at(&contract, &H256::zero()).unwrap(), H256::from_low_u64_be(7));
+        // the caller has nothing
+        let t = RawTransaction {
+            nonce: 0.into(),
+            gas_price: 1.into(),
+            gas: 100_000.into(),
+            action: Action::Call(contract),
+            value: 0.into(),
+            data: vec![],
+        }
+        .fake_sign(Address::from_low_u64_be(0xa));
+        let outcome = view.call(&t, &StateDatabase::default_env_info()).unwrap();
+        assert_eq!(outcome.output, H256::from_low_u64_be(7).as_bytes().to_vec());
+        assert_eq!(view.balance(&Address::from_low_u64_be(0xa)).unwrap(), 0.into());
+    }
+}
@@ -1,12 +1,17 @@
 mod proposer_block;
 mod transaction;
 mod voter_block;
+
+pub use transaction::check_signature_batch;
+
 use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 use crate::crypto::merkle::verify;
+use crate::statedb::StateDatabase;
+use crate::transaction::Transaction;
 extern crate bigint;
 
 /// The result of block validation.
@@ -32,6 +37,10 @@ pub enum BlockResult {
     ZeroValue,
     InsufficientInput,
     WrongSignature,
+    /// The transaction nonce is already used in the state.
+    WrongNonce,
+    /// The transactions of a transaction block may use more gas than the block gas limit.
+    GasLimitExceeded,
 }
 
 impl std::fmt::Display for BlockResult {
@@ -61,6 +70,8 @@ impl std::fmt::Display for BlockResult {
             }
             BlockResult::InsufficientInput => write!(f, "insufficient input"),
             BlockResult::WrongSignature => write!(f, "signature mismatch"),
+            BlockResult::WrongNonce => write!(f, "transaction nonce already used"),
+            BlockResult::GasLimitExceeded => write!(f, "transaction block exceeds the gas limit"),
         }
     }
 }
@@ -146,6 +157,33 @@ pub fn check_data_availability(
     }
 }
 
+/// Drop from the missing references of a block the transaction blocks it need not wait for
+/// once the state was restored from the snapshot at the given proposer level: those referred by
+/// proposer blocks up to the level, which peers that pruned them can no longer serve. They are
+/// still asked for, since a level above may confirm such a proposer block.
+pub fn waive_snapshot_references(
+    block: &Block,
+    blockchain: &BlockChain,
+    snapshot_level: u64,
+    missing: Vec<H256>,
+) -> Vec<H256> {
+    let content = match &block.content {
+        Content::Proposer(content) => content,
+        _ => return missing,
+    };
+    // the level of a block is only known with its parent
+    let parent = block.header.parent;
+    if !check_proposer_block_exists(parent, blockchain)
+        || blockchain.proposer_level(&parent).unwrap() >= snapshot_level
+    {
+        return missing;
+    }
+    missing
+        .into_iter()
+        .filter(|hash| !content.transaction_refs.contains(hash))
+        .collect()
+}
+
 /// Check block content semantic
 pub fn check_content_semantic(
     block: &Block,
@@ -173,6 +211,11 @@ pub fn check_content_semantic(
             BlockResult::Pass
         }
         Content::Transaction(content) => {
+            if let Some(gas_limit) = blockchain.config().tx_block_gas_limit {
+                if !transaction::check_gas_limit(&content.transactions, gas_limit) {
+                    return BlockResult::GasLimitExceeded;
+                }
+            }
             if !transaction::check_signature_batch(&content.transactions) {
                 return BlockResult::WrongSignature;
             }
@@ -181,6 +224,20 @@ pub fn check_content_semantic(
     }
 }
 
+/// Check whether a pending transaction could still be applied on the current state.
+pub fn check_pending_transaction(tx: &Transaction, statedb: &StateDatabase) -> BlockResult {
+    if !transaction::check_signature_batch(std::slice::from_ref(tx)) {
+        return BlockResult::WrongSignature;
+    }
+    if !transaction::check_nonce(tx, statedb) {
+        return BlockResult::WrongNonce;
+    }
+    if !transaction::check_balance(tx, statedb) {
+        return BlockResult::InsufficientInput;
+    }
+    BlockResult::Pass
+}
+
 /// Check whether a proposer block exists in the block database and the blockchain.
 fn check_proposer_block_exists(hash: H256, blockchain: &BlockChain) -> bool {
     match blockchain.contains_proposer(&hash) {
@@ -1,25 +1,225 @@
+use crate::crypto::hash::{Address, EthereumH256};
+use crate::statedb::StateDatabase;
 use crate::transaction::Transaction;
+use ethereum_types::U256;
+use parity_crypto::publickey::{public_to_address, Public};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
 
+/// Number of threads verifying the signatures of a batch.
+const SIGNATURE_THREADS: usize = 4;
+/// Minimum number of signatures verified by one thread, below which spawning is not worth it.
+const SIGNATURE_CHUNK: usize = 16;
+/// Number of transactions whose verified sender is remembered.
+const SENDER_CACHE_SIZE: usize = 1 << 20;
+
+lazy_static! {
+    /// Senders recovered from the signatures verified so far, so that a transaction checked when
+    /// it arrives in the mempool is not verified again in a block, or before execution.
+    static ref VERIFIED_SENDERS: Mutex<SenderCache> = Mutex::new(SenderCache::new(SENDER_CACHE_SIZE));
+}
+
+/// Bounded map from a signed transaction to the sender and public key its signature recovers
+/// to. The oldest entries are evicted first.
+struct SenderCache {
+    senders: HashMap<EthereumH256, (Address, Public)>,
+    order: VecDeque<EthereumH256>,
+    capacity: usize,
+}
+
+impl SenderCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            senders: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Whether the transaction claims the sender its signature was verified to recover to.
+    fn verified(&self, key: &EthereumH256, transaction: &Transaction) -> bool {
+        match self.senders.get(key) {
+            Some((sender, public)) => {
+                *sender == transaction.sender() && Some(*public) == transaction.public_key()
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, key: EthereumH256, sender: Address, public: Public) {
+        if self.senders.insert(key, (sender, public)).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.senders.remove(&oldest);
+        }
+    }
+}
+
+/// Key of a transaction in the sender cache. The hash carried by a transaction is deserialized
+/// along with it, so the key is computed from the signed content itself.
+fn signature_key(transaction: &Transaction) -> EthereumH256 {
+    keccak_hash::keccak(rlp::encode(transaction))
+}
+
+/// Check that the sender and public key of the transaction are the ones its signature recovers
+/// to.
+fn check_signature(transaction: &Transaction) -> bool {
+    if transaction.is_unsigned() {
+        return false;
+    }
+    match transaction.recover_public() {
+        Ok(public) => {
+            public_to_address(&public) == transaction.sender()
+                && Some(public) == transaction.public_key()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Check the signatures of the transactions. Signatures verified before are looked up in the
+/// sender cache, and the others are verified on several threads for large batches.
 pub fn check_signature_batch(transactions: &[Transaction]) -> bool {
-    let mut check = true;
-    for (_idx, transaction) in transactions.iter().enumerate() {
-        let t = transaction.clone();
-        let (unverified, addr, public) = t.deconstruct();
-        let signedtx = unverified.verify_unordered();
-        match signedtx {
-            Ok(tx) => {
-                if addr != tx.sender() || public != tx.public_key() {
-                    //debug!("Tx is not signed properly!");
-                    check = false;
-                    break;
-                }
-            },
-            Err(e) => {
-                //debug!("Tx is not signed properly!");
-                check = false;
-                break;
-            },
+    let keys: Vec<EthereumH256> = transactions.iter().map(signature_key).collect();
+    let unverified: Vec<usize> = {
+        let cache = VERIFIED_SENDERS.lock().unwrap();
+        (0..transactions.len())
+            .filter(|&i| !cache.verified(&keys[i], &transactions[i]))
+            .collect()
+    };
+    if unverified.is_empty() {
+        return true;
+    }
+
+    let chunk_size = std::cmp::max(
+        (unverified.len() + SIGNATURE_THREADS - 1) / SIGNATURE_THREADS,
+        SIGNATURE_CHUNK,
+    );
+    let check = if unverified.len() <= chunk_size {
+        unverified.iter().all(|&i| check_signature(&transactions[i]))
+    } else {
+        crossbeam::scope(|s| {
+            let handles: Vec<_> = unverified
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move |_| chunk.iter().all(|&i| check_signature(&transactions[i])))
+                })
+                .collect();
+            // join every thread before returning, even after a failure
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .fold(true, |all, valid| all && valid)
+        })
+        .unwrap()
+    };
+    if check {
+        let mut cache = VERIFIED_SENDERS.lock().unwrap();
+        for i in unverified {
+            let t = &transactions[i];
+            cache.insert(keys[i], t.sender(), t.public_key().unwrap());
         }
     }
     check
 }
+
+/// Check that the gas the transactions may use adds up to at most `gas_limit`.
+pub fn check_gas_limit(transactions: &[Transaction], gas_limit: u64) -> bool {
+    let mut total = U256::zero();
+    for t in transactions {
+        total = match total.checked_add(t.gas) {
+            Some(total) => total,
+            None => return false,
+        };
+    }
+    total <= gas_limit.into()
+}
+
+/// Check that the nonce of the transaction is not yet used in the state.
+pub fn check_nonce(transaction: &Transaction, statedb: &StateDatabase) -> bool {
+    match statedb.nonce(&transaction.sender()) {
+        Ok(nonce) => transaction.nonce >= nonce,
+        Err(_) => false,
+    }
+}
+
+/// Check that the sender can afford the value and the maximum gas cost of the transaction.
+pub fn check_balance(transaction: &Transaction, statedb: &StateDatabase) -> bool {
+    let cost = match transaction
+        .gas
+        .checked_mul(transaction.gas_price)
+        .and_then(|c| c.checked_add(transaction.value))
+    {
+        Some(c) => c,
+        None => return false,
+    };
+    match statedb.balance(&transaction.sender()) {
+        Ok(balance) => balance >= cost,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Action, RawTransaction};
+    use parity_crypto::publickey::{Generator, Random};
+
+    fn payment(nonce: u64) -> Transaction {
+        let keypair = Random.generate().unwrap();
+        RawTransaction {
+            nonce: nonce.into(),
+            gas_price: 1.into(),
+            gas: 21_000.into(),
+            action: Action::Call(Address::from_low_u64_be(0xa)),
+            value: 1.into(),
+            data: vec![],
+        }
+        .sign(&keypair.secret(), None)
+    }
+
+    #[test]
+    fn signature_batch() {
+        let transactions: Vec<Transaction> = (0..100).map(payment).collect();
+        assert!(check_signature_batch(&transactions));
+        // verified senders are cached
+        let key = signature_key(&transactions[42]);
+        assert!(VERIFIED_SENDERS.lock().unwrap().verified(&key, &transactions[42]));
+        assert!(check_signature_batch(&transactions[10..20]));
+
+        // a transaction claiming another sender, with the signature of a verified one
+        let other = payment(0);
+        let mut forged = serde_json::to_value(&transactions[42]).unwrap();
+        forged["sender"] = serde_json::to_value(other.sender()).unwrap();
+        forged["public"] = serde_json::to_value(other.public_key()).unwrap();
+        let forged: Transaction = serde_json::from_value(forged).unwrap();
+        let mut batch = transactions.clone();
+        batch.push(forged);
+        assert!(!check_signature_batch(&batch));
+    }
+
+    #[test]
+    fn gas_limit() {
+        // each payment may use 21000 gas
+        let transactions: Vec<Transaction> = (0..3).map(payment).collect();
+        assert!(check_gas_limit(&transactions, 63_000));
+        assert!(!check_gas_limit(&transactions, 62_999));
+        assert!(check_gas_limit(&[], 0));
+    }
+
+    #[test]
+    fn sender_cache_eviction() {
+        let mut cache = SenderCache::new(2);
+        let transactions: Vec<Transaction> = (0..3).map(payment).collect();
+        for t in &transactions {
+            cache.insert(signature_key(t), t.sender(), t.public_key().unwrap());
+        }
+        assert!(!cache.verified(&signature_key(&transactions[0]), &transactions[0]));
+        assert!(cache.verified(&signature_key(&transactions[1]), &transactions[1]));
+        assert!(cache.verified(&signature_key(&transactions[2]), &transactions[2]));
+        // a wrong sender is not accepted for a cached signature
+        assert!(!cache.verified(&signature_key(&transactions[1]), &transactions[2]));
+    }
+}
@@ -17,6 +17,8 @@ use parity_crypto::publickey::{KeyPair, Random, Secret, Public, Generator};
 use parity_bytes::Bytes;
 
 pub const KEYPAIR_CF: &str = "KEYPAIR"; // &Address to &Secret
+/// Gas limit of the transactions we sign, unless set otherwise.
+pub const DEFAULT_TX_GAS: u64 = 10_000_000;
 
 pub type Result<T> = std::result::Result<T, WalletError>;
 
@@ -29,6 +31,10 @@ pub struct Wallet {
     /// Keep key pair (in pkcs8 bytes) in memory for performance, it's duplicated in database as well.
     keypairs: Mutex<HashMap<Address, KeyPair>>,
     counter: AtomicUsize,
+    /// Gas price and gas limit of the transactions we sign.
+    gas: Mutex<(U256, U256)>,
+    /// Chain ID the transactions are signed with, as in EIP-155, or None for no replay protection.
+    chain_id: Mutex<Option<u64>>,
 }
 
 #[derive(Debug)]
@@ -76,6 +82,8 @@ impl Wallet {
             nonce_balances: Mutex::new(HashMap::new()),
             keypairs: Mutex::new(HashMap::new()),
             counter: AtomicUsize::new(0),
+            gas: Mutex::new((0.into(), DEFAULT_TX_GAS.into())),
+            chain_id: Mutex::new(None),
         })
     }
 
@@ -137,37 +145,26 @@ impl Wallet {
         Ok(sum)
     }
 
-    /// Create a payment transaction
-    pub fn create_transaction_payment(&self, sender_addr: &Address, receiver_addr: &Address, value: U256) -> Result<Transaction> {
-        let keypairs = self.keypairs.lock().unwrap();
-        let keypair = match keypairs.get(sender_addr) {
-            Some(kp) => kp.clone(),
-            None => return Err(WalletError::MissingKeyPair),
-        };
-        drop(keypairs);
+    /// Set the gas price in Wei and the gas limit of the transactions we sign from now on.
+    pub fn set_gas(&self, gas_price: U256, gas: U256) {
+        *self.gas.lock().unwrap() = (gas_price, gas);
+    }
 
-        let mut nonce_balances = self.nonce_balances.lock().unwrap();
-        let nonce_balance = nonce_balances.entry(keypair.address()).or_insert((0.into(), 0.into()));
-        let nonce = nonce_balance.0;
-        nonce_balance.0 = nonce_balance.0 + 1;
-        drop(nonce_balances);
+    /// Get the gas price and the gas limit of the transactions we sign.
+    pub fn gas(&self) -> (U256, U256) {
+        *self.gas.lock().unwrap()
+    }
 
-        let receiver_addr = receiver_addr.clone();
-        let tx: Transaction = RawTransaction {
-            nonce: nonce.into(),
-            gas_price: 0.into(),
-            gas: 10_000_000.into(),
-            action: Action::Call(receiver_addr),
-            value: value,
-            data: vec![],
-        }.sign(keypair.secret(), None);
-        Ok(tx)
+    /// Set the chain ID of the network, which the transactions we sign from now on commit to.
+    pub fn set_chain_id(&self, chain_id: Option<u64>) {
+        *self.chain_id.lock().unwrap() = chain_id;
     }
 
-    /// Create a payment transaction from and to the same addr
-    pub fn create_transaction_self_payment(&self, addr: &Address, value: U256) -> Result<Transaction> {
+    /// Create a transaction signed by the given address, with the given gas price and gas limit.
+    /// The fee, the gas used times the gas price, is taken from the sender when executed.
+    pub fn create_transaction(&self, sender_addr: &Address, action: Action, value: U256, data: Bytes, gas_price: U256, gas: U256) -> Result<Transaction> {
         let keypairs = self.keypairs.lock().unwrap();
-        let keypair = match keypairs.get(addr) {
+        let keypair = match keypairs.get(sender_addr) {
             Some(kp) => kp.clone(),
             None => return Err(WalletError::MissingKeyPair),
         };
@@ -179,68 +176,44 @@ impl Wallet {
         nonce_balance.0 = nonce_balance.0 + 1;
         drop(nonce_balances);
 
-        let receiver_addr = addr.clone();
+        let chain_id = *self.chain_id.lock().unwrap();
         let tx: Transaction = RawTransaction {
             nonce: nonce.into(),
-            gas_price: 0.into(),
-            gas: 10_000_000.into(),
-            action: Action::Call(receiver_addr),
-            value: value,
-            data: vec![],
-        }.sign(keypair.secret(), None);
+            gas_price,
+            gas,
+            action,
+            value,
+            data,
+        }.sign(keypair.secret(), chain_id);
         Ok(tx)
     }
 
-    /// Create a call transaction with our first keypair and 0 value
-    pub fn create_transaction_call(&self, addr: &Address, receiver_addr: &Address, data: Bytes) -> Result<Transaction> {
-        let keypairs = self.keypairs.lock().unwrap();
-        let keypair = match keypairs.get(addr) {
-            Some(kp) => kp.clone(),
-            None => return Err(WalletError::MissingKeyPair),
-        };
-        drop(keypairs);
+    /// Create a payment transaction
+    pub fn create_transaction_payment(&self, sender_addr: &Address, receiver_addr: &Address, value: U256) -> Result<Transaction> {
+        let (gas_price, gas) = self.gas();
+        self.create_transaction(sender_addr, Action::Call(receiver_addr.clone()), value, vec![], gas_price, gas)
+    }
 
-        let mut nonce_balances = self.nonce_balances.lock().unwrap();
-        let nonce_balance = nonce_balances.entry(keypair.address()).or_insert((0.into(), 0.into()));
-        let nonce = nonce_balance.0;
-        nonce_balance.0 = nonce_balance.0 + 1;
-        drop(nonce_balances);
+    /// Create a payment transaction from and to the same addr
+    pub fn create_transaction_self_payment(&self, addr: &Address, value: U256) -> Result<Transaction> {
+        let (gas_price, gas) = self.gas();
+        self.create_transaction(addr, Action::Call(addr.clone()), value, vec![], gas_price, gas)
+    }
 
-        let tx: Transaction = RawTransaction {
-            nonce: nonce.into(),
-            gas_price: 0.into(),
-            gas: 10_000_000.into(),
-            action: Action::Call(receiver_addr.clone()),
-            value: 0.into(),
-            data,
-        }.sign(keypair.secret(), None).into();
-        Ok(tx)
+    /// Create a call transaction with 0 value
+    pub fn create_transaction_call(&self, addr: &Address, receiver_addr: &Address, data: Bytes) -> Result<Transaction> {
+        let (gas_price, gas) = self.gas();
+        self.create_transaction(addr, Action::Call(receiver_addr.clone()), 0.into(), data, gas_price, gas)
     }
 
     /// Create a create transaction with our first keypair and 0 value
     pub fn create_transaction_create(&self, data: Bytes) -> Result<Transaction> {
-        let keypairs = self.keypairs.lock().unwrap();
-        let keypair = match keypairs.values().next() {
-            Some(kp) => kp.clone(),
+        let addr = match self.addresses()?.into_iter().next() {
+            Some(addr) => addr,
             None => return Err(WalletError::MissingKeyPair),
         };
-        drop(keypairs);
-
-        let mut nonce_balances = self.nonce_balances.lock().unwrap();
-        let nonce_balance = nonce_balances.entry(keypair.address()).or_insert((0.into(), 0.into()));
-        let nonce = nonce_balance.0;
-        nonce_balance.0 = nonce_balance.0 + 1;
-        drop(nonce_balances);
-
-        let tx: Transaction = RawTransaction {
-            nonce: nonce.into(),
-            gas_price: 0.into(),
-            gas: 10_000_000.into(),
-            action: Action::Create,
-            value: 0.into(),
-            data,
-        }.sign(keypair.secret(), None).into();
-        Ok(tx)
+        let (gas_price, gas) = self.gas();
+        self.create_transaction(&addr, Action::Create, 0.into(), data, gas_price, gas)
     }
 }
 